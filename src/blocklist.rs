@@ -0,0 +1,166 @@
+//! A blocklist of clients and counterparties whose events are rejected.
+//!
+//! The file contains one entry per line, either `client <id>` or `counterparty <name>`. Empty lines and lines starting
+//! with `#` are ignored.
+//!
+//! ```text
+//! # Under investigation
+//! client 42
+//! counterparty Shady Imports Ltd
+//! ```
+
+use std::{cell::Cell, collections::HashSet, str::FromStr};
+
+use thiserror::Error;
+
+use crate::{
+    client::ClientState,
+    event::Event,
+    rules::{self, Rule},
+    ClientId,
+};
+
+/// Errors that can happen while parsing a blocklist, each of them refers to a line in the file (starting at one).
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum Error {
+    /// The line is neither empty, a comment nor an entry.
+    #[error("line {0}: expected `client <id>` or `counterparty <name>`")]
+    Syntax(usize),
+    /// The id of a client entry is not a number.
+    #[error("line {0}: `{1}` is not a valid client id")]
+    InvalidClient(usize, String),
+}
+
+/// The number of events that were rejected by a [`Blocklist`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Hits {
+    /// Events of blocked clients.
+    pub clients: u64,
+    /// Deposits and withdrawals with a blocked counterparty.
+    pub counterparties: u64,
+}
+
+/// Clients and counterparties whose events are rejected.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Blocklist {
+    clients: HashSet<ClientId>,
+    counterparties: HashSet<String>,
+    hits: Cell<Hits>,
+}
+
+impl Blocklist {
+    /// Returns how many events have been rejected so far.
+    pub fn hits(&self) -> Hits {
+        self.hits.get()
+    }
+}
+
+impl FromStr for Blocklist {
+    type Err = Error;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let mut blocklist = Blocklist::default();
+
+        for (line, content) in (1..).zip(source.lines()) {
+            let content = content.trim();
+            if content.is_empty() || content.starts_with('#') {
+                continue;
+            }
+
+            match content.split_once(char::is_whitespace) {
+                Some(("client", id)) => {
+                    let id = id.trim();
+                    let id = id.parse().map_err(|_| Error::InvalidClient(line, id.into()))?;
+                    blocklist.clients.insert(id);
+                }
+                Some(("counterparty", name)) => {
+                    blocklist.counterparties.insert(name.trim().into());
+                }
+                _ => return Err(Error::Syntax(line)),
+            }
+        }
+
+        Ok(blocklist)
+    }
+}
+
+impl Rule for Blocklist {
+    fn rejects(&self, event: &Event, _: Option<&ClientState>) -> Result<bool, rules::Error> {
+        let mut hits = self.hits.get();
+
+        // Blocked clients can neither send nor receive transfers.
+        let recipient = match *event {
+            Event::Transfer { to, .. } => Some(to),
+            _ => None,
+        };
+        if self.clients.contains(&event.client()) || recipient.is_some_and(|to| self.clients.contains(&to)) {
+            hits.clients += 1;
+        } else {
+            match event {
+                Event::Deposit {
+                    counterparty: Some(counterparty),
+                    ..
+                }
+                | Event::Withdrawal {
+                    counterparty: Some(counterparty),
+                    ..
+                } if self.counterparties.contains(counterparty) => hits.counterparties += 1,
+                _ => return Ok(false),
+            }
+        }
+
+        self.hits.set(hits);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn parse() -> Result<(), Error> {
+        let blocklist: Blocklist = "# comment\nclient 42\n\n  counterparty Shady Imports Ltd ".parse()?;
+        assert_eq!(blocklist.clients, HashSet::from([42]));
+        assert_eq!(blocklist.counterparties, HashSet::from(["Shady Imports Ltd".into()]));
+
+        assert_eq!("client".parse::<Blocklist>(), Err(Error::Syntax(1)));
+        assert_eq!("merchant acme".parse::<Blocklist>(), Err(Error::Syntax(1)));
+        assert_eq!(
+            "\nclient -1".parse::<Blocklist>(),
+            Err(Error::InvalidClient(2, "-1".into()))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn hits() -> Result<(), Box<dyn std::error::Error>> {
+        let blocklist: Blocklist = "client 1\ncounterparty acme".parse()?;
+        let deposit = |client, counterparty: Option<&str>| Event::Deposit {
+            client,
+            tx: 0,
+            amount: dec!(1),
+            counterparty: counterparty.map(Into::into),
+            related: None,
+        };
+
+        assert!(blocklist.rejects(&deposit(1, None), None)?);
+        assert!(blocklist.rejects(&Event::dispute(1, 0), None)?);
+        assert!(blocklist.rejects(&Event::transfer(2, 1, 0, dec!(1)), None)?);
+        assert!(!blocklist.rejects(&Event::transfer(2, 3, 0, dec!(1)), None)?);
+        assert!(blocklist.rejects(&deposit(2, Some("acme")), None)?);
+        assert!(!blocklist.rejects(&deposit(2, Some("zenith")), None)?);
+        assert!(!blocklist.rejects(&deposit(2, None), None)?);
+
+        let expected = Hits {
+            clients: 3,
+            counterparties: 1,
+        };
+        assert_eq!(blocklist.hits(), expected);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1003 @@
+//! Parsing of the command line arguments.
+
+use std::{num::NonZeroUsize, str::FromStr, time::Duration};
+
+use rust_decimal::Decimal;
+use thiserror::Error;
+use txh::{
+    graph,
+    precision::{InputPrecision, Rounding},
+    source,
+    state::{self, DuplicatePolicy},
+    ClientId,
+};
+
+use crate::{errors, output};
+
+/// Short description of how to invoke the tool.
+pub const USAGE: &str = "\
+usage: txh [--parse-only] [--determinism-check] [--memory-report] [--histograms] [--presize] [--rules <script>.rhai] [--policy <policy_file>] [--blocklist <blocklist_file>]
+           [--fee-schedule <fee_file>] [--sweep <sweep_file>]
+           [--dormancy-report <output_file>.csv --dormant-after <events>]
+           [--counterparty-report <output_file>.csv]
+           [--large-tx-report <output_file>.csv --large-tx-threshold <amount>]
+           [--float-report <output_file>.csv] [--summary <output_file>.csv] [--rejects <output_file>.csv] [--ledger-out <output_file>.csv]
+           [--open-disputes-report <output_file>.csv] [--frozen-report <output_file>.csv]
+           [--on-duplicate error|skip|overwrite] [--on-error abort|skip|collect] [--allow-admin-events]
+           [--input-precision reject|round] [--rounding bankers|truncate]
+           [--max-clients <count>] [--max-transactions <count>] [--max-input-size <bytes>]
+           [--diff-against <previous_output>.csv]
+           [--snapshot <snapshot_file> [--snapshot-every <events>] [--resume]]
+           [--threads <count>]
+           [--follow [--emit-every <events>] [--emit-interval <seconds>]]
+           [--output-format csv|json|table [--stream]] [--sorted]
+           [--output-buffer-size <bytes>] [--output-compression zstd]
+           [--input-format csv|ndjson] [--id-map <map_file>.csv] <input_file>... (`-` reads stdin)
+       txh check-policy <policy_file>
+       txh selftest [--events <count>]
+       txh export --graph dot|json [--on-error abort|skip|collect] [--input-precision reject|round] [--rounding bankers|truncate] <input_file>.csv
+       txh history --client <id> [--on-error abort|skip|collect] [--input-precision reject|round] [--rounding bankers|truncate] <input_file>.csv
+       txh serve --listen <address> [--input-format csv|ndjson] [--max-connections <count>]
+                 [--max-clients <count>] [--max-transactions <count>] [--on-duplicate error|skip|overwrite]
+                 [--allow-admin-events] [--input-precision reject|round] [--rounding bankers|truncate]
+                 [--policy <policy_file>] [--blocklist <blocklist_file>]
+all commands accept [--errors-format text|json]";
+
+/// The number of events between two snapshots, unless `--snapshot-every` is given.
+const DEFAULT_SNAPSHOT_INTERVAL: u64 = 1_000_000;
+
+/// The number of events between two outputs in follow mode, unless `--emit-every` is given.
+const DEFAULT_EMIT_EVERY: u64 = 10_000;
+
+/// The number of connections that `txh serve` accepts at the same time, unless `--max-connections` is given.
+const DEFAULT_MAX_CONNECTIONS: usize = 64;
+
+/// The time between two outputs in follow mode, unless `--emit-interval` is given.
+const DEFAULT_EMIT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What happens to malformed records of the input.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnError {
+    /// Stop processing with an error.
+    #[default]
+    Abort,
+    /// Skip the record with a warning on stderr.
+    Skip,
+    /// Skip the record and list all skipped records on stderr once processing is done.
+    Collect,
+}
+
+impl FromStr for OnError {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "abort" => Ok(OnError::Abort),
+            "skip" => Ok(OnError::Skip),
+            "collect" => Ok(OnError::Collect),
+            _ => Err(()),
+        }
+    }
+}
+
+/// How the records of an input are checked, for the commands that only take these flags of [`Args`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InputChecks {
+    /// How malformed records of the input are handled.
+    pub on_error: OnError,
+    /// How input amounts with too many decimal places are handled.
+    pub input_precision: InputPrecision,
+    /// How amounts are rounded to four decimal places.
+    pub rounding: Rounding,
+}
+
+impl InputChecks {
+    /// Parses the value of `flag` from `args` if it is `--on-error`, `--input-precision` or `--rounding`, and returns
+    /// whether it was.
+    fn parse_flag(&mut self, flag: &str, args: &mut impl Iterator<Item = String>) -> Result<bool, Error> {
+        if !matches!(flag, "--on-error" | "--input-precision" | "--rounding") {
+            return Ok(false);
+        }
+        let value = args.next().ok_or_else(|| Error::MissingValue(flag.into()))?;
+        let invalid = || Error::InvalidValue(flag.into(), value.clone());
+        match flag {
+            "--on-error" => self.on_error = value.parse().map_err(|_| invalid())?,
+            "--input-precision" => self.input_precision = value.parse().map_err(|_| invalid())?,
+            _ => self.rounding = value.parse().map_err(|_| invalid())?,
+        }
+        Ok(true)
+    }
+}
+
+/// Errors that can happen while parsing the command line.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum Error {
+    #[error("missing input file")]
+    MissingInput,
+    #[error("missing value for flag: `{0}`")]
+    MissingValue(String),
+    #[error("invalid value for flag `{0}`: `{1}`")]
+    InvalidValue(String, String),
+    #[error("`{0}` requires `{1}`")]
+    MissingFlag(&'static str, &'static str),
+    #[error("`{0}` can't be combined with `{1}`")]
+    Conflict(&'static str, &'static str),
+    #[error("unknown flag: `{0}`")]
+    UnknownFlag(String),
+    #[error("unexpected argument: `{0}`")]
+    UnexpectedArgument(String),
+}
+
+/// Removes the `--errors-format <format>` flag, which is valid for all commands, from `args` and returns the format.
+pub fn extract_errors_format(args: &mut Vec<String>) -> Result<errors::Format, Error> {
+    let Some(position) = args.iter().position(|arg| arg == "--errors-format") else {
+        return Ok(errors::Format::default());
+    };
+    let flag = args.remove(position);
+    if position == args.len() {
+        return Err(Error::MissingValue(flag));
+    }
+    let value = args.remove(position);
+    value.parse().map_err(|_| Error::InvalidValue(flag, value))
+}
+
+/// The commands supported by the tool.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Processes an input file and prints the resulting client states.
+    Process(Box<Args>),
+    /// Validates a policy file and prints the limits that it sets.
+    CheckPolicy(String),
+    /// Processes an input file and prints the graph of references between its transactions.
+    ExportGraph {
+        format: graph::Format,
+        input: String,
+        checks: InputChecks,
+    },
+    /// Processes an input file and prints every change of a single client.
+    History {
+        client: ClientId,
+        input: String,
+        checks: InputChecks,
+    },
+    /// Accepts connections that stream events into a shared state and can query the client states.
+    Serve(Box<ServeArgs>),
+    /// Processes a synthetic dataset with about the given number of events and checks the result.
+    SelfTest { events: u64 },
+}
+
+impl Command {
+    /// Parses the arguments, excluding the name of the binary.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, Error> {
+        let mut args = args.into_iter();
+        match args.next() {
+            Some(command) if command == "check-policy" => {
+                let path = args.next().ok_or(Error::MissingInput)?;
+                match args.next() {
+                    Some(arg) => Err(Error::UnexpectedArgument(arg)),
+                    None => Ok(Command::CheckPolicy(path)),
+                }
+            }
+            Some(command) if command == "export" => {
+                let mut format = None;
+                let mut input = None;
+                let mut checks = InputChecks::default();
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--graph" => {
+                            let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                            format = Some(value.parse().map_err(|_| Error::InvalidValue(arg, value))?);
+                        }
+                        flag if checks.parse_flag(flag, &mut args)? => {}
+                        flag if flag.starts_with("--") => return Err(Error::UnknownFlag(arg)),
+                        _ if input.is_none() => input = Some(arg),
+                        _ => return Err(Error::UnexpectedArgument(arg)),
+                    }
+                }
+                Ok(Command::ExportGraph {
+                    format: format.ok_or(Error::MissingFlag("export", "--graph"))?,
+                    input: input.ok_or(Error::MissingInput)?,
+                    checks,
+                })
+            }
+            Some(command) if command == "history" => {
+                let mut client = None;
+                let mut input = None;
+                let mut checks = InputChecks::default();
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--client" => {
+                            let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                            client = Some(value.parse().map_err(|_| Error::InvalidValue(arg, value))?);
+                        }
+                        flag if checks.parse_flag(flag, &mut args)? => {}
+                        flag if flag.starts_with("--") => return Err(Error::UnknownFlag(arg)),
+                        _ if input.is_none() => input = Some(arg),
+                        _ => return Err(Error::UnexpectedArgument(arg)),
+                    }
+                }
+                Ok(Command::History {
+                    client: client.ok_or(Error::MissingFlag("history", "--client"))?,
+                    input: input.ok_or(Error::MissingInput)?,
+                    checks,
+                })
+            }
+            Some(command) if command == "serve" => ServeArgs::parse(args).map(|args| Command::Serve(Box::new(args))),
+            Some(command) if command == "selftest" => {
+                let mut events = 1_000_000;
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--events" => {
+                            let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                            events = value.parse().map_err(|_| Error::InvalidValue(arg, value))?;
+                        }
+                        flag if flag.starts_with("--") => return Err(Error::UnknownFlag(arg)),
+                        _ => return Err(Error::UnexpectedArgument(arg)),
+                    }
+                }
+                Ok(Command::SelfTest { events })
+            }
+            first => Args::parse(first.into_iter().chain(args)).map(|args| Command::Process(Box::new(args))),
+        }
+    }
+}
+
+/// The options of `txh serve`, whose engine checks and applies events like a run with the flags of [`Args`] with the
+/// same names.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServeArgs {
+    /// The address to listen on.
+    pub listen: String,
+    /// Format of the events that the connections send.
+    pub format: source::Format,
+    /// The number of connections that are served at the same time, further ones are refused.
+    pub max_connections: usize,
+    /// Limits on the number of clients and transactions.
+    pub limits: state::Limits,
+    /// How deposits and withdrawals with the id of a stored transaction are handled.
+    pub on_duplicate: DuplicatePolicy,
+    /// Apply admin events like `unlock` instead of rejecting them.
+    pub allow_admin_events: bool,
+    /// How input amounts with too many decimal places are handled.
+    pub input_precision: InputPrecision,
+    /// How amounts are rounded to four decimal places.
+    pub rounding: Rounding,
+    /// Path of a policy file with limits that every event has to satisfy.
+    pub policy: Option<String>,
+    /// Path of a blocklist of clients and counterparties.
+    pub blocklist: Option<String>,
+}
+
+impl ServeArgs {
+    /// Parses the arguments after `serve`.
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, Error> {
+        let mut listen = None;
+        let mut serve = ServeArgs {
+            listen: String::new(),
+            format: source::Format::default(),
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            limits: state::Limits::default(),
+            on_duplicate: DuplicatePolicy::default(),
+            allow_admin_events: false,
+            input_precision: InputPrecision::default(),
+            rounding: Rounding::default(),
+            policy: None,
+            blocklist: None,
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--listen" => listen = Some(args.next().ok_or(Error::MissingValue(arg))?),
+                "--input-format" => {
+                    let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                    serve.format = value.parse().map_err(|_| Error::InvalidValue(arg, value))?;
+                }
+                "--max-connections" => {
+                    let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                    serve.max_connections = match value.parse() {
+                        Ok(count) if count > 0 => count,
+                        _ => return Err(Error::InvalidValue(arg, value)),
+                    };
+                }
+                "--max-clients" => {
+                    let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                    serve.limits.max_clients = Some(value.parse().map_err(|_| Error::InvalidValue(arg, value))?);
+                }
+                "--max-transactions" => {
+                    let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                    serve.limits.max_transactions = Some(value.parse().map_err(|_| Error::InvalidValue(arg, value))?);
+                }
+                "--on-duplicate" => {
+                    let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                    serve.on_duplicate = value.parse().map_err(|_| Error::InvalidValue(arg, value))?;
+                }
+                "--allow-admin-events" => serve.allow_admin_events = true,
+                "--input-precision" => {
+                    let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                    serve.input_precision = value.parse().map_err(|_| Error::InvalidValue(arg, value))?;
+                }
+                "--rounding" => {
+                    let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                    serve.rounding = value.parse().map_err(|_| Error::InvalidValue(arg, value))?;
+                }
+                "--policy" => serve.policy = Some(args.next().ok_or(Error::MissingValue(arg))?),
+                "--blocklist" => serve.blocklist = Some(args.next().ok_or(Error::MissingValue(arg))?),
+                // Scripts can't be moved to the engine thread.
+                "--rules" => return Err(Error::Conflict("serve", "--rules")),
+                flag if flag.starts_with("--") => return Err(Error::UnknownFlag(arg)),
+                _ => return Err(Error::UnexpectedArgument(arg)),
+            }
+        }
+        serve.listen = listen.ok_or(Error::MissingFlag("serve", "--listen"))?;
+        Ok(serve)
+    }
+}
+
+/// The options that control a single run of the tool.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Args {
+    /// Paths of the files that contain the events, which are processed in order. `-` refers to stdin.
+    pub inputs: Vec<String>,
+    /// Format of the input file.
+    pub input_format: source::Format,
+    /// Process the input twice and fail if the outputs differ.
+    pub determinism_check: bool,
+    /// Print an estimate of the memory used by the state to stderr.
+    pub memory_report: bool,
+    /// Print histograms of amounts and balances to stderr.
+    pub histograms: bool,
+    /// Only parse and validate the inputs, and print the throughput.
+    pub parse_only: bool,
+    /// Read the input twice, first to count clients and transactions and size the state accordingly.
+    pub presize: bool,
+    /// Path of a script with custom rules that can reject events.
+    pub rules: Option<String>,
+    /// Path of a policy file with limits that can reject events.
+    pub policy: Option<String>,
+    /// Path of a blocklist of clients and counterparties whose events are rejected.
+    pub blocklist: Option<String>,
+    /// Path of the fee schedule of withdrawals.
+    pub fee_schedule: Option<String>,
+    /// Path of the sweep of clients whose available funds exceed a threshold.
+    pub sweep: Option<String>,
+    /// Path of the dormancy report and the number of events after which a client is considered dormant.
+    pub dormancy_report: Option<(String, u64)>,
+    /// Path of the report that aggregates transactions by counterparty.
+    pub counterparty_report: Option<String>,
+    /// Path of the report of transactions above a threshold, and the threshold.
+    pub large_tx_report: Option<(String, Decimal)>,
+    /// Path of the report of the operator's float account.
+    pub float_report: Option<String>,
+    /// Path of the summary of the run.
+    pub summary: Option<String>,
+    /// Path of the report of rejected events.
+    pub rejects: Option<String>,
+    /// Path of the ledger of all retained transactions.
+    pub ledger_out: Option<String>,
+    /// Path of the report of transactions that are still disputed.
+    pub open_disputes_report: Option<String>,
+    /// Path of the report of frozen clients.
+    pub frozen_report: Option<String>,
+    /// Path of the snapshot that is written while processing.
+    pub snapshot: Option<String>,
+    /// The number of events between two snapshots.
+    pub snapshot_every: u64,
+    /// Continue from the snapshot instead of processing the inputs from the start.
+    pub resume: bool,
+    /// Keep reading the input as it grows and write the client states periodically.
+    pub follow: bool,
+    /// The number of events after which the client states are written in follow mode.
+    pub emit_every: u64,
+    /// The time after which the client states are written in follow mode, if any events were processed.
+    pub emit_interval: Duration,
+    /// Process the input with this many worker threads, see [`txh::parallel`].
+    pub threads: Option<NonZeroUsize>,
+    /// How deposits and withdrawals with the id of a stored transaction are handled.
+    pub on_duplicate: DuplicatePolicy,
+    /// How malformed records of the input are handled.
+    pub on_error: OnError,
+    /// Path of the mapping of external client identifiers to client ids.
+    pub id_map: Option<String>,
+    /// Apply admin events like `unlock` instead of rejecting them.
+    pub allow_admin_events: bool,
+    /// How input amounts with too many decimal places are handled.
+    pub input_precision: InputPrecision,
+    /// How amounts are rounded to four decimal places.
+    pub rounding: Rounding,
+    /// Limits on the number of clients and transactions.
+    pub limits: state::Limits,
+    /// The maximum size of the input file, in bytes.
+    pub max_input_size: Option<u64>,
+    /// Path of a previous output, only clients that changed since are written.
+    pub diff_against: Option<String>,
+    /// Format of the client states written to stdout.
+    pub output_format: output::Format,
+    /// Write JSON output as one object per line instead of an array.
+    pub stream: bool,
+    /// Write the clients ordered by their id.
+    pub sorted: bool,
+    /// Size of the buffer in front of stdout, in bytes.
+    pub output_buffer_size: usize,
+    /// Compression of the client states written to stdout.
+    pub output_compression: Option<output::Compression>,
+}
+
+impl Args {
+    /// Returns how the records of the inputs are checked, with malformed records handled according to `on_error`.
+    pub fn checks(&self, on_error: OnError) -> InputChecks {
+        InputChecks {
+            on_error,
+            input_precision: self.input_precision,
+            rounding: self.rounding,
+        }
+    }
+
+    /// Parses the arguments, excluding the name of the binary.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, Error> {
+        let mut inputs = Vec::new();
+        let mut input_format = source::Format::default();
+        let mut determinism_check = false;
+        let mut memory_report = false;
+        let mut histograms = false;
+        let mut parse_only = false;
+        let mut presize = false;
+        let mut rules = None;
+        let mut policy = None;
+        let mut fee_schedule = None;
+        let mut sweep = None;
+        let mut blocklist = None;
+        let mut dormancy_report = None;
+        let mut dormant_after = None;
+        let mut counterparty_report = None;
+        let mut large_tx_report = None;
+        let mut large_tx_threshold = None;
+        let mut float_report = None;
+        let mut summary = None;
+        let mut rejects = None;
+        let mut ledger_out = None;
+        let mut open_disputes_report = None;
+        let mut frozen_report = None;
+        let mut snapshot = None;
+        let mut snapshot_every = None;
+        let mut resume = false;
+        let mut follow = false;
+        let mut emit_every = None;
+        let mut emit_interval = None;
+        let mut threads = None;
+        let mut on_duplicate = DuplicatePolicy::default();
+        let mut on_error = OnError::default();
+        let mut id_map = None;
+        let mut allow_admin_events = false;
+        let mut input_precision = InputPrecision::default();
+        let mut rounding = Rounding::default();
+        let mut limits = state::Limits::default();
+        let mut max_input_size = None;
+        let mut diff_against = None;
+        let mut output_format = output::Format::default();
+        let mut stream = false;
+        let mut sorted = false;
+        let mut output_buffer_size = output::DEFAULT_BUFFER_SIZE;
+        let mut output_compression = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--determinism-check" => determinism_check = true,
+                "--memory-report" => memory_report = true,
+                "--histograms" => histograms = true,
+                "--parse-only" => parse_only = true,
+                "--presize" => presize = true,
+                "--rules" => rules = Some(args.next().ok_or(Error::MissingValue(arg))?),
+                "--policy" => policy = Some(args.next().ok_or(Error::MissingValue(arg))?),
+                "--fee-schedule" => fee_schedule = Some(args.next().ok_or(Error::MissingValue(arg))?),
+                "--sweep" => sweep = Some(args.next().ok_or(Error::MissingValue(arg))?),
+                "--blocklist" => blocklist = Some(args.next().ok_or(Error::MissingValue(arg))?),
+                "--dormancy-report" => dormancy_report = Some(args.next().ok_or(Error::MissingValue(arg))?),
+                "--counterparty-report" => counterparty_report = Some(args.next().ok_or(Error::MissingValue(arg))?),
+                "--float-report" => float_report = Some(args.next().ok_or(Error::MissingValue(arg))?),
+                "--summary" => summary = Some(args.next().ok_or(Error::MissingValue(arg))?),
+                "--rejects" => rejects = Some(args.next().ok_or(Error::MissingValue(arg))?),
+                "--ledger-out" => ledger_out = Some(args.next().ok_or(Error::MissingValue(arg))?),
+                "--open-disputes-report" => open_disputes_report = Some(args.next().ok_or(Error::MissingValue(arg))?),
+                "--frozen-report" => frozen_report = Some(args.next().ok_or(Error::MissingValue(arg))?),
+                "--large-tx-report" => large_tx_report = Some(args.next().ok_or(Error::MissingValue(arg))?),
+                "--large-tx-threshold" => {
+                    let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                    large_tx_threshold = Some(value.parse().map_err(|_| Error::InvalidValue(arg, value))?);
+                }
+                "--dormant-after" => {
+                    let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                    dormant_after = Some(value.parse().map_err(|_| Error::InvalidValue(arg, value))?);
+                }
+                "--snapshot" => snapshot = Some(args.next().ok_or(Error::MissingValue(arg))?),
+                "--snapshot-every" => {
+                    let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                    snapshot_every = match value.parse() {
+                        Ok(0) | Err(_) => return Err(Error::InvalidValue(arg, value)),
+                        Ok(events) => Some(events),
+                    };
+                }
+                "--resume" => resume = true,
+                "--follow" => follow = true,
+                "--emit-every" => {
+                    let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                    emit_every = match value.parse() {
+                        Ok(0) | Err(_) => return Err(Error::InvalidValue(arg, value)),
+                        Ok(events) => Some(events),
+                    };
+                }
+                "--emit-interval" => {
+                    let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                    emit_interval = match value.parse() {
+                        Ok(0) | Err(_) => return Err(Error::InvalidValue(arg, value)),
+                        Ok(seconds) => Some(Duration::from_secs(seconds)),
+                    };
+                }
+                "--threads" => {
+                    let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                    threads = Some(value.parse().map_err(|_| Error::InvalidValue(arg, value))?);
+                }
+                "--on-duplicate" => {
+                    let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                    on_duplicate = value.parse().map_err(|_| Error::InvalidValue(arg, value))?;
+                }
+                "--on-error" => {
+                    let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                    on_error = value.parse().map_err(|_| Error::InvalidValue(arg, value))?;
+                }
+                "--allow-admin-events" => allow_admin_events = true,
+                "--id-map" => id_map = Some(args.next().ok_or(Error::MissingValue(arg))?),
+                "--input-precision" => {
+                    let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                    input_precision = value.parse().map_err(|_| Error::InvalidValue(arg, value))?;
+                }
+                "--rounding" => {
+                    let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                    rounding = value.parse().map_err(|_| Error::InvalidValue(arg, value))?;
+                }
+                "--max-clients" => {
+                    let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                    limits.max_clients = Some(value.parse().map_err(|_| Error::InvalidValue(arg, value))?);
+                }
+                "--max-transactions" => {
+                    let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                    limits.max_transactions = Some(value.parse().map_err(|_| Error::InvalidValue(arg, value))?);
+                }
+                "--max-input-size" => {
+                    let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                    max_input_size = Some(value.parse().map_err(|_| Error::InvalidValue(arg, value))?);
+                }
+                "--input-format" => {
+                    let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                    input_format = value.parse().map_err(|_| Error::InvalidValue(arg, value))?;
+                }
+                "--diff-against" => diff_against = Some(args.next().ok_or(Error::MissingValue(arg))?),
+                "--output-format" => {
+                    let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                    output_format = value.parse().map_err(|_| Error::InvalidValue(arg, value))?;
+                }
+                "--stream" => stream = true,
+                "--sorted" => sorted = true,
+                "--output-buffer-size" => {
+                    let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                    output_buffer_size = match value.parse() {
+                        Ok(0) | Err(_) => return Err(Error::InvalidValue(arg, value)),
+                        Ok(size) => size,
+                    };
+                }
+                "--output-compression" => {
+                    let value = args.next().ok_or_else(|| Error::MissingValue(arg.clone()))?;
+                    output_compression = Some(value.parse().map_err(|_| Error::InvalidValue(arg, value))?);
+                }
+                flag if flag.starts_with("--") => return Err(Error::UnknownFlag(arg)),
+                _ => inputs.push(arg),
+            }
+        }
+
+        if inputs.is_empty() {
+            return Err(Error::MissingInput);
+        }
+        // Stdin can only be read once.
+        if inputs.iter().any(|input| input == "-") {
+            if presize {
+                return Err(Error::Conflict("--presize", "-"));
+            }
+            if determinism_check {
+                return Err(Error::Conflict("--determinism-check", "-"));
+            }
+        }
+        // The size of stdin is only known once it was read.
+        if max_input_size.is_some() && inputs.iter().any(|input| input == "-") {
+            return Err(Error::Conflict("--max-input-size", "-"));
+        }
+        // The first run overwrites the snapshot that the second one would resume from.
+        if resume && determinism_check {
+            return Err(Error::Conflict("--resume", "--determinism-check"));
+        }
+
+        let dormancy_report = match (dormancy_report, dormant_after) {
+            (Some(path), Some(events)) => Some((path, events)),
+            (Some(_), None) => return Err(Error::MissingFlag("--dormancy-report", "--dormant-after")),
+            (None, Some(_)) => return Err(Error::MissingFlag("--dormant-after", "--dormancy-report")),
+            (None, None) => None,
+        };
+
+        if stream && output_format != output::Format::Json {
+            return Err(Error::MissingFlag("--stream", "--output-format json"));
+        }
+        if presize && input_format != source::Format::Csv {
+            return Err(Error::Conflict("--presize", "--input-format ndjson"));
+        }
+        if id_map.is_some() && input_format != source::Format::Csv {
+            return Err(Error::Conflict("--id-map", "--input-format ndjson"));
+        }
+
+        if snapshot.is_none() {
+            if snapshot_every.is_some() {
+                return Err(Error::MissingFlag("--snapshot-every", "--snapshot"));
+            }
+            if resume {
+                return Err(Error::MissingFlag("--resume", "--snapshot"));
+            }
+        }
+        let snapshot_every = snapshot_every.unwrap_or(DEFAULT_SNAPSHOT_INTERVAL);
+
+        if follow {
+            // A followed file never ends, so there is no second run and no place to resume from.
+            let conflicts = [
+                ("-", inputs.iter().any(|input| input == "-")),
+                ("--determinism-check", determinism_check),
+                ("--presize", presize),
+                ("--parse-only", parse_only),
+                ("--resume", resume),
+            ];
+            if let Some((flag, _)) = conflicts.into_iter().find(|(_, set)| *set) {
+                return Err(Error::Conflict("--follow", flag));
+            }
+            if let Some(second) = inputs.get(1) {
+                return Err(Error::UnexpectedArgument(second.clone()));
+            }
+        } else {
+            if emit_every.is_some() {
+                return Err(Error::MissingFlag("--emit-every", "--follow"));
+            }
+            if emit_interval.is_some() {
+                return Err(Error::MissingFlag("--emit-interval", "--follow"));
+            }
+        }
+        let emit_every = emit_every.unwrap_or(DEFAULT_EMIT_EVERY);
+        let emit_interval = emit_interval.unwrap_or(DEFAULT_EMIT_INTERVAL);
+
+        // These need all events in the order of the input, on a single thread.
+        if threads.is_some() {
+            let sequential_only = [
+                ("--determinism-check", determinism_check),
+                ("--memory-report", memory_report),
+                ("--presize", presize),
+                ("--rules", rules.is_some()),
+                ("--policy", policy.is_some()),
+                ("--blocklist", blocklist.is_some()),
+                ("--fee-schedule", fee_schedule.is_some()),
+                ("--sweep", sweep.is_some()),
+                ("--large-tx-report", large_tx_report.is_some()),
+                ("--rejects", rejects.is_some()),
+                ("--summary", summary.is_some()),
+                ("--snapshot", snapshot.is_some()),
+                ("--follow", follow),
+                ("--on-duplicate", on_duplicate != DuplicatePolicy::default()),
+                ("--allow-admin-events", allow_admin_events),
+                ("--max-clients", limits.max_clients.is_some()),
+                ("--max-transactions", limits.max_transactions.is_some()),
+            ];
+            if let Some((flag, _)) = sequential_only.into_iter().find(|(_, set)| *set) {
+                return Err(Error::Conflict("--threads", flag));
+            }
+        }
+
+        let large_tx_report = match (large_tx_report, large_tx_threshold) {
+            (Some(path), Some(threshold)) => Some((path, threshold)),
+            (Some(_), None) => return Err(Error::MissingFlag("--large-tx-report", "--large-tx-threshold")),
+            (None, Some(_)) => return Err(Error::MissingFlag("--large-tx-threshold", "--large-tx-report")),
+            (None, None) => None,
+        };
+
+        Ok(Self {
+            inputs,
+            input_format,
+            determinism_check,
+            memory_report,
+            histograms,
+            parse_only,
+            presize,
+            rules,
+            policy,
+            blocklist,
+            fee_schedule,
+            sweep,
+            dormancy_report,
+            counterparty_report,
+            large_tx_report,
+            float_report,
+            summary,
+            rejects,
+            ledger_out,
+            open_disputes_report,
+            frozen_report,
+            snapshot,
+            snapshot_every,
+            follow,
+            emit_every,
+            emit_interval,
+            resume,
+            threads,
+            on_duplicate,
+            on_error,
+            id_map,
+            allow_admin_events,
+            input_precision,
+            rounding,
+            limits,
+            max_input_size,
+            diff_against,
+            output_format,
+            stream,
+            sorted,
+            output_buffer_size,
+            output_compression,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, Error> {
+        Args::parse(args.iter().map(|&arg| arg.to_owned()))
+    }
+
+    #[test]
+    fn input_only() -> Result<(), Error> {
+        let args = parse(&["input.csv"])?;
+        assert_eq!(args.inputs, ["input.csv"]);
+        assert!(!args.determinism_check);
+
+        Ok(())
+    }
+
+    #[test]
+    fn flags() -> Result<(), Error> {
+        let args = parse(&["--determinism-check", "input.csv", "--rules", "rules.rhai"])?;
+        assert!(args.determinism_check);
+        assert_eq!(args.rules.as_deref(), Some("rules.rhai"));
+
+        let args = parse(&["--dormant-after", "10", "--dormancy-report", "out.csv", "input.csv"])?;
+        assert_eq!(args.dormancy_report, Some(("out.csv".into(), 10)));
+
+        let args = parse(&[
+            "--output-compression",
+            "zstd",
+            "--output-buffer-size",
+            "4096",
+            "input.csv",
+        ])?;
+        assert_eq!(args.output_compression, Some(output::Compression::Zstd));
+        assert_eq!(args.output_buffer_size, 4096);
+
+        let args = parse(&["a.csv", "-", "b.csv"])?;
+        assert_eq!(args.inputs, ["a.csv", "-", "b.csv"]);
+        assert_eq!(parse(&["--presize", "-"]), Err(Error::Conflict("--presize", "-")));
+        assert_eq!(
+            parse(&["--max-input-size", "1000", "a.csv", "-"]),
+            Err(Error::Conflict("--max-input-size", "-"))
+        );
+
+        let args = parse(&["--output-format", "json", "--stream", "input.csv"])?;
+        assert_eq!(args.output_format, output::Format::Json);
+        assert!(args.stream);
+        assert_eq!(
+            parse(&["--stream", "input.csv"]),
+            Err(Error::MissingFlag("--stream", "--output-format json"))
+        );
+
+        let args = parse(&["--input-format", "ndjson", "input.ndjson"])?;
+        assert_eq!(args.input_format, source::Format::Ndjson);
+        assert_eq!(
+            parse(&["--input-format", "ndjson", "--presize", "input.ndjson"]),
+            Err(Error::Conflict("--presize", "--input-format ndjson"))
+        );
+        assert_eq!(
+            parse(&["--output-buffer-size", "0", "input.csv"]),
+            Err(Error::InvalidValue("--output-buffer-size".into(), "0".into()))
+        );
+
+        let args = parse(&["--snapshot", "state.snapshot", "--resume", "input.csv"])?;
+        assert_eq!(args.snapshot.as_deref(), Some("state.snapshot"));
+        assert_eq!(args.snapshot_every, DEFAULT_SNAPSHOT_INTERVAL);
+        assert!(args.resume);
+        assert_eq!(
+            parse(&["--resume", "input.csv"]),
+            Err(Error::MissingFlag("--resume", "--snapshot"))
+        );
+
+        let args = parse(&["--follow", "--emit-interval", "2", "ledger.csv"])?;
+        assert!(args.follow);
+        assert_eq!(args.emit_every, DEFAULT_EMIT_EVERY);
+        assert_eq!(args.emit_interval, Duration::from_secs(2));
+        assert_eq!(
+            parse(&["--follow", "a.csv", "b.csv"]),
+            Err(Error::UnexpectedArgument("b.csv".into()))
+        );
+        assert_eq!(parse(&["--follow", "-"]), Err(Error::Conflict("--follow", "-")));
+        assert_eq!(
+            parse(&["--emit-every", "5", "input.csv"]),
+            Err(Error::MissingFlag("--emit-every", "--follow"))
+        );
+
+        let args = parse(&["--on-duplicate", "skip", "input.csv"])?;
+        assert_eq!(args.on_duplicate, DuplicatePolicy::SkipAndWarn);
+
+        let args = parse(&["--on-error", "collect", "input.csv"])?;
+        assert_eq!(args.on_error, OnError::Collect);
+
+        let args = parse(&["--id-map", "ids.csv", "input.csv"])?;
+        assert_eq!(args.id_map.as_deref(), Some("ids.csv"));
+        assert_eq!(
+            parse(&["--id-map", "ids.csv", "--input-format", "ndjson", "input.json"]),
+            Err(Error::Conflict("--id-map", "--input-format ndjson"))
+        );
+
+        let args = parse(&["--input-precision", "round", "--rounding", "truncate", "input.csv"])?;
+        assert_eq!(args.input_precision, InputPrecision::Round);
+        assert_eq!(args.rounding, Rounding::Truncate);
+        assert_eq!(
+            parse(&["--on-error", "ignore", "input.csv"]),
+            Err(Error::InvalidValue("--on-error".into(), "ignore".into()))
+        );
+
+        let args = parse(&["--threads", "4", "input.csv"])?;
+        assert_eq!(args.threads, NonZeroUsize::new(4));
+        assert_eq!(
+            parse(&["--threads", "0", "input.csv"]),
+            Err(Error::InvalidValue("--threads".into(), "0".into()))
+        );
+        assert_eq!(
+            parse(&["--threads", "4", "--rejects", "rejects.csv", "input.csv"]),
+            Err(Error::Conflict("--threads", "--rejects"))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn commands() -> Result<(), Error> {
+        let command = Command::parse(["check-policy".to_owned(), "limits.policy".to_owned()])?;
+        assert_eq!(command, Command::CheckPolicy("limits.policy".into()));
+        let command = Command::parse(["input.csv".to_owned()])?;
+        assert!(matches!(command, Command::Process(args) if args.inputs == ["input.csv"]));
+        let command = Command::parse(["export", "--graph", "dot", "input.csv"].map(String::from))?;
+        let expected = Command::ExportGraph {
+            format: graph::Format::Dot,
+            input: "input.csv".into(),
+            checks: InputChecks::default(),
+        };
+        assert_eq!(command, expected);
+        assert_eq!(
+            Command::parse(["export", "--graph", "svg", "input.csv"].map(String::from)),
+            Err(Error::InvalidValue("--graph".into(), "svg".into()))
+        );
+        let command = Command::parse(["history", "input.csv", "--client", "42"].map(String::from))?;
+        let expected = Command::History {
+            client: 42,
+            input: "input.csv".into(),
+            checks: InputChecks::default(),
+        };
+        assert_eq!(command, expected);
+        let command = Command::parse(
+            [
+                "history",
+                "--client",
+                "42",
+                "--input-precision",
+                "round",
+                "--rounding",
+                "truncate",
+                "input.csv",
+            ]
+            .map(String::from),
+        )?;
+        let expected = Command::History {
+            client: 42,
+            input: "input.csv".into(),
+            checks: InputChecks {
+                on_error: OnError::Abort,
+                input_precision: InputPrecision::Round,
+                rounding: Rounding::Truncate,
+            },
+        };
+        assert_eq!(command, expected);
+        assert_eq!(
+            Command::parse(["export", "--graph", "dot", "--on-error", "ignore", "input.csv"].map(String::from)),
+            Err(Error::InvalidValue("--on-error".into(), "ignore".into()))
+        );
+        assert_eq!(
+            Command::parse(["history", "input.csv"].map(String::from)),
+            Err(Error::MissingFlag("history", "--client"))
+        );
+        let command = Command::parse(["serve", "--listen", "127.0.0.1:9000"].map(String::from))?;
+        assert!(
+            matches!(command, Command::Serve(serve) if serve.listen == "127.0.0.1:9000"
+            && serve.format == source::Format::Csv
+            && serve.max_connections == DEFAULT_MAX_CONNECTIONS
+            && serve.limits == state::Limits::default())
+        );
+        let command = Command::parse(
+            [
+                "serve",
+                "--listen",
+                ":9000",
+                "--max-connections",
+                "2",
+                "--max-clients",
+                "10",
+                "--policy",
+                "p",
+            ]
+            .map(String::from),
+        )?;
+        assert!(matches!(command, Command::Serve(serve) if serve.max_connections == 2
+            && serve.limits.max_clients == Some(10)
+            && serve.policy.as_deref() == Some("p")));
+        assert_eq!(
+            Command::parse(["serve", "--listen", ":9000", "--max-connections", "0"].map(String::from)),
+            Err(Error::InvalidValue("--max-connections".into(), "0".into()))
+        );
+        assert_eq!(
+            Command::parse(["serve", "--listen", ":9000", "--rules", "r.rhai"].map(String::from)),
+            Err(Error::Conflict("serve", "--rules"))
+        );
+        assert_eq!(
+            Command::parse(["serve".to_owned()]),
+            Err(Error::MissingFlag("serve", "--listen"))
+        );
+        let command = Command::parse(["selftest", "--events", "1000"].map(String::from))?;
+        assert_eq!(command, Command::SelfTest { events: 1000 });
+        assert_eq!(Command::parse([]), Err(Error::MissingInput));
+
+        Ok(())
+    }
+
+    #[test]
+    fn errors_format() -> Result<(), Error> {
+        let mut args = ["export", "--errors-format", "json", "--graph", "dot"]
+            .map(String::from)
+            .to_vec();
+        assert_eq!(extract_errors_format(&mut args)?, errors::Format::Json);
+        assert_eq!(args, ["export", "--graph", "dot"]);
+
+        let mut args = vec!["input.csv".to_owned()];
+        assert_eq!(extract_errors_format(&mut args)?, errors::Format::Text);
+
+        let mut args = vec!["--errors-format".to_owned()];
+        assert_eq!(
+            extract_errors_format(&mut args),
+            Err(Error::MissingValue("--errors-format".into()))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(parse(&[]), Err(Error::MissingInput));
+        assert_eq!(parse(&["a.csv", "--rules"]), Err(Error::MissingValue("--rules".into())));
+        assert_eq!(parse(&["--foo", "a.csv"]), Err(Error::UnknownFlag("--foo".into())));
+        assert_eq!(
+            parse(&["a.csv", "--dormant-after", "x"]),
+            Err(Error::InvalidValue("--dormant-after".into(), "x".into()))
+        );
+        assert_eq!(
+            parse(&["a.csv", "--dormancy-report", "out.csv"]),
+            Err(Error::MissingFlag("--dormancy-report", "--dormant-after"))
+        );
+    }
+}
@@ -1,44 +1,97 @@
 //! A simple state machine to capture changes in user funds.
 
+use std::fmt;
+
 use rust_decimal::Decimal;
 use thiserror::Error;
 
+use crate::{EventIndex, TxId};
+
 /// [`ClientState`] is a simple state machine that captures the state of the funds of a user.
 ///
 /// The fields are private so that they can only be changed through transitions in the state machine, which should make
 /// our implementation more robust and easier to reason about.
 #[must_use]
-#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct ClientState {
-    frozen: bool,
+    frozen: Option<Freeze>,
     available: Decimal,
     held: Decimal,
     // We can always compute the total from `available` and `held`.
 }
 
+/// Records why and when a client account was frozen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Freeze {
+    /// The reason for freezing the account.
+    pub reason: FreezeReason,
+    /// Index of the event in the input stream that froze the account.
+    pub at: EventIndex,
+}
+
+/// The different reasons for freezing a client account.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum FreezeReason {
+    /// A chargeback was issued for the transaction with the given id.
+    Chargeback(TxId),
+}
+
+impl fmt::Display for FreezeReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FreezeReason::Chargeback(tx) => write!(f, "chargeback of tx {tx}"),
+        }
+    }
+}
+
 /// Errors that can happen during state transitions.
 #[derive(Debug, Error, PartialEq, Eq)]
 pub enum Error {
+    /// Frozen accounts don't accept any transitions.
     #[error("client is frozen")]
     ClientFrozen,
+    /// The available funds don't cover the amount.
     #[error("insufficient funds")]
     InsufficientFunds,
+    /// Only frozen accounts can be unfrozen.
+    #[error("client is not frozen")]
+    NotFrozen,
 }
 
 /// The different transitions of the state machine.
 #[derive(Clone, Copy, Debug)]
 pub enum Transition {
+    /// Adds the amount to the available funds.
     Deposit(Decimal),
+    /// Removes the amount from the available funds.
     Withdrawal(Decimal),
+    /// Moves the amount of a disputed deposit from the available to the held funds.
     DisputeDeposit(Decimal),
+    /// Holds the amount of a disputed withdrawal until the dispute is settled.
     DisputeWithdrawal(Decimal),
+    /// Releases the held amount of a dispute to the available funds.
     Resolve(Decimal),
-    Chargeback,
+    /// Removes the held amount of a disputed deposit and freezes the account.
+    Chargeback {
+        /// The held amount that is returned to the issuer.
+        amount: Decimal,
+        /// The charged back transaction.
+        tx: TxId,
+        /// Index of the chargeback in the input stream.
+        at: EventIndex,
+    },
+    /// Unfreezes a frozen account, the funds stay as they are.
+    Unfreeze,
 }
 
 impl ClientState {
     /// Returns `true` if the client account is frozen.
     pub fn frozen(&self) -> bool {
+        self.frozen.is_some()
+    }
+
+    /// Returns why and when the client account was frozen, if it is.
+    pub fn freeze(&self) -> Option<Freeze> {
         self.frozen
     }
 
@@ -63,8 +116,22 @@ impl ClientState {
     pub fn apply(mut self, transition: Transition) -> Result<Self, Error> {
         use Transition::*;
         match (transition, &mut self) {
-            (_, ClientState { frozen: true, .. }) => return Err(Error::ClientFrozen),
-            (Chargeback, ClientState { frozen, .. }) => *frozen = true,
+            (
+                Unfreeze,
+                ClientState {
+                    frozen: frozen @ Some(_),
+                    ..
+                },
+            ) => *frozen = None,
+            (Unfreeze, _) => return Err(Error::NotFrozen),
+            (_, ClientState { frozen: Some(_), .. }) => return Err(Error::ClientFrozen),
+            (Chargeback { amount, tx, at }, ClientState { frozen, held, .. }) => {
+                *held -= amount;
+                *frozen = Some(Freeze {
+                    reason: FreezeReason::Chargeback(tx),
+                    at,
+                })
+            }
             (Deposit(amount), ClientState { available, .. }) => *available += amount,
             (Withdrawal(amount), ClientState { available, .. }) => match *available < amount {
                 true => return Err(Error::InsufficientFunds),
@@ -93,13 +160,13 @@ mod test {
     use rust_decimal_macros::dec;
 
     use super::{
-        Transition::{Chargeback, Deposit, DisputeDeposit, Resolve, Withdrawal},
+        Transition::{Chargeback, Deposit, DisputeDeposit, Resolve, Unfreeze, Withdrawal},
         *,
     };
 
     // Used by other modules for testing.
     impl ClientState {
-        pub fn new(frozen: bool, available: Decimal, held: Decimal) -> Self {
+        pub(crate) fn new(frozen: Option<Freeze>, available: Decimal, held: Decimal) -> Self {
             Self {
                 frozen,
                 available,
@@ -133,15 +200,47 @@ mod test {
 
     #[test]
     fn frozen() -> Result<(), Error> {
-        let state = ClientState::default().apply(Deposit(dec!(42)))?.apply(Chargeback)?;
-        assert_eq!(state.available, dec!(42));
-        assert_eq!(state.frozen, true);
+        let state = ClientState::default()
+            .apply(Deposit(dec!(42)))?
+            .apply(DisputeDeposit(dec!(40)))?
+            .apply(Chargeback {
+                amount: dec!(40),
+                tx: 7,
+                at: 2,
+            })?;
+        assert_eq!(state.available, dec!(2));
+        assert_eq!(state.held, dec!(0));
+        assert_eq!(
+            state.freeze(),
+            Some(Freeze {
+                reason: FreezeReason::Chargeback(7),
+                at: 2
+            })
+        );
         let state = state.apply(Deposit(dec!(42)));
         assert_eq!(state, Err(Error::ClientFrozen));
 
         Ok(())
     }
 
+    #[test]
+    fn unfreeze() -> Result<(), Error> {
+        let state = ClientState::default().apply(Unfreeze);
+        assert_eq!(state, Err(Error::NotFrozen));
+
+        let freeze = Freeze {
+            reason: FreezeReason::Chargeback(7),
+            at: 2,
+        };
+        let state = ClientState::new(Some(freeze), dec!(2), dec!(0))
+            .apply(Unfreeze)?
+            .apply(Deposit(dec!(3)))?;
+        assert!(!state.frozen());
+        assert_eq!(state.available, dec!(5));
+
+        Ok(())
+    }
+
     #[test]
     fn dispute_resolve() -> Result<(), Error> {
         let state = ClientState::default().apply(Withdrawal(dec!(1)));
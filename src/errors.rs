@@ -0,0 +1,232 @@
+//! Reporting of fatal errors, either as human-readable text or as JSON objects with stable error codes.
+
+use std::{io, str::FromStr};
+
+use serde::Serialize;
+use thiserror::Error;
+use txh::{
+    blocklist, fees, graph, idmap, parallel, policy, records, reporting, rules, selftest, snapshot, source, state,
+    sweep, EventIndex,
+};
+
+use crate::{cli, output};
+
+/// Errors that are raised by the command line tool itself.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("determinism check failed: processing `{0}` twice produced different outputs")]
+    DeterminismCheckFailed(String),
+    #[error("input `{path}` has {size} bytes, which exceeds the limit of {limit} bytes")]
+    InputTooLarge { path: String, size: u64, limit: u64 },
+    #[error("snapshot `{path}` covers {events} events, but the inputs have fewer")]
+    SnapshotAheadOfInput { path: String, events: EventIndex },
+}
+
+/// The supported formats for errors.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// The error and its causes as text, the way `anyhow` prints them.
+    #[default]
+    Text,
+    /// A single line with a JSON object that contains the fields `level`, `code`, `message` and `causes`.
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonError {
+    level: &'static str,
+    code: &'static str,
+    message: String,
+    causes: Vec<String>,
+}
+
+/// Writes `err` to `writer` in the given `format`.
+pub fn report(err: &anyhow::Error, format: Format, mut writer: impl io::Write) -> io::Result<()> {
+    match format {
+        Format::Text => writeln!(writer, "Error: {err:?}"),
+        Format::Json => {
+            let json = JsonError {
+                level: "error",
+                code: code(err),
+                message: err.to_string(),
+                causes: err.chain().skip(1).map(ToString::to_string).collect(),
+            };
+            serde_json::to_writer(&mut writer, &json)?;
+            writeln!(writer)
+        }
+    }
+}
+
+/// Returns a stable code for the kind of `err`, based on the first cause with a known type.
+///
+/// The codes are part of the interface of the tool, so existing codes must not be changed.
+pub fn code(err: &anyhow::Error) -> &'static str {
+    err.chain().find_map(cause_code).unwrap_or("other")
+}
+
+fn cause_code(cause: &(dyn std::error::Error + 'static)) -> Option<&'static str> {
+    if let Some(err) = cause.downcast_ref::<Error>() {
+        return Some(match err {
+            Error::DeterminismCheckFailed(_) => "determinism_check_failed",
+            Error::InputTooLarge { .. } => "input_too_large",
+            Error::SnapshotAheadOfInput { .. } => "snapshot_ahead_of_input",
+        });
+    }
+    if let Some(err) = cause.downcast_ref::<selftest::Error>() {
+        return match err {
+            selftest::Error::Source(err) => cause_code(err),
+            selftest::Error::State(err) => cause_code(err),
+            _ => Some("selftest_failed"),
+        };
+    }
+    if let Some(err) = cause.downcast_ref::<parallel::Error>() {
+        return match err {
+            parallel::Error::Source(err) => cause_code(err),
+            parallel::Error::State(err) => cause_code(err),
+            parallel::Error::Transfer(_) => Some("transfer_not_parallel"),
+            parallel::Error::Group(_) => Some("group_not_parallel"),
+        };
+    }
+    if cause.is::<cli::Error>() {
+        return Some("usage");
+    }
+    if let Some(err) = cause.downcast_ref::<state::Error>() {
+        return Some(match err {
+            state::Error::DuplicateTxId(_) => "duplicate_tx_id",
+            state::Error::ForeignEvent { .. } => "foreign_event",
+            state::Error::SharedTransfer { .. } => "shared_transfer",
+            state::Error::FeeAccount(_) => "fee_account",
+            state::Error::FeeRefund(_) => "fee_refund",
+            state::Error::DuplicateClient(_) => "duplicate_client",
+            state::Error::ClientLimit(_) => "client_limit_exceeded",
+            state::Error::TransactionLimit(_) => "transaction_limit_exceeded",
+        });
+    }
+    if let Some(err) = cause.downcast_ref::<records::Error>() {
+        return Some(match err {
+            records::Error::InvalidTransactionType(_) => "invalid_transaction_type",
+            records::Error::MissingAmount(_) => "missing_amount",
+            records::Error::MissingRecipient(_) => "missing_recipient",
+            records::Error::ClientOutOfRange(_) => "client_out_of_range",
+            records::Error::ClientCollision(..) => "client_id_collision",
+            records::Error::NonPositiveAmount(..) => "non_positive_amount",
+            records::Error::TooPrecise(..) => "too_precise",
+        });
+    }
+    if let Some(err) = cause.downcast_ref::<reporting::Error>() {
+        return match err {
+            reporting::Error::State(err) => cause_code(err),
+            reporting::Error::Csv(err) => cause_code(err),
+        };
+    }
+    if let Some(err) = cause.downcast_ref::<source::Error>() {
+        return match err {
+            source::Error::Csv(err) => cause_code(err),
+            source::Error::Json { .. } => Some("invalid_json"),
+            source::Error::Io(err) => cause_code(err),
+            source::Error::Record(err) => cause_code(err),
+            source::Error::IdMap(err) => cause_code(err),
+            source::Error::Other(err) => cause_code(err.as_ref()),
+        };
+    }
+    if let Some(err) = cause.downcast_ref::<idmap::Error>() {
+        return match err {
+            idmap::Error::Csv(err) => cause_code(err),
+            idmap::Error::DuplicateExternal(_) | idmap::Error::DuplicateClient(_) => Some("invalid_id_map"),
+            idmap::Error::Exhausted(_) => Some("client_ids_exhausted"),
+        };
+    }
+    if let Some(err) = cause.downcast_ref::<snapshot::Error>() {
+        return match err {
+            snapshot::Error::Io(err) => cause_code(err),
+            snapshot::Error::Json(_) | snapshot::Error::NotASnapshot => Some("invalid_snapshot"),
+            snapshot::Error::Version { .. } => Some("unsupported_snapshot_version"),
+        };
+    }
+    if let Some(err) = cause.downcast_ref::<output::Error>() {
+        return match err {
+            output::Error::Io(err) => cause_code(err),
+            output::Error::Csv(err) => cause_code(err),
+            output::Error::Json(_) => Some("io"),
+        };
+    }
+    if let Some(err) = cause.downcast_ref::<graph::Error>() {
+        return match err {
+            graph::Error::UnknownFormat(_) => Some("usage"),
+            graph::Error::Io(err) => cause_code(err),
+            graph::Error::Json(_) => Some("io"),
+        };
+    }
+    if cause.is::<policy::Error>() {
+        return Some("invalid_policy");
+    }
+    if cause.is::<sweep::Error>() {
+        return Some("invalid_sweep");
+    }
+    if cause.is::<fees::Error>() {
+        return Some("invalid_fee_schedule");
+    }
+    if cause.is::<blocklist::Error>() {
+        return Some("invalid_blocklist");
+    }
+    if cause.is::<rules::Error>() {
+        return Some("rule_failed");
+    }
+    if let Some(err) = cause.downcast_ref::<csv::Error>() {
+        return Some(match err.kind() {
+            csv::ErrorKind::Io(_) => "io",
+            _ => "invalid_csv",
+        });
+    }
+    if cause.is::<io::Error>() {
+        return Some("io");
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use txh::keyvalue;
+
+    use super::*;
+
+    #[test]
+    fn codes() {
+        let err = anyhow::Error::new(state::Error::DuplicateTxId(1)).context("Failed to process.");
+        assert_eq!(code(&err), "duplicate_tx_id");
+
+        let err = anyhow::Error::new(io::Error::from(io::ErrorKind::NotFound)).context("Failed to open CSV.");
+        assert_eq!(code(&err), "io");
+
+        let err = anyhow::Error::new(reporting::Error::State(state::Error::DuplicateTxId(1)));
+        assert_eq!(code(&err), "duplicate_tx_id");
+
+        assert_eq!(code(&anyhow::anyhow!("Something else.")), "other");
+    }
+
+    #[test]
+    fn json() -> Result<(), Box<dyn std::error::Error>> {
+        let err = anyhow::Error::new(policy::Error::KeyValue(keyvalue::Error::Syntax(3)))
+            .context("Invalid policy: `limits.policy`.");
+        let mut output = Vec::new();
+        report(&err, Format::Json, &mut output)?;
+
+        let expected = r#"{"level":"error","code":"invalid_policy","message":"Invalid policy: `limits.policy`.","causes":["line 3: expected `key = value`"]}
+"#;
+        assert_eq!(String::from_utf8(output)?, expected);
+
+        Ok(())
+    }
+}
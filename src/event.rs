@@ -4,52 +4,146 @@ use rust_decimal::Decimal;
 
 use crate::{ClientId, TxId};
 
+/// A single entry of the input stream, which is applied to the state of a client.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Event {
+    /// Credits `amount` to the client.
     Deposit {
+        /// The client that receives the money.
         client: ClientId,
+        /// The id of the new transaction.
         tx: TxId,
+        /// The amount of money.
         amount: Decimal,
+        /// The merchant or other party that sent the money.
+        counterparty: Option<String>,
+        /// An earlier transaction that this one refers to, e.g. the withdrawal that a refund reverses.
+        related: Option<TxId>,
     },
+    /// Debits `amount` from the client, if the available funds suffice.
     Withdrawal {
+        /// The client that pays out the money.
         client: ClientId,
+        /// The id of the new transaction.
         tx: TxId,
+        /// The amount of money.
         amount: Decimal,
+        /// The merchant or other party that receives the money.
+        counterparty: Option<String>,
+        /// An earlier transaction that this one refers to, e.g. the withdrawal that a refund reverses.
+        related: Option<TxId>,
     },
+    /// Moves `amount` from one client to another, if the available funds of the sender suffice.
+    ///
+    /// The recipient can dispute the transfer like a deposit, and a chargeback returns the funds to the sender.
+    Transfer {
+        /// The client that pays the money.
+        from: ClientId,
+        /// The client that receives the money.
+        to: ClientId,
+        /// The id of the new transaction.
+        tx: TxId,
+        /// The amount of money.
+        amount: Decimal,
+    },
+    /// Claims that the deposit, withdrawal or transfer `tx` was erroneous and holds its funds.
     Dispute {
+        /// The client that the transaction belongs to.
         client: ClientId,
+        /// The disputed transaction.
         tx: TxId,
     },
+    /// Settles the dispute of `tx` in favour of the client and releases the held funds.
     Resolve {
+        /// The client that the transaction belongs to.
         client: ClientId,
+        /// The disputed transaction.
         tx: TxId,
     },
+    /// Reverses the deposit or transfer `tx` and freezes the client.
     Chargeback {
+        /// The client that the transaction belongs to.
         client: ClientId,
+        /// The charged back deposit.
         tx: TxId,
     },
+    /// Unfreezes the client after a manual review. This is an admin event, see [`State::allow_admin_events()`].
+    ///
+    /// [`State::allow_admin_events()`]: crate::State::allow_admin_events
+    Unlock {
+        /// The frozen client.
+        client: ClientId,
+    },
+}
+
+impl Event {
+    /// Returns the client that the event refers to, which is the sender of a transfer.
+    pub fn client(&self) -> ClientId {
+        match *self {
+            Event::Transfer { from, .. } => from,
+            Event::Deposit { client, .. }
+            | Event::Withdrawal { client, .. }
+            | Event::Dispute { client, .. }
+            | Event::Resolve { client, .. }
+            | Event::Chargeback { client, .. }
+            | Event::Unlock { client } => client,
+        }
+    }
+
+    /// Returns the transaction that the event refers to, admin events don't refer to any.
+    pub fn tx(&self) -> Option<TxId> {
+        match *self {
+            Event::Deposit { tx, .. }
+            | Event::Withdrawal { tx, .. }
+            | Event::Transfer { tx, .. }
+            | Event::Dispute { tx, .. }
+            | Event::Resolve { tx, .. }
+            | Event::Chargeback { tx, .. } => Some(tx),
+            Event::Unlock { .. } => None,
+        }
+    }
 }
 
 #[cfg(test)]
 // The following are convenience functions used for testing.
 impl Event {
-    pub fn deposit(client: ClientId, tx: TxId, amount: Decimal) -> Self {
-        Event::Deposit { client, tx, amount }
+    pub(crate) fn deposit(client: ClientId, tx: TxId, amount: Decimal) -> Self {
+        Event::Deposit {
+            client,
+            tx,
+            amount,
+            counterparty: None,
+            related: None,
+        }
     }
 
-    pub fn withdrawal(client: ClientId, tx: TxId, amount: Decimal) -> Self {
-        Event::Withdrawal { client, tx, amount }
+    pub(crate) fn withdrawal(client: ClientId, tx: TxId, amount: Decimal) -> Self {
+        Event::Withdrawal {
+            client,
+            tx,
+            amount,
+            counterparty: None,
+            related: None,
+        }
     }
 
-    pub fn dispute(client: ClientId, tx: TxId) -> Self {
+    pub(crate) fn transfer(from: ClientId, to: ClientId, tx: TxId, amount: Decimal) -> Self {
+        Event::Transfer { from, to, tx, amount }
+    }
+
+    pub(crate) fn dispute(client: ClientId, tx: TxId) -> Self {
         Event::Dispute { client, tx }
     }
 
-    pub fn resolve(client: ClientId, tx: TxId) -> Self {
+    pub(crate) fn resolve(client: ClientId, tx: TxId) -> Self {
         Event::Resolve { client, tx }
     }
 
-    pub fn chargeback(client: ClientId, tx: TxId) -> Self {
+    pub(crate) fn chargeback(client: ClientId, tx: TxId) -> Self {
         Event::Chargeback { client, tx }
     }
+
+    pub(crate) fn unlock(client: ClientId) -> Self {
+        Event::Unlock { client }
+    }
 }
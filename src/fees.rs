@@ -0,0 +1,207 @@
+//! A fee schedule for withdrawals, whose fees are collected into a designated fee account.
+//!
+//! The file consists of [`key = value`](crate::keyvalue) lines like a [policy](crate::policy). Empty lines and lines
+//! starting with `#` are ignored. The following keys are supported, each of them may appear at most once:
+//!
+//! * `account`: the client that collects the fees, required.
+//! * `flat`: a fixed fee per withdrawal.
+//! * `percentage`: a fee in percent of the withdrawn amount.
+//!
+//! ```text
+//! # Retail fees
+//! account = 9999
+//! flat = 0.25
+//! percentage = 1.5
+//! ```
+//!
+//! The fee of a withdrawal is the sum of both parts, rounded to [`DECIMAL_PLACES`](crate::precision::DECIMAL_PLACES).
+//! It is debited from the client in addition to the withdrawn amount, and withdrawals from the fee account itself are
+//! free. Rules don't check the fee account, see [`State::set_fee_schedule()`](crate::State::set_fee_schedule). Every
+//! [`Fee`] keeps the remainder of its rounding, so that the charged fees can be reconciled with the exact ones to the
+//! last digit.
+
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::{keyvalue, precision::Rounding, ClientId};
+
+/// Errors that can happen while parsing a fee schedule, each of them refers to a line in the file (starting at one).
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum Error {
+    /// The line is not a `key = value` pair, or its key is unknown or set more than once.
+    #[error(transparent)]
+    KeyValue(#[from] keyvalue::Error),
+    /// The value of `account` is not a client id.
+    #[error("line {0}: `{1}` is not a valid client")]
+    InvalidAccount(usize, String),
+    /// The value of `flat` or `percentage` is not a decimal number.
+    #[error("line {0}: `{1}` is not a valid amount")]
+    InvalidAmount(usize, String),
+    /// The value of `flat` or `percentage` is negative.
+    #[error("line {0}: `{1}` must not be negative")]
+    Negative(usize, String),
+    /// The file doesn't set `account`.
+    #[error("the fee `account` is not set")]
+    MissingAccount,
+}
+
+/// The fees of withdrawals and the account that collects them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FeeSchedule {
+    /// The client that collects the fees.
+    pub account: ClientId,
+    /// A fixed fee per withdrawal.
+    pub flat: Decimal,
+    /// A fee in percent of the withdrawn amount.
+    pub percentage: Decimal,
+    /// How fees are rounded to [`DECIMAL_PLACES`](crate::precision::DECIMAL_PLACES).
+    pub rounding: Rounding,
+}
+
+impl FeeSchedule {
+    /// Returns the fee of a withdrawal of `amount` by `client`, or `None` if the client is the fee account.
+    pub fn fee(&self, client: ClientId, amount: Decimal) -> Option<Fee> {
+        if client == self.account {
+            return None;
+        }
+        let exact = self.flat + amount * self.percentage / Decimal::ONE_HUNDRED;
+        let charged = self.rounding.round(exact);
+        Some(Fee {
+            account: self.account,
+            amount: charged.normalize(),
+            remainder: (exact - charged).normalize(),
+        })
+    }
+}
+
+/// The fee of a single withdrawal, together with the rounding that was applied to it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Fee {
+    /// The client that collected the fee.
+    pub account: ClientId,
+    /// The charged fee, rounded to [`DECIMAL_PLACES`](crate::precision::DECIMAL_PLACES).
+    pub amount: Decimal,
+    /// The exact fee minus the charged one, which is positive if the fee was rounded down and negative if it was
+    /// rounded up.
+    pub remainder: Decimal,
+}
+
+impl FromStr for FeeSchedule {
+    type Err = Error;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let mut account = None;
+        let mut flat = None;
+        let mut percentage = None;
+
+        for pair in keyvalue::pairs(source, &["account", "flat", "percentage"]) {
+            let (line, key, value) = pair?;
+            let fee = match key {
+                "account" => {
+                    account = Some(ClientId::from_str(value).map_err(|_| Error::InvalidAccount(line, value.into()))?);
+                    continue;
+                }
+                "flat" => &mut flat,
+                "percentage" => &mut percentage,
+                _ => unreachable!("`pairs()` only yields the given keys"),
+            };
+            let amount = Decimal::from_str(value).map_err(|_| Error::InvalidAmount(line, value.into()))?;
+            if amount < Decimal::ZERO {
+                return Err(Error::Negative(line, key.into()));
+            }
+            *fee = Some(amount);
+        }
+
+        Ok(FeeSchedule {
+            account: account.ok_or(Error::MissingAccount)?,
+            flat: flat.unwrap_or_default(),
+            percentage: percentage.unwrap_or_default(),
+            rounding: Rounding::default(),
+        })
+    }
+}
+
+/// The fees that were collected while processing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FeeSummary {
+    /// The number of withdrawals that paid a fee.
+    pub withdrawals: u64,
+    /// The sum of all fees.
+    pub collected: Decimal,
+    /// The sum of the rounding remainders of all fees, see [`Fee::remainder`].
+    pub remainder: Decimal,
+}
+
+impl FeeSummary {
+    /// Records the fee of a withdrawal, a fee that was rounded to zero only adds its remainder.
+    pub fn collect(&mut self, fee: &Fee) {
+        if fee.amount > Decimal::ZERO {
+            self.withdrawals += 1;
+            self.collected += fee.amount;
+        }
+        self.remainder += fee.remainder;
+    }
+
+    /// Takes back a fee that was recorded with [`FeeSummary::collect()`], see
+    /// [`State::recompute_client()`](crate::State::recompute_client).
+    pub fn refund(&mut self, fee: &Fee) {
+        if fee.amount > Decimal::ZERO {
+            self.withdrawals -= 1;
+            self.collected -= fee.amount;
+        }
+        self.remainder -= fee.remainder;
+    }
+
+    /// Adds the fees collected by another state, see [`State::merge()`](crate::State::merge).
+    pub fn merge(&mut self, other: &FeeSummary) {
+        self.withdrawals += other.withdrawals;
+        self.collected += other.collected;
+        self.remainder += other.remainder;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn parse() -> Result<(), Error> {
+        let schedule: FeeSchedule = "# Retail\naccount = 9999\n\nflat = 0.25\npercentage = 1.5\n".parse()?;
+        let fee = |amount, remainder| {
+            Some(Fee {
+                account: 9999,
+                amount,
+                remainder,
+            })
+        };
+        assert_eq!(schedule.fee(1, dec!(100)), fee(dec!(1.75), dec!(0)));
+        // The exact fees 0.25015 and 0.25045 are ties, which are rounded up and down to the even digit.
+        assert_eq!(schedule.fee(1, dec!(0.01)), fee(dec!(0.2502), dec!(-0.00005)));
+        assert_eq!(schedule.fee(1, dec!(0.03)), fee(dec!(0.2504), dec!(0.00005)));
+        assert_eq!(schedule.fee(9999, dec!(100)), None);
+
+        let schedule: FeeSchedule = "account = 1\npercentage = 2".parse()?;
+        assert_eq!(schedule.flat, dec!(0));
+        assert_eq!(schedule.fee(2, dec!(10)).map(|fee| fee.amount), Some(dec!(0.2)));
+
+        assert_eq!("flat = 1".parse::<FeeSchedule>(), Err(Error::MissingAccount));
+        assert_eq!(
+            "account = 1\nflat = -1".parse::<FeeSchedule>(),
+            Err(Error::Negative(2, "flat".into()))
+        );
+        assert_eq!(
+            "account = x".parse::<FeeSchedule>(),
+            Err(Error::InvalidAccount(1, "x".into()))
+        );
+        assert_eq!(
+            "account = 1\naccount = 2".parse::<FeeSchedule>(),
+            Err(Error::KeyValue(keyvalue::Error::DuplicateKey(2, "account".into())))
+        );
+
+        Ok(())
+    }
+}
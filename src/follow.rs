@@ -0,0 +1,310 @@
+//! Reading of an input file that is still being appended to, see `--follow`.
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fs::File,
+    io::{self, Read},
+    rc::Rc,
+    time::Duration,
+};
+
+use txh::{
+    event::Event,
+    idmap::IdMap,
+    source::{self, EventSource},
+    GroupId,
+};
+
+/// How long to wait before looking for new lines again once the end of the file is reached.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The size of the blocks in which the file is read, in bytes.
+const BLOCK_SIZE: usize = 64 * 1024;
+
+/// Splits a growing file into chunks of complete lines, like `tail -f`.
+///
+/// A line that is only partially written stays in the buffer until its newline arrives. The chunks are parsed with
+/// [`Events`].
+pub struct Follow<R> {
+    reader: R,
+    /// Bytes that were read but not returned yet.
+    pending: Vec<u8>,
+}
+
+impl Follow<File> {
+    /// Opens the file at `path`.
+    pub fn open(path: &str) -> io::Result<Self> {
+        Ok(Self::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> Follow<R> {
+    /// Follows `reader`.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Returns the next chunk of at most `limit` complete lines, or `None` if no complete line was appended since the
+    /// last chunk.
+    pub fn next_chunk(&mut self, limit: usize) -> io::Result<Option<Vec<u8>>> {
+        let lines = self.take_lines(limit)?;
+        Ok((!lines.is_empty()).then_some(lines))
+    }
+
+    /// Removes and returns at most `limit` complete lines from the start of the buffer, reading more if needed.
+    fn take_lines(&mut self, limit: usize) -> io::Result<Vec<u8>> {
+        let mut lines = 0;
+        let mut end = 0;
+        loop {
+            while lines < limit {
+                let Some(position) = self.pending[end..].iter().position(|&byte| byte == b'\n') else {
+                    break;
+                };
+                end += position + 1;
+                lines += 1;
+            }
+            if lines == limit {
+                break;
+            }
+            let len = self.pending.len();
+            self.pending.resize(len + BLOCK_SIZE, 0);
+            let read = self.reader.read(&mut self.pending[len..])?;
+            self.pending.truncate(len + read);
+            if read == 0 {
+                break;
+            }
+        }
+        let rest = self.pending.split_off(end);
+        Ok(std::mem::replace(&mut self.pending, rest))
+    }
+}
+
+/// The events of the chunks of a followed file, which are all parsed by the same source.
+///
+/// Line numbers of malformed records therefore count from the start of the file, and a CSV header is only read once.
+/// The rows of an atomic group at the end of a chunk are held back until a later row shows that the group is complete,
+/// so that a group is applied at once even if it is split across chunks. After the events of a chunk are read, the
+/// source ends until the next chunk is [pushed](Events::push).
+pub struct Events {
+    feed: Feed,
+    source: Box<dyn EventSource>,
+    /// Whether the next line is the header of a CSV input, which the source reads together with the first record.
+    header: bool,
+    /// The lines of the current chunk that were not parsed yet.
+    chunk: VecDeque<u8>,
+    /// Events of complete groups or without a group that are ready to be returned, and malformed records.
+    ready: VecDeque<Parsed>,
+    /// The events of the last parsed group, which may continue in the next chunk.
+    group: Option<(GroupId, Vec<Parsed>)>,
+    /// The line and group of the last returned event or error.
+    last: (Option<u64>, Option<GroupId>),
+}
+
+/// An event or a malformed record together with its group and line.
+///
+/// Malformed records are kept in order with the events of their group, so that a [`Lenient`](source::Lenient) source
+/// sees the whole group at once.
+type Parsed = (Result<Event, source::Error>, Option<GroupId>, Option<u64>);
+
+impl Events {
+    /// Creates a source of events in `format`, whose clients are mapped with `id_map` if it is given.
+    pub fn new(format: source::Format, id_map: Option<&Rc<RefCell<IdMap>>>) -> Self {
+        let feed = Feed::default();
+        Self {
+            source: crate::read_source(Box::new(feed.clone()), format, id_map),
+            feed,
+            header: format == source::Format::Csv,
+            chunk: VecDeque::new(),
+            ready: VecDeque::new(),
+            group: None,
+            last: (None, None),
+        }
+    }
+
+    /// Appends a `chunk` of complete lines, whose events are returned next.
+    pub fn push(&mut self, chunk: Vec<u8>) {
+        self.chunk.extend(chunk);
+    }
+
+    /// Moves the next line of the chunk that isn't blank, and any blank lines before it, to the feed of the source.
+    ///
+    /// Returns `false` if the chunk has no such line. The source skips blank lines itself, but it must never find the
+    /// feed empty, as that would end it for good.
+    fn feed_line(&mut self) -> bool {
+        let mut end = 0;
+        loop {
+            let Some(length) = self.chunk.range(end..).position(|&byte| byte == b'\n') else {
+                return false;
+            };
+            let blank = self.chunk.range(end..end + length).all(u8::is_ascii_whitespace);
+            end += length + 1;
+            if !blank {
+                break;
+            }
+        }
+        let line: Vec<u8> = self.chunk.drain(..end).collect();
+        self.feed.push(&line);
+        true
+    }
+}
+
+impl EventSource for Events {
+    fn next_event(&mut self) -> Option<Result<Event, source::Error>> {
+        loop {
+            if let Some((event, group, line)) = self.ready.pop_front() {
+                self.last = (line, group);
+                return Some(event);
+            }
+            if !self.feed_line() {
+                return None;
+            }
+            if self.header {
+                self.header = false;
+                continue;
+            }
+
+            let Some(event) = self.source.next_event() else {
+                let err = io::Error::new(io::ErrorKind::UnexpectedEof, "the input ended within a record");
+                return Some(Err(err.into()));
+            };
+            let parsed = (event, self.source.group(), self.source.line());
+            match (parsed.1, &mut self.group) {
+                (Some(next), Some((current, events))) if next == *current => events.push(parsed),
+                // Errors without a group don't end the current one.
+                (None, _) if parsed.0.is_err() => self.ready.push_back(parsed),
+                (next, _) => {
+                    if let Some((_, events)) = self.group.take() {
+                        self.ready.extend(events);
+                    }
+                    match next {
+                        Some(next) => self.group = Some((next, vec![parsed])),
+                        None => self.ready.push_back(parsed),
+                    }
+                }
+            }
+        }
+    }
+
+    fn line(&self) -> Option<u64> {
+        self.last.0
+    }
+
+    fn group(&self) -> Option<GroupId> {
+        self.last.1
+    }
+}
+
+/// A reader of the lines that were pushed to it, which lets a source parse one line at a time.
+///
+/// Lines are only pushed right before the source reads them, so the source never sees the end of the data.
+#[derive(Clone, Default)]
+pub struct Feed(Rc<RefCell<VecDeque<u8>>>);
+
+impl Feed {
+    /// Appends `line`, which ends with a newline.
+    pub fn push(&self, line: &[u8]) {
+        self.0.borrow_mut().extend(line);
+    }
+}
+
+impl Read for Feed {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.borrow_mut().read(buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::iter;
+
+    use txh::TxId;
+
+    use super::*;
+
+    /// A reader that returns what was appended to it so far.
+    #[derive(Clone, Default)]
+    struct Growing(Rc<RefCell<Vec<u8>>>);
+
+    impl Growing {
+        fn append(&self, bytes: &[u8]) {
+            self.0.borrow_mut().extend_from_slice(bytes);
+        }
+    }
+
+    impl Read for Growing {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let mut bytes = self.0.borrow_mut();
+            let len = bytes.len().min(buf.len());
+            buf[..len].copy_from_slice(&bytes[..len]);
+            bytes.drain(..len);
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn chunks() -> io::Result<()> {
+        let file = Growing::default();
+        let mut follow = Follow::new(file.clone());
+        assert_eq!(follow.next_chunk(2)?, None);
+
+        file.append(b"type,client\ndeposit,1\ndeposit,2\ndepo");
+        assert_eq!(follow.next_chunk(2)?.as_deref(), Some(&b"type,client\ndeposit,1\n"[..]));
+        assert_eq!(follow.next_chunk(2)?.as_deref(), Some(&b"deposit,2\n"[..]));
+        assert_eq!(follow.next_chunk(2)?, None);
+
+        file.append(b"sit,3\n");
+        assert_eq!(follow.next_chunk(2)?.as_deref(), Some(&b"deposit,3\n"[..]));
+
+        Ok(())
+    }
+
+    /// The transaction id and group of an event, or the line of a malformed record.
+    type Item = Result<(TxId, Option<GroupId>), Option<u64>>;
+
+    /// Returns what is left to read from `events`.
+    fn drain(events: &mut Events) -> Vec<Item> {
+        iter::from_fn(|| {
+            events.next_event().map(|event| match event {
+                Ok(event) => Ok((event.tx().unwrap_or_default(), events.group())),
+                Err(_) => Err(events.line()),
+            })
+        })
+        .collect()
+    }
+
+    #[test]
+    fn events() {
+        let mut events = Events::new(source::Format::Csv, None);
+        events.push(b"type,client,tx,amount,group\ndeposit,1,1,1,\n\ndeposit,1,2,1,7\n".to_vec());
+        // The group may continue in the next chunk.
+        assert_eq!(drain(&mut events), [Ok((1, None))]);
+
+        // A malformed record doesn't end the group, and its line counts from the start of the file.
+        events.push(b"withdrawal,1,3,1,7\ndeposit,x,4,1,\ndeposit,1,5,1,\n".to_vec());
+        let read = [Err(Some(6)), Ok((2, Some(7))), Ok((3, Some(7))), Ok((5, None))];
+        assert_eq!(drain(&mut events), read);
+
+        events.push(b"deposit,1,6,1,8\n".to_vec());
+        assert_eq!(drain(&mut events), []);
+        events.push(b"deposit,y,7,1,\n\n".to_vec());
+        assert_eq!(drain(&mut events), [Err(Some(9))]);
+        events.push(b"deposit,1,8,1,\n".to_vec());
+        assert_eq!(drain(&mut events), [Ok((6, Some(8))), Ok((8, None))]);
+
+        // A malformed record of a group is held back with it.
+        events.push(b"deposit,1,9,1,10\ndeposit,q,10,1,10\n".to_vec());
+        assert_eq!(drain(&mut events), []);
+        events.push(b"deposit,1,11,1,\n".to_vec());
+        assert_eq!(drain(&mut events), [Ok((9, Some(10))), Err(Some(13)), Ok((11, None))]);
+
+        let mut events = Events::new(source::Format::Ndjson, None);
+        events.push(b"{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"1\"}\n".to_vec());
+        assert_eq!(drain(&mut events), [Ok((1, None))]);
+        events.push(b"{}\n".to_vec());
+        assert_eq!(drain(&mut events), [Err(Some(2))]);
+    }
+}
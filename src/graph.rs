@@ -0,0 +1,173 @@
+//! Exports the references between transactions as a graph for investigation tooling.
+
+use std::{io, str::FromStr};
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{state::State, transaction::Transaction, ClientId, TxId};
+
+/// Errors that can happen while exporting a graph.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The name of the format is not supported.
+    #[error("unknown graph format: `{0}`, expected `dot` or `json`")]
+    UnknownFormat(String),
+    /// Writing the graph failed.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// Serializing the graph as JSON failed.
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// The supported output formats.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// The [DOT language](https://graphviz.org/doc/info/lang.html) of Graphviz.
+    Dot,
+    /// A JSON object with a list of `nodes` and a list of `edges`.
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(Format::Dot),
+            "json" => Ok(Format::Json),
+            _ => Err(Error::UnknownFormat(s.into())),
+        }
+    }
+}
+
+/// A transaction in the graph.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Node {
+    /// The id of the transaction.
+    pub tx: TxId,
+    /// The client that the transaction belongs to, see [`Transaction::client()`].
+    pub client: ClientId,
+    /// Either `deposit`, `withdrawal` or `transfer`.
+    #[serde(rename = "type")]
+    pub ty: &'static str,
+    /// The amount of the transaction.
+    pub amount: Decimal,
+}
+
+/// A reference from the transaction `from` to the earlier transaction `to`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Edge {
+    /// The referring transaction.
+    pub from: TxId,
+    /// The referenced transaction.
+    pub to: TxId,
+}
+
+/// The reference graph of all retained transactions, ordered by transaction id.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Graph {
+    /// All transactions.
+    pub nodes: Vec<Node>,
+    /// All references between transactions, including those to unknown transactions.
+    pub edges: Vec<Edge>,
+}
+
+impl Graph {
+    /// Builds the graph from the transactions in `state`.
+    pub fn new(state: &State) -> Self {
+        let mut nodes = Vec::new();
+        let mut edges = Vec::new();
+
+        for (&tx, transaction) in state.transactions() {
+            let (ty, client, amount, related) = match transaction {
+                Transaction::Deposit(deposit) => ("deposit", deposit.client, deposit.amount, deposit.related),
+                Transaction::Withdrawal(withdrawal) => {
+                    ("withdrawal", withdrawal.client, withdrawal.amount, withdrawal.related)
+                }
+                Transaction::Transfer(transfer) => ("transfer", transfer.to, transfer.amount, None),
+            };
+            nodes.push(Node { tx, client, ty, amount });
+            if let Some(to) = related {
+                edges.push(Edge { from: tx, to });
+            }
+        }
+
+        nodes.sort_by_key(|node| node.tx);
+        edges.sort_by_key(|edge| (edge.from, edge.to));
+
+        Self { nodes, edges }
+    }
+
+    /// Writes the graph to `writer` in the given `format`.
+    pub fn write(&self, format: Format, mut writer: impl io::Write) -> Result<(), Error> {
+        match format {
+            Format::Dot => {
+                writeln!(writer, "digraph transactions {{")?;
+                for Node { tx, client, ty, amount } in &self.nodes {
+                    writeln!(writer, "    {tx} [label=\"{ty} {tx}\\nclient {client}\\n{amount}\"];")?;
+                }
+                for Edge { from, to } in &self.edges {
+                    writeln!(writer, "    {from} -> {to};")?;
+                }
+                writeln!(writer, "}}")?;
+            }
+            Format::Json => {
+                serde_json::to_writer(&mut writer, self)?;
+                writeln!(writer)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::event::Event;
+
+    fn graph() -> Result<Graph, crate::state::Error> {
+        let mut state = State::new();
+        state.handle(Event::deposit(1, 2, dec!(10)))?;
+        state.handle(Event::Withdrawal {
+            client: 1,
+            tx: 3,
+            amount: dec!(4),
+            counterparty: None,
+            related: Some(2),
+        })?;
+        Ok(Graph::new(&state))
+    }
+
+    #[test]
+    fn dot() -> Result<(), Box<dyn std::error::Error>> {
+        let mut output = Vec::new();
+        graph()?.write(Format::Dot, &mut output)?;
+
+        let expected = "digraph transactions {
+    2 [label=\"deposit 2\\nclient 1\\n10\"];
+    3 [label=\"withdrawal 3\\nclient 1\\n4\"];
+    3 -> 2;
+}
+";
+        assert_eq!(String::from_utf8(output)?, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn json() -> Result<(), Box<dyn std::error::Error>> {
+        let mut output = Vec::new();
+        graph()?.write(Format::Json, &mut output)?;
+
+        let expected = r#"{"nodes":[{"tx":2,"client":1,"type":"deposit","amount":"10"},{"tx":3,"client":1,"type":"withdrawal","amount":"4"}],"edges":[{"from":3,"to":2}]}
+"#;
+        assert_eq!(String::from_utf8(output)?, expected);
+
+        Ok(())
+    }
+}
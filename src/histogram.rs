@@ -0,0 +1,173 @@
+//! Distributions of amounts and balances, to sanity-check the input data at a glance.
+
+use std::{collections::BTreeMap, fmt};
+
+use rust_decimal::Decimal;
+
+use crate::{state::State, transaction::Transaction};
+
+/// A range of amounts that a [`Histogram`] counts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Bucket {
+    /// All amounts below zero.
+    Negative,
+    /// Exactly zero.
+    Zero,
+    /// Amounts from `10^e` up to, but excluding, `10^(e + 1)`.
+    PowerOfTen(i32),
+}
+
+impl Bucket {
+    /// Returns the bucket that contains `amount`.
+    pub fn of(amount: Decimal) -> Self {
+        if amount.is_zero() {
+            return Bucket::Zero;
+        }
+        if amount.is_sign_negative() {
+            return Bucket::Negative;
+        }
+        // The amount is `mantissa * 10^-scale`, so its magnitude follows from the number of digits of the mantissa.
+        let digits = amount.mantissa().unsigned_abs().to_string().len() as i32;
+        Bucket::PowerOfTen(digits - 1 - amount.scale() as i32)
+    }
+}
+
+impl fmt::Display for Bucket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bucket::Negative => f.pad("< 0"),
+            Bucket::Zero => f.pad("0"),
+            Bucket::PowerOfTen(e) => f.pad(&format!("[1e{e}, 1e{})", e + 1)),
+        }
+    }
+}
+
+/// Counts amounts in buckets that grow by powers of ten.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Histogram {
+    buckets: BTreeMap<Bucket, u64>,
+}
+
+impl Histogram {
+    /// Counts `amount` in its bucket.
+    pub fn add(&mut self, amount: Decimal) {
+        *self.buckets.entry(Bucket::of(amount)).or_default() += 1;
+    }
+
+    /// Returns the non-empty buckets and their counts, in ascending order.
+    pub fn buckets(&self) -> impl Iterator<Item = (Bucket, u64)> + '_ {
+        self.buckets.iter().map(|(&bucket, &count)| (bucket, count))
+    }
+}
+
+impl FromIterator<Decimal> for Histogram {
+    fn from_iter<I: IntoIterator<Item = Decimal>>(amounts: I) -> Self {
+        let mut histogram = Histogram::default();
+        for amount in amounts {
+            histogram.add(amount);
+        }
+        histogram
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (bucket, count) in self.buckets() {
+            writeln!(f, "  {bucket:<16} {count:>10}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The histograms of a processed [`State`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Histograms {
+    /// Amounts of the retained deposits.
+    pub deposits: Histogram,
+    /// Total funds of each client.
+    pub balances: Histogram,
+    /// Amounts of the deposits and withdrawals that have been disputed at least once.
+    pub disputes: Histogram,
+}
+
+impl Histograms {
+    /// Computes the histograms of `state`.
+    pub fn new(state: &State) -> Self {
+        let deposits = state
+            .transactions()
+            .filter_map(|(_, transaction)| match transaction {
+                Transaction::Deposit(deposit) => Some(deposit.amount),
+                Transaction::Withdrawal(_) | Transaction::Transfer(_) => None,
+            })
+            .collect();
+        let balances = state.client_states().map(|(_, client)| client.total()).collect();
+        let disputes = state
+            .transactions()
+            .filter_map(|(_, transaction)| match transaction {
+                Transaction::Deposit(deposit) => (deposit.disputes > 0).then_some(deposit.amount),
+                Transaction::Withdrawal(withdrawal) => (withdrawal.disputes > 0).then_some(withdrawal.amount),
+                Transaction::Transfer(transfer) => (transfer.disputes > 0).then_some(transfer.amount),
+            })
+            .collect();
+
+        Self {
+            deposits,
+            balances,
+            disputes,
+        }
+    }
+}
+
+impl fmt::Display for Histograms {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Deposit amounts:")?;
+        write!(f, "{}", self.deposits)?;
+        writeln!(f, "Client balances:")?;
+        write!(f, "{}", self.balances)?;
+        writeln!(f, "Disputed amounts:")?;
+        write!(f, "{}", self.disputes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::{event::Event, state};
+
+    #[test]
+    fn buckets() {
+        assert_eq!(Bucket::of(dec!(-0.5)), Bucket::Negative);
+        assert_eq!(Bucket::of(dec!(0.000)), Bucket::Zero);
+        assert_eq!(Bucket::of(dec!(0.05)), Bucket::PowerOfTen(-2));
+        assert_eq!(Bucket::of(dec!(1.0)), Bucket::PowerOfTen(0));
+        assert_eq!(Bucket::of(dec!(9.9999)), Bucket::PowerOfTen(0));
+        assert_eq!(Bucket::of(dec!(10)), Bucket::PowerOfTen(1));
+        assert_eq!(Bucket::PowerOfTen(-2).to_string(), "[1e-2, 1e-1)");
+    }
+
+    #[test]
+    fn histograms() -> Result<(), state::Error> {
+        let mut state = State::new();
+        state.handle(Event::deposit(0, 0, dec!(5)))?;
+        state.handle(Event::deposit(0, 1, dec!(50)))?;
+        state.handle(Event::deposit(1, 2, dec!(7)))?;
+        state.handle(Event::withdrawal(1, 3, dec!(7)))?;
+        state.handle(Event::dispute(0, 1))?;
+
+        let histograms = Histograms::new(&state);
+        let buckets = |histogram: &Histogram| histogram.buckets().collect::<Vec<_>>();
+        assert_eq!(
+            buckets(&histograms.deposits),
+            [(Bucket::PowerOfTen(0), 2), (Bucket::PowerOfTen(1), 1)]
+        );
+        assert_eq!(
+            buckets(&histograms.balances),
+            [(Bucket::Zero, 1), (Bucket::PowerOfTen(1), 1)]
+        );
+        assert_eq!(buckets(&histograms.disputes), [(Bucket::PowerOfTen(1), 1)]);
+
+        Ok(())
+    }
+}
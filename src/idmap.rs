@@ -0,0 +1,136 @@
+//! A mapping of external client identifiers, e.g. UUIDs or account numbers, to internal [`ClientId`]s.
+//!
+//! Clients that are not in the mapping yet get the next unused id. The mapping is stored as CSV with the columns of
+//! [`IdMapCsvRecord`], so that later runs assign the same ids and the output can be translated back.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+};
+
+use thiserror::Error;
+
+use crate::{records::IdMapCsvRecord, ClientId};
+
+/// Errors that can happen while loading or extending a mapping.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The mapping could not be read or written.
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    /// The mapping contains an external identifier twice.
+    #[error("external client `{0}` is mapped twice")]
+    DuplicateExternal(String),
+    /// The mapping contains an internal id twice.
+    #[error("client `{0}` is mapped twice")]
+    DuplicateClient(ClientId),
+    /// All internal ids have been assigned.
+    #[error("no client ids left for external client `{0}`")]
+    Exhausted(String),
+}
+
+/// The internal ids of external client identifiers.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IdMap {
+    clients: HashMap<String, ClientId>,
+    /// The next id to assign, which is one more than the largest assigned id.
+    next: u64,
+}
+
+impl IdMap {
+    /// Creates an empty mapping.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads a mapping from CSV.
+    pub fn load(reader: impl io::Read) -> Result<Self, Error> {
+        let mut map = Self::new();
+        let mut assigned = HashSet::new();
+        for record in csv::Reader::from_reader(reader).deserialize() {
+            let IdMapCsvRecord { external, client } = record?;
+            if !assigned.insert(client) {
+                return Err(Error::DuplicateClient(client));
+            }
+            if map.clients.contains_key(&external) {
+                return Err(Error::DuplicateExternal(external));
+            }
+            map.next = map.next.max(u64::from(client) + 1);
+            map.clients.insert(external, client);
+        }
+        Ok(map)
+    }
+
+    /// Writes the mapping as CSV, ordered by the internal id.
+    pub fn save(&self, writer: impl io::Write) -> Result<(), Error> {
+        let mut records: Vec<_> = self.clients.iter().collect();
+        records.sort_unstable_by_key(|&(_, client)| client);
+
+        let mut writer = csv::Writer::from_writer(writer);
+        for (external, &client) in records {
+            writer.serialize(IdMapCsvRecord {
+                external: external.clone(),
+                client,
+            })?;
+        }
+        writer.flush().map_err(csv::Error::from)?;
+        Ok(())
+    }
+
+    /// Returns the internal id of `external`, if it has one.
+    pub fn get(&self, external: &str) -> Option<ClientId> {
+        self.clients.get(external).copied()
+    }
+
+    /// Returns the internal id of `external`, and assigns the next unused id if it doesn't have one yet.
+    pub fn assign(&mut self, external: &str) -> Result<ClientId, Error> {
+        if let Some(client) = self.get(external) {
+            return Ok(client);
+        }
+        let client = ClientId::try_from(self.next).map_err(|_| Error::Exhausted(external.to_owned()))?;
+        self.clients.insert(external.to_owned(), client);
+        self.next += 1;
+        Ok(client)
+    }
+
+    /// Returns the number of mapped clients.
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Returns `true` if no clients are mapped.
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn assign() -> Result<(), Error> {
+        let input = "external,client\nacc-7,7\nacc-2,2\n";
+        let mut map = IdMap::load(input.as_bytes())?;
+        assert_eq!(map.assign("acc-2")?, 2);
+        assert_eq!(map.assign("3f2a")?, 8);
+        assert_eq!(map.assign("acc-9")?, 9);
+        assert_eq!(map.assign("3f2a")?, 8);
+        assert_eq!(map.len(), 4);
+
+        let mut output = Vec::new();
+        map.save(&mut output)?;
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "external,client\nacc-2,2\nacc-7,7\n3f2a,8\nacc-9,9\n"
+        );
+
+        let input = "external,client\nacc-7,7\nacc-8,7\n";
+        assert!(matches!(IdMap::load(input.as_bytes()), Err(Error::DuplicateClient(7))));
+
+        let mut map = IdMap::load(format!("external,client\nlast,{}\n", ClientId::MAX).as_bytes())?;
+        assert!(matches!(map.assign("more"), Err(Error::Exhausted(_))));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+//! The `key = value` files of [policies](crate::policy), [fee schedules](crate::fees) and [sweeps](crate::sweep).
+//!
+//! Empty lines and lines starting with `#` are ignored, every other line is a `key = value` pair. Each kind of file
+//! supports its own keys, and each of them may appear at most once.
+
+use std::collections::HashSet;
+
+use thiserror::Error;
+
+/// Errors that can happen while reading the pairs of a file, each of them refers to a line in the file (starting at
+/// one).
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum Error {
+    /// The line is neither empty, a comment nor a `key = value` pair.
+    #[error("line {0}: expected `key = value`")]
+    Syntax(usize),
+    /// The key is not one of the supported keys.
+    #[error("line {0}: unknown key `{1}`")]
+    UnknownKey(usize, String),
+    /// The key was already set on an earlier line.
+    #[error("line {0}: `{1}` is set more than once")]
+    DuplicateKey(usize, String),
+}
+
+/// Returns the line, key and value of each pair in `source`, with the key and value trimmed.
+///
+/// A pair whose key isn't one of `keys`, or that was already set, is an error.
+pub fn pairs<'a>(
+    source: &'a str,
+    keys: &'a [&'a str],
+) -> impl Iterator<Item = Result<(usize, &'a str, &'a str), Error>> + 'a {
+    let mut seen = HashSet::new();
+    (1..).zip(source.lines()).filter_map(move |(line, content)| {
+        let content = content.trim();
+        if content.is_empty() || content.starts_with('#') {
+            return None;
+        }
+        Some(pair(line, content, keys, &mut seen))
+    })
+}
+
+/// Returns the key and value of the `content` of a `line` for [`pairs()`].
+fn pair<'a>(
+    line: usize,
+    content: &'a str,
+    keys: &[&str],
+    seen: &mut HashSet<&'a str>,
+) -> Result<(usize, &'a str, &'a str), Error> {
+    let (key, value) = content.split_once('=').ok_or(Error::Syntax(line))?;
+    let (key, value) = (key.trim(), value.trim());
+    if !keys.contains(&key) {
+        return Err(Error::UnknownKey(line, key.into()));
+    }
+    if !seen.insert(key) {
+        return Err(Error::DuplicateKey(line, key.into()));
+    }
+    Ok((line, key, value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() -> Result<(), Error> {
+        let source = "# comment\n\n  a = 1 \nb=\n";
+        let parsed = pairs(source, &["a", "b", "c"]).collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(parsed, [(3, "a", "1"), (4, "b", "")]);
+
+        let errors = |source| pairs(source, &["a"]).find_map(Result::err);
+        assert_eq!(errors("a 1"), Some(Error::Syntax(1)));
+        assert_eq!(errors("a = 1\nb = 2"), Some(Error::UnknownKey(2, "b".into())));
+        assert_eq!(errors("a = 1\na = 1"), Some(Error::DuplicateKey(2, "a".into())));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+// Usually it is better to catch these things in a CI workflow, but for this small project the following is enough.
+#![warn(missing_docs)]
+#![warn(warnings)]
+
+//! A payments engine that computes the state of client accounts from a stream of deposits, withdrawals, disputes,
+//! resolutions and chargebacks.
+//!
+//! Events are applied to a [`State`], either one by one with [`State::handle()`] or from an
+//! [`EventSource`](source::EventSource). [`rules`] can reject events before they are applied, and [`reporting`] derives
+//! reports from the result.
+//!
+//! ```
+//! use rust_decimal_macros::dec;
+//! use txh::{Event, State};
+//!
+//! let mut state = State::new();
+//! state.handle(Event::Deposit {
+//!     client: 1,
+//!     tx: 1,
+//!     amount: dec!(10),
+//!     counterparty: None,
+//!     related: None,
+//! })?;
+//! state.handle(Event::Withdrawal {
+//!     client: 1,
+//!     tx: 2,
+//!     amount: dec!(4),
+//!     counterparty: None,
+//!     related: None,
+//! })?;
+//!
+//! assert_eq!(
+//!     state.client_state(1).map(|client| client.available()),
+//!     Some(dec!(6))
+//! );
+//! # Ok::<(), txh::state::Error>(())
+//! ```
+
+pub mod blocklist;
+pub mod client;
+pub mod event;
+pub mod fees;
+pub mod graph;
+pub mod histogram;
+pub mod idmap;
+pub mod keyvalue;
+pub mod parallel;
+pub mod policy;
+pub mod precision;
+pub mod records;
+pub mod reporting;
+pub mod rules;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod selftest;
+pub mod snapshot;
+pub mod source;
+pub mod state;
+pub mod sweep;
+pub mod transaction;
+pub mod treasury;
+
+pub use client::ClientState;
+pub use event::Event;
+pub use state::State;
+
+/// Uniquely refers to a client.
+pub type ClientId = u32;
+/// Uniquely refers to a transaction.
+pub type TxId = u32;
+/// Marks consecutive events of the input stream as an atomic group, see [`State::handle_group()`].
+pub type GroupId = u64;
+/// Position of an event in the input stream, starting at zero.
+pub type EventIndex = u64;
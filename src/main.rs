@@ -4,60 +4,948 @@
 
 //! This tool can be used to retrieve the client status from a list of transactions in the form of a CSV file.
 
-mod client;
-mod event;
-mod records;
-mod state;
-mod transaction;
+mod cli;
+mod errors;
+mod follow;
+mod output;
+mod serve;
 
-use std::{env, fs::File, io};
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    env,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    net::TcpListener,
+    path::Path,
+    process::ExitCode,
+    rc::Rc,
+    thread,
+    time::Instant,
+};
 
 use anyhow::{Context as _, Result};
+use cli::{Args, Command, InputChecks, OnError, USAGE};
 use csv::WriterBuilder;
-use records::ClientCsvRecord;
-use state::State;
+use follow::Follow;
+use output::{Output, RecordWriter};
+use txh::{
+    blocklist::Blocklist,
+    client::ClientState,
+    fees::FeeSchedule,
+    graph,
+    histogram::Histograms,
+    idmap::IdMap,
+    parallel,
+    policy::Policy,
+    precision::{self, Rounding},
+    records::{ClientCsvRecord, DormantClientCsvRecord, FloatCsvRecord},
+    reporting::{self, LargeTransactionReport, RejectionReport, Summary},
+    rules::{Rule, Rules},
+    selftest,
+    source::{self, Chain, CsvSource, EventSource, Grouped, Groups, Lenient, Malformed, NdjsonSource},
+    state::{Limits, Outcome, Rejection},
+    sweep::Sweep,
+    ClientId, Event, EventIndex, State,
+};
 
-use self::{event::Event, records::EventCsvRecord};
+fn main() -> ExitCode {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let (format, result) = match cli::extract_errors_format(&mut args) {
+        Ok(format) => (format, run(args)),
+        Err(err) => (errors::Format::default(), Err(anyhow::Error::new(err).context(USAGE))),
+    };
 
-/// Uniquely refers to a client.
-type ClientId = u16;
-/// Uniquely refers to a transaction.
-type TxId = u32;
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            // There is nowhere left to report a failure to write to stderr.
+            let _ = errors::report(&err, format, io::stderr().lock());
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Runs the command described by `args`, which exclude the name of the binary.
+fn run(args: Vec<String>) -> Result<()> {
+    let args = match Command::parse(args) {
+        Ok(Command::Process(args)) => *args,
+        Ok(Command::CheckPolicy(path)) => {
+            let policy = load_policy(&path)?;
+            print!("{policy}");
+            return Ok(());
+        }
+        Ok(Command::SelfTest { events }) => {
+            let report = selftest::run(events).context("Self-test failed.")?;
+            println!(
+                "Self-test passed: {} events of {} clients in {:.3} s, {:.0} events/s.",
+                report.events,
+                report.clients,
+                report.elapsed.as_secs_f64(),
+                report.throughput()
+            );
+            return Ok(());
+        }
+        Ok(Command::ExportGraph { format, input, checks }) => {
+            let malformed = RefCell::new(Vec::new());
+            let source = check_source(checks, &malformed, open_input(&input, source::Format::Csv, None)?);
+            let state = process(source, &Rules::default(), None, State::new(), Reports::default(), None)?;
+            report_malformed(&malformed.into_inner());
+            graph::Graph::new(&state).write(format, io::stdout().lock())?;
+            return Ok(());
+        }
+        Ok(Command::Serve(args)) => {
+            // Like a run, the blocklist comes first.
+            let mut rules: Vec<Box<dyn Rule + Send>> = Vec::new();
+            if let Some(path) = &args.blocklist {
+                rules.push(Box::new(load_blocklist(path)?));
+            }
+            if let Some(path) = &args.policy {
+                rules.push(Box::new(load_policy(path)?));
+            }
+            let mut state = State::with_policy(args.on_duplicate);
+            state.set_limits(args.limits);
+            state.allow_admin_events(args.allow_admin_events);
+            let config = serve::Config {
+                format: args.format,
+                max_connections: args.max_connections,
+                input_precision: args.input_precision,
+                rounding: args.rounding,
+                state,
+                rules,
+            };
+            let listen = &args.listen;
+            let listener = TcpListener::bind(listen).context(format!("Failed to listen on `{listen}`."))?;
+            eprintln!("Listening on {}.", listener.local_addr()?);
+            serve::run(listener, config);
+            return Ok(());
+        }
+        Ok(Command::History { client, input, checks }) => {
+            let malformed = RefCell::new(Vec::new());
+            let source = check_source(checks, &malformed, open_input(&input, source::Format::Csv, None)?);
+            let state = process(
+                source,
+                &Rules::default(),
+                None,
+                State::with_journal(),
+                Reports::default(),
+                None,
+            )?;
+            report_malformed(&malformed.into_inner());
+            let mut wtr = WriterBuilder::new().has_headers(true).from_writer(io::stdout().lock());
+            for record in reporting::history(&state, client) {
+                wtr.serialize(record)?;
+            }
+            return Ok(());
+        }
+        Err(cli::Error::MissingInput) => {
+            println!("{USAGE}");
+            return Ok(());
+        }
+        Err(err) => return Err(err).context(USAGE),
+    };
+
+    let malformed = RefCell::new(Vec::new());
+    let id_map = match &args.id_map {
+        Some(path) => Some(Rc::new(RefCell::new(load_id_map(path)?))),
+        None => None,
+    };
 
-fn main() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("usage: txh <input_file>.csv");
+    if args.parse_only {
+        let start = Instant::now();
+        let events = count_parsed(open_source(&args, args.on_error, &malformed, id_map.as_ref())?)?;
+        let elapsed = start.elapsed().as_secs_f64();
+        println!(
+            "Parsed {events} events in {elapsed:.3} s, {:.0} events/s.",
+            events as f64 / elapsed
+        );
+        report_malformed(&malformed.into_inner());
         return Ok(());
     }
-    let filename = &args[1];
-    let file = File::open(filename).context(format!("Failed to open CSV: `{filename}`."))?;
 
-    let mut state = State::new();
+    let mut rules = Rules::default();
+    // The rules stop at the first one that rejects an event, so the blocklist comes first to count all of its hits.
+    let blocklist = match &args.blocklist {
+        Some(path) => {
+            let blocklist = Rc::new(load_blocklist(path)?);
+            rules.push(Box::new(Rc::clone(&blocklist)));
+            Some(blocklist)
+        }
+        None => None,
+    };
+    if let Some(path) = &args.rules {
+        rules.push(load_script(path)?);
+    }
+    if let Some(path) = &args.policy {
+        rules.push(Box::new(load_policy(path)?));
+    }
+    let fee_schedule = match &args.fee_schedule {
+        Some(path) => Some(load_fee_schedule(path, args.rounding)?),
+        None => None,
+    };
+    let sweep = match &args.sweep {
+        Some(path) => Some(load_sweep(path)?),
+        None => None,
+    };
 
-    // Read from CSV file
-    let mut rdr = csv::ReaderBuilder::new().has_headers(true).from_reader(file);
+    let mut large_transactions = match &args.large_tx_report {
+        Some((path, threshold)) => {
+            let file = File::create(path).context(format!("Failed to create large-transaction report: `{path}`."))?;
+            Some(LargeTransactionReport::new(*threshold, file))
+        }
+        None => None,
+    };
 
-    for record in rdr.deserialize() {
-        let record: EventCsvRecord = record?;
-        let event = Event::try_from(record)?;
+    let mut rejects = match &args.rejects {
+        Some(path) => {
+            let file = File::create(path).context(format!("Failed to create rejection report: `{path}`."))?;
+            Some(RejectionReport::new(file))
+        }
+        None => None,
+    };
+    let mut summary = args.summary.is_some().then(Summary::new);
 
-        state.handle(event)?;
+    if let Some(limit) = args.max_input_size {
+        for path in &args.inputs {
+            let size = std::fs::metadata(path)
+                .context(format!("Failed to open input: `{path}`."))?
+                .len();
+            if size > limit {
+                let path = path.clone();
+                return Err(errors::Error::InputTooLarge { path, size, limit }.into());
+            }
+        }
     }
 
-    // Output to stdout
-    let mut wtr = WriterBuilder::new().has_headers(true).from_writer(io::stdout());
-    for (&client, state) in state.client_states() {
-        let record = ClientCsvRecord {
-            client,
-            available: state.available(),
-            held: state.held(),
-            total: state.total(),
-            locked: state.frozen(),
+    let snapshots = args.snapshot.as_deref().map(|path| Snapshots {
+        path,
+        every: args.snapshot_every,
+    });
+
+    let initial_state = || -> Result<State> {
+        let mut state = match &args.snapshot {
+            // Without a snapshot, the run crashed before the first one was written.
+            Some(path) if args.resume && Path::new(path).exists() => {
+                let file = File::open(path).context(format!("Failed to open snapshot: `{path}`."))?;
+                let mut state =
+                    State::restore(BufReader::new(file)).context(format!("Failed to restore snapshot: `{path}`."))?;
+                state.set_limits(args.limits);
+                state.set_duplicate_policy(args.on_duplicate);
+                state
+            }
+            _ => {
+                let mut state = State::with_policy(args.on_duplicate);
+                state.set_limits(args.limits);
+                state
+            }
         };
+        state.allow_admin_events(args.allow_admin_events);
+        state.set_fee_schedule(fee_schedule);
+        if args.presize {
+            let (clients, transactions) = count_events(&args.inputs, args.limits, args.id_map.is_some())?;
+            state.reserve(clients, transactions);
+        }
+        Ok(state)
+    };
+
+    let state = match args.threads {
+        Some(threads) => {
+            let shards = parallel::process(open_source(&args, args.on_error, &malformed, id_map.as_ref())?, threads)?;
+            // This also detects transaction ids that were reused by clients of different shards.
+            shards.into_iter().collect::<Result<State, _>>()?
+        }
+        None if args.follow => {
+            let path = &args.inputs[0];
+            let mut follow = Follow::open(path).context(format!("Failed to open input: `{path}`."))?;
+            let mut events = follow::Events::new(args.input_format, id_map.as_ref());
+            let mut state = initial_state()?;
+            let mut emitted = state.next_index();
+            let mut last_emit = Instant::now();
+            // The run only ends with an error or when it is killed.
+            loop {
+                match follow
+                    .next_chunk(args.emit_every as usize)
+                    .context(format!("Failed to read input: `{path}`."))?
+                {
+                    Some(chunk) => {
+                        events.push(chunk);
+                        let reports = Reports {
+                            large_transactions: large_transactions.as_mut(),
+                            rejects: rejects.as_mut(),
+                            summary: summary.as_mut(),
+                        };
+                        let source = check_source(args.checks(args.on_error), &malformed, &mut events);
+                        state = process(source, &rules, sweep.as_ref(), state, reports, snapshots.as_ref())?;
+                        report_malformed(&malformed.take());
+                    }
+                    None => thread::sleep(follow::POLL_INTERVAL),
+                }
+                let events = state.next_index() - emitted;
+                if events > 0 && (events >= args.emit_every || last_emit.elapsed() >= args.emit_interval) {
+                    write_clients(&args, &state)?;
+                    if let Some(report) = &mut large_transactions {
+                        report.flush()?;
+                    }
+                    if let Some(report) = &mut rejects {
+                        report.flush()?;
+                    }
+                    emitted = state.next_index();
+                    last_emit = Instant::now();
+                }
+            }
+        }
+        None => {
+            let state = initial_state()?;
+            let mut source = open_source(&args, args.on_error, &malformed, id_map.as_ref())?;
+            // A restored state has already handled the first events of the inputs.
+            let resumed = state.next_index();
+            if !skip_events(&mut source, resumed)? {
+                let path = args.snapshot.clone().unwrap_or_default();
+                return Err(errors::Error::SnapshotAheadOfInput { path, events: resumed }.into());
+            }
+            let reports = Reports {
+                large_transactions: large_transactions.as_mut(),
+                rejects: rejects.as_mut(),
+                summary: summary.as_mut(),
+            };
+            let state = process(source, &rules, sweep.as_ref(), state, reports, snapshots.as_ref())?;
+            if let Some(snapshots) = &snapshots {
+                snapshots.write(&state)?;
+            }
+            state
+        }
+    };
+
+    report_malformed(&malformed.into_inner());
+
+    if let (Some(path), Some(id_map)) = (&args.id_map, &id_map) {
+        save_id_map(path, &id_map.borrow())?;
+    }
+
+    if let Some(blocklist) = blocklist {
+        let hits = blocklist.hits();
+        eprintln!(
+            "Blocklist rejected {} event(s) of blocked clients and {} event(s) with blocked counterparties.",
+            hits.clients, hits.counterparties
+        );
+    }
+
+    if let Some(schedule) = fee_schedule {
+        let fees = state.fees_collected();
+        eprintln!(
+            "Collected {} in fees from {} withdrawal(s) into client {}, rounding kept {} of the exact fees.",
+            fees.collected, fees.withdrawals, schedule.account, fees.remainder
+        );
+    }
+
+    if args.memory_report {
+        let usage = state.memory_usage();
+        eprintln!(
+            "Approximate memory usage: {} bytes for clients, {} bytes for transactions, {} bytes for the client index, \
+             {} bytes in total.",
+            usage.clients,
+            usage.transactions,
+            usage.client_index,
+            usage.total()
+        );
+    }
+
+    if args.histograms {
+        eprint!("{}", Histograms::new(&state));
+    }
+
+    if args.determinism_check {
+        // The malformed records of the second run have already been reported.
+        let on_error = match args.on_error {
+            OnError::Abort => OnError::Abort,
+            OnError::Skip | OnError::Collect => OnError::Collect,
+        };
+        let ignored = RefCell::new(Vec::new());
+        let first: Vec<_> = client_records(state.client_states_sorted(), args.rounding).collect();
+        let second = process(
+            open_source(&args, on_error, &ignored, id_map.as_ref())?,
+            &rules,
+            sweep.as_ref(),
+            initial_state()?,
+            Reports::default(),
+            None,
+        )?;
+        let second: Vec<_> = client_records(second.client_states_sorted(), args.rounding).collect();
+        if first != second {
+            return Err(errors::Error::DeterminismCheckFailed(args.inputs.join(" ")).into());
+        }
+    }
+
+    write_clients(&args, &state)?;
+
+    if let Some((path, idle)) = &args.dormancy_report {
+        let mut wtr = WriterBuilder::new()
+            .has_headers(true)
+            .from_path(path)
+            .context(format!("Failed to create dormancy report: `{path}`."))?;
+        for (client, state, last_activity) in state.dormant_clients(*idle) {
+            wtr.serialize(DormantClientCsvRecord {
+                client,
+                total: state.total(),
+                last_activity,
+            })?;
+        }
+    }
+
+    if let Some(path) = &args.counterparty_report {
+        let mut wtr = WriterBuilder::new()
+            .has_headers(true)
+            .from_path(path)
+            .context(format!("Failed to create counterparty report: `{path}`."))?;
+        for record in reporting::counterparty_report(&state) {
+            wtr.serialize(record)?;
+        }
+    }
+
+    if let Some(path) = &args.ledger_out {
+        let mut wtr = WriterBuilder::new()
+            .has_headers(true)
+            .from_path(path)
+            .context(format!("Failed to create ledger: `{path}`."))?;
+        for record in reporting::ledger(&state) {
+            wtr.serialize(record)?;
+        }
+    }
+
+    if let Some(path) = &args.open_disputes_report {
+        let mut wtr = WriterBuilder::new()
+            .has_headers(true)
+            .from_path(path)
+            .context(format!("Failed to create open disputes report: `{path}`."))?;
+        for record in reporting::open_disputes(&state) {
+            wtr.serialize(record)?;
+        }
+    }
 
-        wtr.serialize(record)?;
+    if let Some(path) = &args.frozen_report {
+        let mut wtr = WriterBuilder::new()
+            .has_headers(true)
+            .from_path(path)
+            .context(format!("Failed to create frozen clients report: `{path}`."))?;
+        for record in reporting::frozen_clients(&state) {
+            wtr.serialize(record)?;
+        }
     }
 
+    if let (Some(path), Some(summary)) = (&args.summary, summary) {
+        let mut wtr = WriterBuilder::new()
+            .has_headers(true)
+            .from_path(path)
+            .context(format!("Failed to create summary: `{path}`."))?;
+        wtr.serialize(summary.finish(&state))?;
+    }
+
+    if let Some(path) = &args.float_report {
+        let mut wtr = WriterBuilder::new()
+            .has_headers(true)
+            .from_path(path)
+            .context(format!("Failed to create float report: `{path}`."))?;
+        let float = state.float();
+        wtr.serialize(FloatCsvRecord {
+            deposited: float.deposited(),
+            withdrawn: float.withdrawn(),
+            reversed: float.reversed(),
+            charged_back: float.charged_back(),
+            balance: float.balance(),
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Loads and validates the policy file at `path`.
+fn load_policy(path: &str) -> Result<Policy> {
+    let source = std::fs::read_to_string(path).context(format!("Failed to read policy: `{path}`."))?;
+    source.parse().context(format!("Invalid policy: `{path}`."))
+}
+
+/// Loads the blocklist at `path`.
+fn load_blocklist(path: &str) -> Result<Blocklist> {
+    let source = std::fs::read_to_string(path).context(format!("Failed to read blocklist: `{path}`."))?;
+    source.parse().context(format!("Invalid blocklist: `{path}`."))
+}
+
+/// Loads the fee schedule at `path`, with fees rounded like the output.
+fn load_fee_schedule(path: &str, rounding: Rounding) -> Result<FeeSchedule> {
+    let source = std::fs::read_to_string(path).context(format!("Failed to read fee schedule: `{path}`."))?;
+    let schedule: FeeSchedule = source.parse().context(format!("Invalid fee schedule: `{path}`."))?;
+    Ok(FeeSchedule { rounding, ..schedule })
+}
+
+/// Loads and validates the sweep at `path`.
+fn load_sweep(path: &str) -> Result<Sweep> {
+    let source = std::fs::read_to_string(path).context(format!("Failed to read sweep: `{path}`."))?;
+    source.parse().context(format!("Invalid sweep: `{path}`."))
+}
+
+/// Loads the mapping of external client identifiers at `path`, or starts an empty one if the file doesn't exist yet.
+fn load_id_map(path: &str) -> Result<IdMap> {
+    if !Path::new(path).exists() {
+        return Ok(IdMap::new());
+    }
+    let file = File::open(path).context(format!("Failed to open id map: `{path}`."))?;
+    IdMap::load(BufReader::new(file)).context(format!("Invalid id map: `{path}`."))
+}
+
+/// Writes `id_map` to a temporary file that replaces the mapping at `path`.
+fn save_id_map(path: &str, id_map: &IdMap) -> Result<()> {
+    let tmp = format!("{path}.tmp");
+    let file = File::create(&tmp).context(format!("Failed to create id map: `{tmp}`."))?;
+    id_map
+        .save(BufWriter::new(file))
+        .context(format!("Failed to write id map: `{tmp}`."))?;
+    fs::rename(&tmp, path).context(format!("Failed to replace id map: `{path}`."))?;
     Ok(())
 }
+
+/// Loads the client states from the output of a previous run at `path`.
+fn load_previous_output(path: &str) -> Result<Vec<ClientCsvRecord>> {
+    let mut rdr = csv::Reader::from_path(path).context(format!("Failed to open previous output: `{path}`."))?;
+    rdr.deserialize()
+        .collect::<Result<_, _>>()
+        .context(format!("Invalid previous output: `{path}`."))
+}
+
+/// Loads a rule script from the file at `path`.
+#[cfg(feature = "scripting")]
+fn load_script(path: &str) -> Result<Box<dyn Rule>> {
+    let source = std::fs::read_to_string(path).context(format!("Failed to read rules: `{path}`."))?;
+    Ok(Box::new(txh::script::ScriptRule::compile(&source)?))
+}
+
+#[cfg(not(feature = "scripting"))]
+fn load_script(_path: &str) -> Result<Box<dyn Rule>> {
+    anyhow::bail!("Rule scripts require txh to be built with the `scripting` feature.")
+}
+
+/// Opens the file at `filename`, or stdin for `-`, as a source of events in the given `format`.
+///
+/// If an `id_map` is given, the clients of CSV inputs are external identifiers that are mapped with it.
+fn open_input(
+    filename: &str,
+    format: source::Format,
+    id_map: Option<&Rc<RefCell<IdMap>>>,
+) -> Result<Box<dyn EventSource>> {
+    let reader: Box<dyn io::Read> = match filename {
+        "-" => Box::new(io::stdin().lock()),
+        _ => open_file(filename)?,
+    };
+    Ok(read_source(reader, format, id_map))
+}
+
+/// Reads events in the given `format` from `reader`.
+fn read_source(
+    reader: Box<dyn io::Read>,
+    format: source::Format,
+    id_map: Option<&Rc<RefCell<IdMap>>>,
+) -> Box<dyn EventSource> {
+    match format {
+        source::Format::Csv => match id_map {
+            Some(id_map) => Box::new(CsvSource::with_id_map(reader, Rc::clone(id_map))),
+            None => Box::new(CsvSource::new(reader)),
+        },
+        source::Format::Ndjson => Box::new(NdjsonSource::new(io::BufReader::new(reader))),
+    }
+}
+
+/// Opens the input file at `filename`, which is decompressed on the fly if it ends with `.gz` or `.zst`.
+fn open_file(filename: &str) -> Result<Box<dyn io::Read>> {
+    let file = File::open(filename).context(format!("Failed to open input: `{filename}`."))?;
+    let extension = Path::new(filename).extension().and_then(|extension| extension.to_str());
+    Ok(match extension {
+        // Multiple members are common for logs that are compressed in chunks and concatenated.
+        #[cfg(feature = "compression")]
+        Some("gz") => Box::new(flate2::read::MultiGzDecoder::new(BufReader::new(file))),
+        #[cfg(feature = "compression")]
+        Some("zst") => Box::new(zstd::Decoder::new(file).context(format!("Failed to open input: `{filename}`."))?),
+        #[cfg(not(feature = "compression"))]
+        Some("gz" | "zst") => {
+            anyhow::bail!("Compressed input requires txh to be built with the `compression` feature.")
+        }
+        _ => Box::new(file),
+    })
+}
+
+/// Opens all `filenames` as a single source that yields their events in order.
+fn open_inputs(
+    filenames: &[String],
+    format: source::Format,
+    id_map: Option<&Rc<RefCell<IdMap>>>,
+) -> Result<Chain<Box<dyn EventSource>>> {
+    let sources = filenames
+        .iter()
+        .map(|filename| open_input(filename, format, id_map))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Chain::new(sources))
+}
+
+/// Opens the inputs of `args` like [`open_inputs()`] and handles their malformed records according to `on_error`.
+///
+/// With [`OnError::Collect`], the malformed records are appended to `malformed`.
+fn open_source<'a>(
+    args: &Args,
+    on_error: OnError,
+    malformed: &'a RefCell<Vec<Malformed>>,
+    id_map: Option<&Rc<RefCell<IdMap>>>,
+) -> Result<Box<dyn EventSource + 'a>> {
+    let inputs = open_inputs(&args.inputs, args.input_format, id_map)?;
+    Ok(check_source(args.checks(on_error), malformed, inputs))
+}
+
+/// Checks the precision of the events of `inputs` and handles their malformed records according to `checks`, see
+/// [`open_source()`].
+fn check_source<'a>(
+    checks: InputChecks,
+    malformed: &'a RefCell<Vec<Malformed>>,
+    inputs: impl EventSource + 'a,
+) -> Box<dyn EventSource + 'a> {
+    let inputs = precision::Checked::new(inputs, checks.input_precision, checks.rounding);
+    match checks.on_error {
+        OnError::Abort => Box::new(inputs),
+        OnError::Skip => Box::new(Lenient::new(inputs, |record: Malformed| {
+            eprintln!("Warning: skipped malformed record: {record}");
+        })),
+        OnError::Collect => Box::new(Lenient::new(inputs, |record| malformed.borrow_mut().push(record))),
+    }
+}
+
+/// Prints the collected `malformed` records to stderr.
+fn report_malformed(malformed: &[Malformed]) {
+    if malformed.is_empty() {
+        return;
+    }
+    eprintln!("Skipped {} malformed record(s):", malformed.len());
+    for record in malformed {
+        eprintln!("  {record}");
+    }
+}
+
+/// Where and how often [`process()`] writes snapshots of the state.
+struct Snapshots<'a> {
+    path: &'a str,
+    every: u64,
+}
+
+impl Snapshots<'_> {
+    /// Writes `state` to a temporary file that replaces the snapshot, so that a crash while writing keeps the previous
+    /// snapshot intact.
+    fn write(&self, state: &State) -> Result<()> {
+        let path = self.path;
+        let tmp = format!("{path}.tmp");
+        let file = File::create(&tmp).context(format!("Failed to create snapshot: `{tmp}`."))?;
+        state
+            .snapshot(BufWriter::new(file))
+            .context(format!("Failed to write snapshot: `{tmp}`."))?;
+        fs::rename(&tmp, path).context(format!("Failed to replace snapshot: `{path}`."))?;
+        Ok(())
+    }
+}
+
+/// Reads and validates all events of `source` without applying them, and returns their number.
+fn count_parsed(mut source: impl EventSource) -> Result<u64> {
+    let mut events = 0;
+    while let Some(event) = source.next_event() {
+        event?;
+        events += 1;
+    }
+    Ok(events)
+}
+
+/// Skips the first `count` events of `source`, and returns `false` if it ends before.
+fn skip_events(source: &mut impl EventSource, count: EventIndex) -> Result<bool> {
+    for _ in 0..count {
+        if source.next_event().transpose()?.is_none() {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// The reports that [`process()`] writes while the events are handled.
+#[derive(Default)]
+struct Reports<'a> {
+    /// Applied transactions above the threshold.
+    large_transactions: Option<&'a mut LargeTransactionReport<File>>,
+    /// Events that are rejected by the rules or the state.
+    rejects: Option<&'a mut RejectionReport<File>>,
+    /// Counts of all events.
+    summary: Option<&'a mut Summary>,
+}
+
+/// Reads all events from `source` and applies the ones accepted by `rules` to `state`.
+///
+/// If a `sweep` is given, each client that an event credits is swept right after the event, and the sweep is handled
+/// like an event of the input that shares the index of the event. Events are written to the `reports` as they are
+/// handled. If `snapshots` are given, the state is written to them every `snapshots.every` events, but never within an
+/// atomic group or before its sweeps.
+fn process(
+    source: impl EventSource,
+    rules: &Rules,
+    sweep: Option<&Sweep>,
+    mut state: State,
+    mut reports: Reports,
+    snapshots: Option<&Snapshots>,
+) -> Result<State> {
+    let mut source = Groups::new(source);
+    while let Some(grouped) = source.next_grouped() {
+        let grouped = grouped?;
+        let first = state.next_index();
+        // Clients that have to be checked for a sweep once the events are handled.
+        let credited: Vec<_> = match sweep {
+            Some(_) => {
+                let events = match &grouped {
+                    Grouped::Single(event) => std::slice::from_ref(event),
+                    Grouped::Group(events) => events.as_slice(),
+                    // Nothing of an incomplete group is applied.
+                    Grouped::Incomplete(_) => &[],
+                };
+                events
+                    .iter()
+                    .filter_map(|event| Sweep::candidate(&state, event))
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+
+        match grouped {
+            Grouped::Single(event) => {
+                handle_event(rules, &mut state, &mut reports, event, false)?;
+            }
+            Grouped::Group(events) => {
+                handle_group(rules, &mut state, &mut reports, events, false)?;
+            }
+            Grouped::Incomplete(events) => {
+                handle_group(rules, &mut state, &mut reports, events, true)?;
+            }
+        }
+
+        for client in credited {
+            if let Some(event) = sweep.and_then(|sweep| sweep.trigger(&state, client)) {
+                handle_event(rules, &mut state, &mut reports, event, true)?;
+            }
+        }
+
+        if let Some(snapshots) = snapshots {
+            if state.next_index() / snapshots.every > first / snapshots.every {
+                snapshots.write(&state)?;
+            }
+        }
+    }
+
+    Ok(state)
+}
+
+/// Applies the `events` of an atomic group for [`process()`] if `rules` accept all of them, and writes them to the
+/// `reports`.
+///
+/// An `incomplete` group, which lost a malformed record, is rejected as a whole without evaluating the rules. Returns
+/// the outcomes of the events.
+fn handle_group(
+    rules: &Rules,
+    state: &mut State,
+    reports: &mut Reports,
+    events: Vec<Event>,
+    incomplete: bool,
+) -> Result<Vec<Outcome>> {
+    let first = state.next_index();
+    // A rule that rejects one event rejects the whole group.
+    let mut ruled = None;
+    if !incomplete {
+        for (offset, event) in events.iter().enumerate() {
+            if rules.rejects(event, state)? {
+                ruled = Some(offset);
+                break;
+            }
+        }
+    }
+    // Groups are small, and the events are needed again if they are rejected.
+    let copies = events.clone();
+    let outcomes = match (incomplete, ruled, &mut reports.large_transactions) {
+        (true, _, _) => (0..events.len())
+            .map(|_| {
+                state.skip();
+                Outcome::Rejected(Rejection::GroupIncomplete)
+            })
+            .collect(),
+        (false, Some(ruled), _) => (0..events.len())
+            .map(|offset| {
+                state.skip();
+                Outcome::Rejected(match offset == ruled {
+                    true => Rejection::Rule,
+                    false => Rejection::GroupRejected,
+                })
+            })
+            .collect(),
+        (false, None, Some(report)) => report.handle_group(state, events)?,
+        (false, None, None) => state.handle_group(events)?,
+    };
+    for ((index, event), &outcome) in (first..).zip(&copies).zip(&outcomes) {
+        if let Some(summary) = &mut reports.summary {
+            summary.add(event, outcome);
+        }
+        let Outcome::Rejected(rejection) = outcome else {
+            continue;
+        };
+        if let (Rejection::DuplicateTxId, Some(tx)) = (rejection, event.tx()) {
+            eprintln!("Warning: skipped event {index}, which reuses the transaction id `{tx}`.");
+        }
+        if let Some(report) = &mut reports.rejects {
+            report.write(index, event, rejection)?;
+        }
+    }
+    Ok(outcomes)
+}
+
+/// Applies a single `event` for [`process()`] if `rules` accept it, writes it to the `reports` and returns its outcome.
+///
+/// A `synthetic` event is handled with [`State::handle_synthetic()`], so it doesn't take up an index of the input
+/// stream even if it is rejected.
+fn handle_event(
+    rules: &Rules,
+    state: &mut State,
+    reports: &mut Reports,
+    event: Event,
+    synthetic: bool,
+) -> Result<Outcome> {
+    let index = match synthetic {
+        true => state.next_index().saturating_sub(1),
+        false => state.next_index(),
+    };
+    if rules.rejects(&event, state)? {
+        if !synthetic {
+            state.skip();
+        }
+        if let Some(report) = &mut reports.rejects {
+            report.write(index, &event, Rejection::Rule)?;
+        }
+        if let Some(summary) = &mut reports.summary {
+            summary.add(&event, Outcome::Rejected(Rejection::Rule));
+        }
+        return Ok(Outcome::Rejected(Rejection::Rule));
+    }
+
+    let tx = event.tx();
+    // The event is only needed again if it is rejected or counted.
+    let copy = (reports.rejects.is_some() || reports.summary.is_some()).then(|| event.clone());
+    let outcome = match (&mut reports.large_transactions, synthetic) {
+        (Some(report), false) => report.handle(state, event)?,
+        (Some(report), true) => report.handle_synthetic(state, event)?,
+        (None, false) => state.handle(event)?,
+        (None, true) => state.handle_synthetic(event)?,
+    };
+    if let (Outcome::Rejected(Rejection::DuplicateTxId), Some(tx)) = (outcome, tx) {
+        eprintln!("Warning: skipped event {index}, which reuses the transaction id `{tx}`.");
+    }
+    if let (Some(report), Some(event), Outcome::Rejected(rejection)) = (&mut reports.rejects, &copy, outcome) {
+        report.write(index, event, rejection)?;
+    }
+    if let (Some(summary), Some(event)) = (&mut reports.summary, &copy) {
+        summary.add(event, outcome);
+    }
+    Ok(outcome)
+}
+
+/// Quickly counts the distinct clients and the deposits, withdrawals and transfers in the CSV files at `filenames`,
+/// without validating the events.
+///
+/// The clients are the ones in the `client` and `to` columns. With an `id_map`, these hold external ids, which the map
+/// turns into distinct clients, so they are counted as they are. The counts stop at the `limits`, so that counting
+/// doesn't allocate the memory that the limits protect either, and reading stops once both are reached.
+fn count_events(filenames: &[String], limits: Limits, id_map: bool) -> Result<(usize, usize)> {
+    let max_clients = limits.max_clients.unwrap_or(usize::MAX);
+    let max_transactions = limits.max_transactions.unwrap_or(usize::MAX);
+    let mut clients = HashSet::new();
+    let mut transactions = 0;
+
+    'files: for filename in filenames {
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .from_reader(open_file(filename)?);
+        let headers = rdr.byte_headers()?;
+        let column = |name: &str| headers.iter().position(|header| header == name.as_bytes());
+        let (Some(ty), Some(client)) = (column("type"), column("client")) else {
+            // Processing reports the missing columns.
+            continue;
+        };
+        let to = column("to");
+
+        let mut record = csv::ByteRecord::new();
+        while rdr.read_byte_record(&mut record)? {
+            for column in [Some(client), to].into_iter().flatten() {
+                let Some(id) = record.get(column).and_then(|id| std::str::from_utf8(id).ok()) else {
+                    continue;
+                };
+                // Processing rejects ids that aren't in their canonical spelling, so equal ids are equal strings.
+                let id = id.trim();
+                let valid = match id_map {
+                    true => !id.is_empty(),
+                    false => id.parse::<ClientId>().is_ok(),
+                };
+                if valid && clients.len() < max_clients && !clients.contains(id) {
+                    clients.insert(id.to_owned());
+                }
+            }
+            if matches!(record.get(ty), Some(b"deposit" | b"withdrawal" | b"transfer")) {
+                transactions = (transactions + 1).min(max_transactions);
+            }
+            if clients.len() >= max_clients && transactions >= max_transactions {
+                break 'files;
+            }
+        }
+    }
+
+    Ok((clients.len(), transactions))
+}
+
+/// Writes the client states of `state` with [`write_output()`], ordered by client if `--sorted` is given.
+fn write_clients(args: &Args, state: &State) -> Result<()> {
+    let clients: Box<dyn Iterator<Item = _>> = if args.sorted {
+        Box::new(state.client_states_sorted())
+    } else {
+        Box::new(state.client_states())
+    };
+    write_output(args, client_records(clients, args.rounding))
+}
+
+/// Writes the client states to stdout, or only the changes to `--diff-against`.
+fn write_output(args: &Args, records: impl Iterator<Item = ClientCsvRecord>) -> Result<()> {
+    let stdout = Output::new(io::stdout().lock(), args.output_buffer_size, args.output_compression)
+        .context("Failed to set up output.")?;
+    let mut wtr = RecordWriter::new(args.output_format, args.stream, stdout);
+    match &args.diff_against {
+        Some(path) => {
+            let previous = load_previous_output(path)?;
+            for record in reporting::client_diff(previous, records) {
+                wtr.write(record)?;
+            }
+        }
+        None => {
+            for record in records {
+                wtr.write(record)?;
+            }
+        }
+    }
+    wtr.finish()?.finish().context("Failed to write output.")?;
+    Ok(())
+}
+
+/// Returns the output rows of `clients`, with balances rounded with `rounding`.
+fn client_records<'a>(
+    clients: impl Iterator<Item = (&'a ClientId, &'a ClientState)> + 'a,
+    rounding: Rounding,
+) -> impl Iterator<Item = ClientCsvRecord> + 'a {
+    clients.map(move |(&client, state)| ClientCsvRecord {
+        client,
+        available: rounding.round(state.available()),
+        held: rounding.round(state.held()),
+        total: rounding.round(state.total()),
+        locked: state.frozen(),
+        lock_reason: state.freeze().map(|freeze| freeze.reason.to_string()),
+        locked_at: state.freeze().map(|freeze| freeze.at),
+    })
+}
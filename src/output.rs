@@ -0,0 +1,301 @@
+//! Buffered and optionally compressed writing of large outputs in different formats.
+
+use std::{
+    io::{self, BufWriter, Write},
+    str::FromStr,
+};
+
+use serde::Serialize;
+use thiserror::Error;
+
+/// Errors that can happen while writing records.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// The default size of the buffer in front of the output, in bytes.
+pub const DEFAULT_BUFFER_SIZE: usize = 1 << 20;
+
+/// The supported compression formats for the output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    /// A single zstd frame with the default compression level.
+    Zstd,
+}
+
+impl FromStr for Compression {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The supported formats for records.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// CSV with a header row.
+    #[default]
+    Csv,
+    /// A JSON array of objects, or one object per line if streamed.
+    Json,
+    /// A table with aligned columns for humans.
+    Table,
+}
+
+impl FromStr for Format {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "table" => Ok(Format::Table),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Writes serializable records to a writer in one of the supported [`Format`]s.
+///
+/// [`RecordWriter::finish`] must be called once all records are written.
+pub enum RecordWriter<W: Write> {
+    /// See [`Format::Csv`].
+    Csv(csv::Writer<W>),
+    /// See [`Format::Json`], `stream` selects one object per line and `empty` is `true` until the first record.
+    Json { writer: W, stream: bool, empty: bool },
+    /// See [`Format::Table`], the rows are collected as CSV because the widths of the columns depend on all of them.
+    Table { rows: csv::Writer<Vec<u8>>, writer: W },
+}
+
+impl<W: Write> RecordWriter<W> {
+    /// Creates a writer of records in the given `format`, `stream` only affects JSON.
+    pub fn new(format: Format, stream: bool, writer: W) -> Self {
+        match format {
+            Format::Csv => RecordWriter::Csv(csv::Writer::from_writer(writer)),
+            Format::Json => RecordWriter::Json {
+                writer,
+                stream,
+                empty: true,
+            },
+            Format::Table => RecordWriter::Table {
+                rows: csv::Writer::from_writer(Vec::new()),
+                writer,
+            },
+        }
+    }
+
+    /// Writes a single record.
+    pub fn write(&mut self, record: impl Serialize) -> Result<(), Error> {
+        match self {
+            RecordWriter::Csv(writer) => writer.serialize(record)?,
+            RecordWriter::Table { rows, .. } => rows.serialize(record)?,
+            RecordWriter::Json { writer, stream, empty } => {
+                match (*stream, *empty) {
+                    (true, _) => {}
+                    (false, true) => writer.write_all(b"[\n")?,
+                    (false, false) => writer.write_all(b",\n")?,
+                }
+                serde_json::to_writer(&mut *writer, &record)?;
+                if *stream {
+                    writer.write_all(b"\n")?;
+                }
+                *empty = false;
+            }
+        }
+        Ok(())
+    }
+
+    /// Completes the output and returns the underlying writer.
+    pub fn finish(self) -> Result<W, Error> {
+        match self {
+            RecordWriter::Csv(writer) => writer.into_inner().map_err(|err| into_io_error(err.error())),
+            RecordWriter::Json {
+                mut writer,
+                stream,
+                empty,
+            } => {
+                match (stream, empty) {
+                    (true, _) => {}
+                    (false, true) => writer.write_all(b"[]\n")?,
+                    (false, false) => writer.write_all(b"\n]\n")?,
+                }
+                Ok(writer)
+            }
+            RecordWriter::Table { rows, mut writer } => {
+                let rows = rows.into_inner().map_err(|err| into_io_error(err.error()))?;
+                let rows = csv::ReaderBuilder::new()
+                    .has_headers(false)
+                    .from_reader(rows.as_slice())
+                    .into_records()
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let mut widths = Vec::new();
+                for row in &rows {
+                    widths.resize(widths.len().max(row.len()), 0);
+                    for (width, field) in widths.iter_mut().zip(row) {
+                        *width = field.chars().count().max(*width);
+                    }
+                }
+                for row in &rows {
+                    let line: Vec<_> = row
+                        .iter()
+                        .zip(&widths)
+                        .map(|(field, width)| format!("{field:<width$}"))
+                        .collect();
+                    writeln!(writer, "{}", line.join("  ").trim_end())?;
+                }
+                Ok(writer)
+            }
+        }
+    }
+}
+
+fn into_io_error(err: &io::Error) -> Error {
+    Error::Io(io::Error::new(err.kind(), err.to_string()))
+}
+
+/// A writer that passes data on to the underlying writer in chunks of a fixed size, compressing it if requested.
+///
+/// [`Output::finish`] must be called once everything is written, otherwise the output may be incomplete.
+pub enum Output<W: Write> {
+    /// Uncompressed output.
+    Plain(BufWriter<W>),
+    /// Output compressed with zstd.
+    #[cfg(feature = "compression")]
+    Zstd(zstd::Encoder<'static, BufWriter<W>>),
+}
+
+impl<W: Write> Output<W> {
+    /// Wraps `writer` with a buffer of `buffer_size` bytes and the given `compression`.
+    pub fn new(writer: W, buffer_size: usize, compression: Option<Compression>) -> io::Result<Self> {
+        let writer = BufWriter::with_capacity(buffer_size, writer);
+        match compression {
+            None => Ok(Output::Plain(writer)),
+            #[cfg(feature = "compression")]
+            Some(Compression::Zstd) => Ok(Output::Zstd(zstd::Encoder::new(writer, 0)?)),
+            #[cfg(not(feature = "compression"))]
+            Some(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "compressed output requires txh to be built with the `compression` feature",
+            )),
+        }
+    }
+
+    /// Completes the compressed stream, if any, and flushes everything to the underlying writer.
+    #[cfg_attr(not(feature = "compression"), allow(clippy::infallible_destructuring_match))]
+    pub fn finish(self) -> io::Result<()> {
+        let writer = match self {
+            Output::Plain(writer) => writer,
+            #[cfg(feature = "compression")]
+            Output::Zstd(encoder) => encoder.finish()?,
+        };
+        writer.into_inner().map(drop).map_err(io::IntoInnerError::into_error)
+    }
+}
+
+impl<W: Write> Write for Output<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(writer) => writer.write(buf),
+            #[cfg(feature = "compression")]
+            Output::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(writer) => writer.flush(),
+            #[cfg(feature = "compression")]
+            Output::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn plain() -> io::Result<()> {
+        let mut buffer = Vec::new();
+        let mut output = Output::new(&mut buffer, 4, None)?;
+        output.write_all(b"client,available\n1,2\n")?;
+        output.finish()?;
+        assert_eq!(buffer, b"client,available\n1,2\n");
+
+        Ok(())
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn zstd() -> io::Result<()> {
+        let mut compressed = Vec::new();
+        let mut output = Output::new(&mut compressed, 4, Some(Compression::Zstd))?;
+        output.write_all(b"client,available\n1,2\n")?;
+        output.finish()?;
+        assert_eq!(zstd::decode_all(compressed.as_slice())?, b"client,available\n1,2\n");
+
+        Ok(())
+    }
+
+    #[derive(Serialize)]
+    struct Record {
+        client: u16,
+        available: &'static str,
+        reason: Option<&'static str>,
+    }
+
+    fn write(format: Format, stream: bool) -> Result<String, Box<dyn std::error::Error>> {
+        let mut writer = RecordWriter::new(format, stream, Vec::new());
+        for (client, available, reason) in [(1, "10.5", None), (200, "0", Some("chargeback"))] {
+            writer.write(Record {
+                client,
+                available,
+                reason,
+            })?;
+        }
+        Ok(String::from_utf8(writer.finish()?)?)
+    }
+
+    #[test]
+    fn formats() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(
+            write(Format::Csv, false)?,
+            "client,available,reason\n1,10.5,\n200,0,chargeback\n"
+        );
+
+        let expected = r#"[
+{"client":1,"available":"10.5","reason":null},
+{"client":200,"available":"0","reason":"chargeback"}
+]
+"#;
+        assert_eq!(write(Format::Json, false)?, expected);
+
+        let expected = r#"{"client":1,"available":"10.5","reason":null}
+{"client":200,"available":"0","reason":"chargeback"}
+"#;
+        assert_eq!(write(Format::Json, true)?, expected);
+
+        let expected = "\
+client  available  reason
+1       10.5
+200     0          chargeback
+";
+        assert_eq!(write(Format::Table, false)?, expected);
+
+        let writer = RecordWriter::new(Format::Json, false, Vec::new());
+        assert_eq!(writer.finish()?, b"[]\n");
+
+        Ok(())
+    }
+}
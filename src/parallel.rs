@@ -0,0 +1,150 @@
+//! Processing of an input stream by several threads, each of which owns the clients of one shard.
+//!
+//! Events of different clients are independent, so the current thread only parses the input and routes every event by
+//! its client id to a worker thread that applies it to the [`State`] of its shard. The shards together hold the same
+//! client states as a sequential run, with two exceptions: a transaction id that is reused by clients of different
+//! shards is only detected as a duplicate when the shards are merged with [`State::merge()`], and an event that refers
+//! to a transaction of a client in another shard is rejected as an unknown transaction instead of a client mismatch.
+//! Transfers and atomic groups can change the clients of several shards, so inputs that contain them are refused.
+
+use std::{
+    num::NonZeroUsize,
+    panic,
+    sync::mpsc::{self, SyncSender},
+    thread,
+};
+
+use thiserror::Error;
+
+use crate::{
+    event::Event,
+    source::{self, EventSource},
+    state::{self, State},
+    EventIndex, GroupId, TxId,
+};
+
+/// The number of events that can be queued for each worker before the reader waits.
+const CHANNEL_CAPACITY: usize = 4096;
+
+/// Errors that can happen during parallel processing.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The input could not be read.
+    #[error(transparent)]
+    Source(#[from] source::Error),
+    /// An event could not be applied.
+    #[error(transparent)]
+    State(#[from] state::Error),
+    /// The input contains a transfer, which changes the clients of two shards.
+    #[error("transfer `{0}` can't be processed by several threads")]
+    Transfer(TxId),
+    /// The input contains an atomic group, whose events can belong to clients of several shards.
+    #[error("atomic group `{0}` can't be processed by several threads")]
+    Group(GroupId),
+}
+
+/// Reads all events from `source` and applies them to `shards` states on as many worker threads.
+///
+/// The events of a client are applied to the state at index `client % shards`, with their index in the input stream.
+pub fn process(mut source: impl EventSource, shards: NonZeroUsize) -> Result<Vec<State>, Error> {
+    thread::scope(|scope| {
+        let (senders, workers): (Vec<SyncSender<(EventIndex, Event)>>, Vec<_>) = (0..shards.get())
+            .map(|_| {
+                let (sender, receiver) = mpsc::sync_channel(CHANNEL_CAPACITY);
+                let worker = scope.spawn(move || -> Result<State, state::Error> {
+                    let mut state = State::new();
+                    for (index, event) in receiver {
+                        state.handle_indexed(index, event)?;
+                    }
+                    Ok(state)
+                });
+                (sender, worker)
+            })
+            .unzip();
+
+        let mut read = Ok(());
+        let mut index = 0;
+        while let Some(event) = source.next_event() {
+            let event = match event {
+                Ok(Event::Transfer { tx, .. }) => {
+                    read = Err(Error::Transfer(tx));
+                    break;
+                }
+                Ok(event) => event,
+                Err(err) => {
+                    read = Err(err.into());
+                    break;
+                }
+            };
+            if let Some(group) = source.group() {
+                read = Err(Error::Group(group));
+                break;
+            }
+            let shard = event.client() as usize % senders.len();
+            // A worker only hangs up after an error, which is returned when it is joined.
+            if senders[shard].send((index, event)).is_err() {
+                break;
+            }
+            index += 1;
+        }
+        // Closing the channels lets the workers finish.
+        drop(senders);
+
+        let states = workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap_or_else(|payload| panic::resume_unwind(payload)))
+            .collect::<Result<Vec<_>, _>>()?;
+        read?;
+        Ok(states)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::source::CsvSource;
+
+    #[test]
+    fn same_as_sequential() -> Result<(), Box<dyn std::error::Error>> {
+        let csv = "type,client,tx,amount\n\
+                   deposit,1,1,10\n\
+                   deposit,2,2,5\n\
+                   withdrawal,1,3,4\n\
+                   deposit,3,4,7\n\
+                   dispute,2,2,0\n\
+                   chargeback,2,2,0\n\
+                   withdrawal,3,5,8\n\
+                   deposit,2,6,1\n";
+
+        let mut sequential = State::new();
+        let mut source = CsvSource::new(csv.as_bytes());
+        while let Some(event) = source.next_event() {
+            sequential.handle(event?)?;
+        }
+        let expected: HashMap<_, _> = sequential.client_states().collect();
+
+        let shards = process(
+            CsvSource::new(csv.as_bytes()),
+            NonZeroUsize::new(2).ok_or("zero shards")?,
+        )?;
+        assert_eq!(shards.len(), 2);
+        let found: HashMap<_, _> = shards.iter().flat_map(State::client_states).collect();
+        assert_eq!(found, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn errors() -> Result<(), Box<dyn std::error::Error>> {
+        let csv = "type,client,tx,amount\ndeposit,1,1,10\ndeposit,1,1,10\n";
+        let result = process(
+            CsvSource::new(csv.as_bytes()),
+            NonZeroUsize::new(2).ok_or("zero shards")?,
+        );
+        assert!(matches!(result, Err(Error::State(state::Error::DuplicateTxId(1)))));
+
+        Ok(())
+    }
+}
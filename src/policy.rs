@@ -0,0 +1,200 @@
+//! A declarative policy file with limits that are enforced on every event.
+//!
+//! The file consists of [`key = value`](crate::keyvalue) lines. Empty lines and lines starting with `#` are ignored.
+//! The following keys are supported, each of them is optional and may appear at most once:
+//!
+//! * `max_deposit`: deposits of a larger amount are rejected.
+//! * `max_withdrawal`: withdrawals of a larger amount are rejected.
+//! * `max_balance`: deposits, transfers and chargebacks of transfers that would push the total funds of a client above
+//!   this amount are rejected.
+//!
+//! ```text
+//! # Limits for retail clients
+//! max_deposit = 10000
+//! max_withdrawal = 2500.50
+//! ```
+
+use std::{fmt, str::FromStr};
+
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::{
+    client::ClientState,
+    event::Event,
+    keyvalue,
+    rules::{self, Rule},
+};
+
+/// Errors that can happen while parsing a policy, each of them refers to a line in the file (starting at one).
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum Error {
+    /// The line is not a `key = value` pair, or its key is unknown or set more than once.
+    #[error(transparent)]
+    KeyValue(#[from] keyvalue::Error),
+    /// The value is not a decimal number.
+    #[error("line {0}: `{1}` is not a valid amount")]
+    InvalidAmount(usize, String),
+    /// The value is zero or negative.
+    #[error("line {0}: `{1}` must be positive")]
+    NonPositive(usize, String),
+}
+
+/// Limits that apply to all clients.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Policy {
+    /// Deposits of a larger amount are rejected.
+    pub max_deposit: Option<Decimal>,
+    /// Withdrawals of a larger amount are rejected.
+    pub max_withdrawal: Option<Decimal>,
+    /// Deposits, transfers and chargebacks of transfers that would push the total funds of a client above this amount
+    /// are rejected.
+    pub max_balance: Option<Decimal>,
+}
+
+impl FromStr for Policy {
+    type Err = Error;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let mut policy = Policy::default();
+
+        for pair in keyvalue::pairs(source, &["max_deposit", "max_withdrawal", "max_balance"]) {
+            let (line, key, value) = pair?;
+            let limit = match key {
+                "max_deposit" => &mut policy.max_deposit,
+                "max_withdrawal" => &mut policy.max_withdrawal,
+                "max_balance" => &mut policy.max_balance,
+                _ => unreachable!("`pairs()` only yields the given keys"),
+            };
+
+            let amount = Decimal::from_str(value).map_err(|_| Error::InvalidAmount(line, value.into()))?;
+            if amount <= Decimal::ZERO {
+                return Err(Error::NonPositive(line, key.into()));
+            }
+            *limit = Some(amount);
+        }
+
+        Ok(policy)
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let limits = [
+            ("max_deposit", self.max_deposit),
+            ("max_withdrawal", self.max_withdrawal),
+            ("max_balance", self.max_balance),
+        ];
+        for (key, limit) in limits {
+            match limit {
+                Some(amount) => writeln!(f, "{key} = {amount}")?,
+                None => writeln!(f, "# {key} is not limited")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Rule for Policy {
+    fn rejects(&self, event: &Event, client: Option<&ClientState>) -> Result<bool, rules::Error> {
+        let exceeds = |limit: Option<Decimal>, amount: Decimal| limit.is_some_and(|limit| amount > limit);
+        Ok(match *event {
+            Event::Deposit { amount, .. } => {
+                let total = client.map_or(Decimal::ZERO, ClientState::total) + amount;
+                exceeds(self.max_deposit, amount) || exceeds(self.max_balance, total)
+            }
+            // A transfer is a withdrawal for the sender, which this rule is about.
+            Event::Withdrawal { amount, .. } | Event::Transfer { amount, .. } => exceeds(self.max_withdrawal, amount),
+            Event::Dispute { .. } | Event::Resolve { .. } | Event::Chargeback { .. } | Event::Unlock { .. } => false,
+        })
+    }
+
+    fn rejects_credit(&self, counterpart: Option<&ClientState>, amount: Decimal) -> Result<bool, rules::Error> {
+        let total = counterpart.map_or(Decimal::ZERO, ClientState::total) + amount;
+        Ok(self.max_balance.is_some_and(|limit| total > limit))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::{rules::Rules, state::State};
+
+    #[test]
+    fn parse() -> Result<(), Error> {
+        let policy: Policy = "# comment\n\nmax_deposit = 100\n  max_withdrawal=2.5  ".parse()?;
+        let expected = Policy {
+            max_deposit: Some(dec!(100)),
+            max_withdrawal: Some(dec!(2.5)),
+            max_balance: None,
+        };
+        assert_eq!(policy, expected);
+        assert_eq!(policy.to_string().parse::<Policy>()?, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            "\nmax_fee = 1".parse::<Policy>(),
+            Err(Error::KeyValue(keyvalue::Error::UnknownKey(2, "max_fee".into())))
+        );
+        assert_eq!(
+            "max_balance = lots".parse::<Policy>(),
+            Err(Error::InvalidAmount(1, "lots".into()))
+        );
+        assert_eq!(
+            "max_withdrawal = 0".parse::<Policy>(),
+            Err(Error::NonPositive(1, "max_withdrawal".into()))
+        );
+    }
+
+    #[test]
+    fn limits() -> Result<(), rules::Error> {
+        let policy = Policy {
+            max_deposit: Some(dec!(100)),
+            max_withdrawal: Some(dec!(50)),
+            max_balance: Some(dec!(150)),
+        };
+        let client = ClientState::new(None, dec!(60), dec!(0));
+
+        assert!(!policy.rejects(&Event::deposit(0, 0, dec!(100)), None)?);
+        assert!(policy.rejects(&Event::deposit(0, 0, dec!(100.01)), None)?);
+        assert!(policy.rejects(&Event::deposit(0, 0, dec!(91)), Some(&client))?);
+        assert!(!policy.rejects(&Event::withdrawal(0, 0, dec!(50)), Some(&client))?);
+        assert!(policy.rejects(&Event::withdrawal(0, 0, dec!(51)), Some(&client))?);
+        assert!(!policy.rejects(&Event::chargeback(0, 0), Some(&client))?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn transfers() -> Result<(), Box<dyn std::error::Error>> {
+        let policy = Policy {
+            max_balance: Some(dec!(15)),
+            ..Policy::default()
+        };
+        let mut rules = Rules::default();
+        rules.push(Box::new(policy));
+        let mut state = State::new();
+        state.handle(Event::deposit(1, 1, dec!(10)))?;
+        state.handle(Event::deposit(2, 2, dec!(10)))?;
+
+        // The recipient would end up above the limit.
+        assert!(rules.rejects(&Event::transfer(1, 2, 3, dec!(6)), &state)?);
+        assert!(!rules.rejects(&Event::transfer(1, 2, 3, dec!(5)), &state)?);
+        state.handle(Event::transfer(1, 2, 3, dec!(5)))?;
+
+        // The chargeback returns the transfer to the sender, who received another deposit in the meantime.
+        state.handle(Event::deposit(1, 4, dec!(10)))?;
+        state.handle(Event::dispute(2, 3))?;
+        assert!(rules.rejects(&Event::chargeback(2, 3), &state)?);
+        state.handle(Event::withdrawal(1, 5, dec!(5)))?;
+        assert!(!rules.rejects(&Event::chargeback(2, 3), &state)?);
+
+        Ok(())
+    }
+}
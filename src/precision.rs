@@ -0,0 +1,190 @@
+//! The precision of amounts: inputs have at most four decimal places, and outputs are rounded to as many.
+//!
+//! The arithmetic of the engine itself stays exact, only the amounts entering and leaving it are checked or rounded.
+
+use std::str::FromStr;
+
+use rust_decimal::{Decimal, RoundingStrategy};
+
+use crate::{
+    event::Event,
+    records,
+    source::{self, EventSource},
+    GroupId,
+};
+
+/// The number of decimal places of input amounts and output balances.
+pub const DECIMAL_PLACES: u32 = 4;
+
+/// What happens to input amounts with more than [`DECIMAL_PLACES`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InputPrecision {
+    /// The event is malformed, see [`records::Error::TooPrecise`].
+    #[default]
+    Reject,
+    /// The amount is rounded with the configured [`Rounding`].
+    Round,
+}
+
+impl FromStr for InputPrecision {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(InputPrecision::Reject),
+            "round" => Ok(InputPrecision::Round),
+            _ => Err(()),
+        }
+    }
+}
+
+/// How amounts are rounded to [`DECIMAL_PLACES`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Rounding {
+    /// Round to the nearest value and ties to the even one, which doesn't drift in either direction on average.
+    #[default]
+    Bankers,
+    /// Round towards zero.
+    Truncate,
+}
+
+impl Rounding {
+    /// Returns `amount` rounded to [`DECIMAL_PLACES`].
+    pub fn round(self, amount: Decimal) -> Decimal {
+        // Rounding would pad e.g. zero to `0.0000`.
+        if amount.scale() <= DECIMAL_PLACES {
+            return amount;
+        }
+        let strategy = match self {
+            Rounding::Bankers => RoundingStrategy::MidpointNearestEven,
+            Rounding::Truncate => RoundingStrategy::ToZero,
+        };
+        amount.round_dp_with_strategy(DECIMAL_PLACES, strategy)
+    }
+}
+
+impl FromStr for Rounding {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bankers" => Ok(Rounding::Bankers),
+            "truncate" => Ok(Rounding::Truncate),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Checks the precision of the amounts of the deposits, withdrawals and transfers of a source.
+pub struct Checked<S> {
+    source: S,
+    input: InputPrecision,
+    rounding: Rounding,
+}
+
+impl<S: EventSource> Checked<S> {
+    /// Creates a source that yields the events of `source`, with amounts that are checked according to `input`.
+    pub fn new(source: S, input: InputPrecision, rounding: Rounding) -> Self {
+        Self {
+            source,
+            input,
+            rounding,
+        }
+    }
+
+    /// Returns `event` with an amount of at most [`DECIMAL_PLACES`].
+    fn check(&self, mut event: Event) -> Result<Event, source::Error> {
+        if let Event::Deposit { tx, amount, .. }
+        | Event::Withdrawal { tx, amount, .. }
+        | Event::Transfer { tx, amount, .. } = &mut event
+        {
+            let rounded = self.rounding.round(*amount);
+            if rounded != *amount {
+                match self.input {
+                    InputPrecision::Reject => return Err(records::Error::TooPrecise(*tx, *amount).into()),
+                    InputPrecision::Round => *amount = rounded,
+                }
+            }
+        }
+        Ok(event)
+    }
+}
+
+impl<S: EventSource> EventSource for Checked<S> {
+    fn next_event(&mut self) -> Option<Result<Event, source::Error>> {
+        let event = self.source.next_event()?;
+        Some(event.and_then(|event| self.check(event)))
+    }
+
+    fn line(&self) -> Option<u64> {
+        self.source.line()
+    }
+
+    fn group(&self) -> Option<GroupId> {
+        self.source.group()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::source::{CsvSource, Grouped, Groups, Lenient};
+
+    #[test]
+    fn rounding() {
+        assert_eq!(Rounding::Bankers.round(dec!(1.00005)), dec!(1.0000));
+        assert_eq!(Rounding::Bankers.round(dec!(1.00015)), dec!(1.0002));
+        assert_eq!(Rounding::Bankers.round(dec!(-1.00016)), dec!(-1.0002));
+        assert_eq!(Rounding::Truncate.round(dec!(1.00019)), dec!(1.0001));
+        assert_eq!(Rounding::Truncate.round(dec!(-1.00019)), dec!(-1.0001));
+        assert_eq!(Rounding::Truncate.round(dec!(2.5)).to_string(), "2.5");
+        assert_eq!(Rounding::Bankers.round(dec!(0)).to_string(), "0");
+    }
+
+    #[test]
+    fn input() -> Result<(), source::Error> {
+        let csv = "type,client,tx,amount\ndeposit,1,1,1.23456\ndeposit,1,2,1.2340000\ndispute,1,1,0.123456\n";
+
+        let mut source = Checked::new(
+            CsvSource::new(csv.as_bytes()),
+            InputPrecision::Reject,
+            Rounding::Bankers,
+        );
+        assert!(matches!(
+            source.next_event(),
+            Some(Err(source::Error::Record(records::Error::TooPrecise(1, _))))
+        ));
+        assert_eq!(
+            source.next_event().transpose()?,
+            Some(Event::deposit(1, 2, dec!(1.234)))
+        );
+        assert_eq!(source.next_event().transpose()?, Some(Event::dispute(1, 1)));
+
+        let mut source = Checked::new(
+            CsvSource::new(csv.as_bytes()),
+            InputPrecision::Round,
+            Rounding::Truncate,
+        );
+        assert_eq!(
+            source.next_event().transpose()?,
+            Some(Event::deposit(1, 1, dec!(1.2345)))
+        );
+
+        // A skipped amount rejects its whole group.
+        let csv = "type,client,tx,amount,group\nwithdrawal,1,1,1,7\nwithdrawal,1,2,0.00001,7\n";
+        let checked = Checked::new(
+            CsvSource::new(csv.as_bytes()),
+            InputPrecision::Reject,
+            Rounding::Bankers,
+        );
+        let mut groups = Groups::new(Lenient::new(checked, drop));
+        assert_eq!(
+            groups.next_grouped().transpose()?,
+            Some(Grouped::Incomplete(vec![Event::withdrawal(1, 1, dec!(1))]))
+        );
+
+        Ok(())
+    }
+}
@@ -3,48 +3,452 @@
 use rust_decimal::Decimal;
 use thiserror::Error;
 
-use crate::{event::Event, ClientId, TxId};
+use crate::{event::Event, ClientId, EventIndex, GroupId, TxId};
 
+/// Errors that can happen while converting records.
 #[derive(Debug, Error)]
 pub enum Error {
+    /// The `type` column contains an unknown type of event.
     #[error("invalid transaction type: `{0}`")]
     InvalidTransactionType(String),
+    /// A deposit, withdrawal or transfer without an amount.
+    #[error("missing amount of transaction `{0}`")]
+    MissingAmount(TxId),
+    /// A client id that is larger than [`ClientId::MAX`].
+    #[error("client id `{0}` is out of range")]
+    ClientOutOfRange(String),
+    /// A client id with leading zeros or a sign, which would be the same client as its canonical spelling.
+    #[error("client id `{0}` collides with client `{1}`")]
+    ClientCollision(String, ClientId),
+    /// A transfer without a recipient.
+    #[error("missing recipient of transfer `{0}`")]
+    MissingRecipient(TxId),
+    /// A deposit, withdrawal or transfer with an amount that is zero or negative.
+    #[error("amount of transaction `{0}` is not positive: `{1}`")]
+    NonPositiveAmount(TxId, Decimal),
+    /// A deposit, withdrawal or transfer with more decimal places than
+    /// [`DECIMAL_PLACES`](crate::precision::DECIMAL_PLACES).
+    #[error("amount of transaction `{0}` has too many decimal places: `{1}`")]
+    TooPrecise(TxId, Decimal),
 }
 
 /// Row format of an event in the input CSV file.
 #[derive(Clone, Debug, PartialEq, serde::Deserialize)]
 pub struct EventCsvRecord {
+    /// One of `deposit`, `withdrawal`, `transfer`, `dispute`, `resolve`, `chargeback` or the admin event `unlock`.
     #[serde(rename = "type")]
     pub ty: String,
+    /// The client that the event refers to, which is the sender of a transfer.
     pub client: ClientId,
+    /// The transaction that the event creates or refers to.
     pub tx: TxId,
-    pub amount: Decimal,
+    /// The amount of a deposit, withdrawal or transfer, usually empty and always ignored for other events.
+    pub amount: Option<Decimal>,
+    /// Optional column that names the merchant or other party of a deposit or withdrawal.
+    #[serde(default)]
+    pub counterparty: Option<String>,
+    /// Optional column that refers to an earlier transaction, e.g. the withdrawal that a refund reverses.
+    #[serde(default)]
+    pub related_tx: Option<TxId>,
+    /// Optional column with the recipient of a transfer, ignored for other events.
+    #[serde(default)]
+    pub to: Option<ClientId>,
+    /// Optional column that marks consecutive rows with the same value as an atomic group.
+    #[serde(default)]
+    pub group: Option<GroupId>,
+}
+
+/// Row format of an event in NDJSON input, one JSON object per line.
+///
+/// The fields are the same as the columns of [`EventCsvRecord`], but `amount` may be omitted for events other than
+/// deposits and withdrawals, like an empty column. Amounts can be JSON numbers or strings, strings keep the exact
+/// decimal representation.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+pub struct EventJsonRecord {
+    /// See [`EventCsvRecord::ty`].
+    #[serde(rename = "type")]
+    pub ty: String,
+    /// See [`EventCsvRecord::client`].
+    pub client: ClientId,
+    /// See [`EventCsvRecord::tx`].
+    pub tx: TxId,
+    /// See [`EventCsvRecord::amount`].
+    #[serde(default)]
+    pub amount: Option<Decimal>,
+    /// See [`EventCsvRecord::counterparty`].
+    #[serde(default)]
+    pub counterparty: Option<String>,
+    /// See [`EventCsvRecord::related_tx`].
+    #[serde(default)]
+    pub related_tx: Option<TxId>,
+    /// See [`EventCsvRecord::to`].
+    #[serde(default)]
+    pub to: Option<ClientId>,
+    /// See [`EventCsvRecord::group`].
+    #[serde(default)]
+    pub group: Option<GroupId>,
 }
 
 impl TryFrom<EventCsvRecord> for Event {
     type Error = Error;
 
     fn try_from(value: EventCsvRecord) -> Result<Self, Self::Error> {
-        let EventCsvRecord { ty, client, tx, amount } = value;
-        Ok(match ty.as_str() {
-            "deposit" => Event::Deposit { client, tx, amount },
-            "withdrawal" => Event::Withdrawal { client, tx, amount },
-            "dispute" => Event::Dispute { client, tx },
-            "resolve" => Event::Resolve { client, tx },
-            "chargeback" => Event::Chargeback { client, tx },
-            _ => Err(Error::InvalidTransactionType(ty))?,
-        })
+        let EventCsvRecord {
+            ty,
+            client,
+            tx,
+            amount,
+            counterparty,
+            related_tx,
+            to,
+            ..
+        } = value;
+        event(ty, client, tx, amount, counterparty, related_tx, to)
     }
 }
 
+impl TryFrom<EventJsonRecord> for Event {
+    type Error = Error;
+
+    fn try_from(value: EventJsonRecord) -> Result<Self, Self::Error> {
+        let EventJsonRecord {
+            ty,
+            client,
+            tx,
+            amount,
+            counterparty,
+            related_tx,
+            to,
+            ..
+        } = value;
+        event(ty, client, tx, amount, counterparty, related_tx, to)
+    }
+}
+
+/// Builds the event of type `ty`, the amount is only required for deposits, withdrawals and transfers.
+fn event(
+    ty: String,
+    client: ClientId,
+    tx: TxId,
+    amount: Option<Decimal>,
+    counterparty: Option<String>,
+    related: Option<TxId>,
+    to: Option<ClientId>,
+) -> Result<Event, Error> {
+    // A negative withdrawal would credit the client, so only positive amounts are valid.
+    let positive = |amount: Option<Decimal>| match amount {
+        Some(amount) if amount > Decimal::ZERO => Ok(amount),
+        Some(amount) => Err(Error::NonPositiveAmount(tx, amount)),
+        None => Err(Error::MissingAmount(tx)),
+    };
+    Ok(match ty.as_str() {
+        "deposit" => Event::Deposit {
+            client,
+            tx,
+            amount: positive(amount)?,
+            counterparty,
+            related,
+        },
+        "withdrawal" => Event::Withdrawal {
+            client,
+            tx,
+            amount: positive(amount)?,
+            counterparty,
+            related,
+        },
+        "transfer" => Event::Transfer {
+            from: client,
+            to: to.ok_or(Error::MissingRecipient(tx))?,
+            tx,
+            amount: positive(amount)?,
+        },
+        "dispute" => Event::Dispute { client, tx },
+        "resolve" => Event::Resolve { client, tx },
+        "chargeback" => Event::Chargeback { client, tx },
+        "unlock" => Event::Unlock { client },
+        _ => Err(Error::InvalidTransactionType(ty))?,
+    })
+}
+
 /// Row format of a client in the output CSV file.
-#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct ClientCsvRecord {
+    /// The id of the client.
     pub client: ClientId,
+    /// Funds that can be withdrawn.
     pub available: Decimal,
+    /// Funds that are held because of disputes.
     pub held: Decimal,
+    /// The sum of the available and held funds.
     pub total: Decimal,
+    /// Whether the account is frozen.
     pub locked: bool,
+    /// Empty unless the client is locked.
+    pub lock_reason: Option<String>,
+    /// Index of the event that locked the client, empty unless the client is locked.
+    pub locked_at: Option<EventIndex>,
+}
+
+/// Row format of a client in the output CSV file when only changes to a previous output are written.
+///
+/// Erased clients, which are part of the previous output but not of the current one, only have a `client` and `change`.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct ClientDiffCsvRecord {
+    /// Either `added`, `changed` or `erased`.
+    pub change: &'static str,
+    /// The id of the client.
+    pub client: ClientId,
+    /// See [`ClientCsvRecord::available`].
+    pub available: Option<Decimal>,
+    /// See [`ClientCsvRecord::held`].
+    pub held: Option<Decimal>,
+    /// See [`ClientCsvRecord::total`].
+    pub total: Option<Decimal>,
+    /// See [`ClientCsvRecord::locked`].
+    pub locked: Option<bool>,
+    /// See [`ClientCsvRecord::lock_reason`].
+    pub lock_reason: Option<String>,
+    /// See [`ClientCsvRecord::locked_at`].
+    pub locked_at: Option<EventIndex>,
+}
+
+impl ClientDiffCsvRecord {
+    /// Creates the row of a client that was added or changed.
+    pub fn new(change: &'static str, record: ClientCsvRecord) -> Self {
+        Self {
+            change,
+            client: record.client,
+            available: Some(record.available),
+            held: Some(record.held),
+            total: Some(record.total),
+            locked: Some(record.locked),
+            lock_reason: record.lock_reason,
+            locked_at: record.locked_at,
+        }
+    }
+
+    /// Creates the tombstone of a client that was erased.
+    pub fn erased(client: ClientId) -> Self {
+        Self {
+            change: "erased",
+            client,
+            available: None,
+            held: None,
+            total: None,
+            locked: None,
+            lock_reason: None,
+            locked_at: None,
+        }
+    }
+}
+
+/// Row format of a client in the dormancy report.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct DormantClientCsvRecord {
+    /// The id of the client.
+    pub client: ClientId,
+    /// The funds that the client still holds.
+    pub total: Decimal,
+    /// Index of the last event that referred to the client.
+    pub last_activity: EventIndex,
+}
+
+/// Row format of a counterparty in the counterparty report.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct CounterpartyCsvRecord {
+    /// The name of the counterparty.
+    pub counterparty: String,
+    /// Number of deposits.
+    pub deposits: u64,
+    /// Sum of all deposits.
+    pub deposited: Decimal,
+    /// Number of withdrawals.
+    pub withdrawals: u64,
+    /// Sum of all withdrawals.
+    pub withdrawn: Decimal,
+    /// Number of disputes of deposits and withdrawals.
+    pub disputes: u64,
+    /// Number of disputes per transaction.
+    pub dispute_rate: Decimal,
+}
+
+/// Row format of a transaction in the large-transaction report.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct LargeTransactionCsvRecord {
+    /// Index of the event in the input stream.
+    pub index: EventIndex,
+    /// Either `deposit` or `withdrawal`.
+    #[serde(rename = "type")]
+    pub ty: &'static str,
+    /// The client that the transaction belongs to.
+    pub client: ClientId,
+    /// The id of the transaction.
+    pub tx: TxId,
+    /// The amount of the transaction.
+    pub amount: Decimal,
+    /// The merchant or other party of the transaction.
+    pub counterparty: Option<String>,
+}
+
+/// Row format of an event in the report of rejected events.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct RejectionCsvRecord {
+    /// Index of the event in the input stream.
+    pub index: EventIndex,
+    /// See [`EventCsvRecord::ty`].
+    #[serde(rename = "type")]
+    pub ty: &'static str,
+    /// See [`EventCsvRecord::client`].
+    pub client: ClientId,
+    /// See [`EventCsvRecord::tx`], empty for admin events.
+    pub tx: Option<TxId>,
+    /// See [`EventCsvRecord::amount`], empty for events other than deposits and withdrawals.
+    pub amount: Option<Decimal>,
+    /// See [`EventCsvRecord::counterparty`].
+    pub counterparty: Option<String>,
+    /// See [`EventCsvRecord::related_tx`].
+    pub related_tx: Option<TxId>,
+    /// See [`EventCsvRecord::to`].
+    pub to: Option<ClientId>,
+    /// Why the event was rejected, see [`Rejection::reason()`](crate::state::Rejection::reason).
+    pub reason: &'static str,
+}
+
+/// Row format of a transaction in the ledger.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct LedgerCsvRecord {
+    /// The id of the transaction.
+    pub tx: TxId,
+    /// Either `deposit`, `withdrawal` or `transfer`.
+    #[serde(rename = "type")]
+    pub ty: &'static str,
+    /// The client that the transaction belongs to, see
+    /// [`Transaction::client()`](crate::transaction::Transaction::client).
+    pub client: ClientId,
+    /// The amount of the transaction.
+    pub amount: Decimal,
+    /// The merchant or other party of the transaction.
+    pub counterparty: Option<String>,
+    /// See [`EventCsvRecord::related_tx`].
+    pub related_tx: Option<TxId>,
+    /// Number of disputes that have been opened for the transaction.
+    pub disputes: u32,
+    /// One of `clean`, `disputed`, `resolved`, `charged_back` or `refunded`.
+    pub status: &'static str,
+    /// The fee of a withdrawal, see [`Fee::amount`](crate::fees::Fee::amount).
+    pub fee: Option<Decimal>,
+    /// The rounding remainder of the fee, see [`Fee::remainder`](crate::fees::Fee::remainder).
+    pub fee_remainder: Option<Decimal>,
+}
+
+/// Row format of the open disputes report.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct OpenDisputeCsvRecord {
+    /// The client that the transaction belongs to.
+    pub client: ClientId,
+    /// The disputed transaction.
+    pub tx: TxId,
+    /// Either `deposit` or `withdrawal`.
+    #[serde(rename = "type")]
+    pub ty: &'static str,
+    /// The held amount.
+    pub amount: Decimal,
+    /// Index of the event in the input stream that opened the dispute.
+    pub opened_at: EventIndex,
+}
+
+/// Row format of the frozen clients report.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct FrozenClientCsvRecord {
+    /// The frozen client.
+    pub client: ClientId,
+    /// The charged back deposit that froze the client.
+    pub tx: TxId,
+    /// The amount of the charged back deposit.
+    pub amount: Option<Decimal>,
+    /// Funds available to the client when it was frozen.
+    pub available: Decimal,
+    /// Funds held when the client was frozen.
+    pub held: Decimal,
+    /// Total funds when the client was frozen.
+    pub total: Decimal,
+    /// Index of the chargeback in the input stream.
+    pub frozen_at: EventIndex,
+}
+
+/// Row format of the float report.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct FloatCsvRecord {
+    /// See [`Float::deposited()`](crate::treasury::Float::deposited).
+    pub deposited: Decimal,
+    /// See [`Float::withdrawn()`](crate::treasury::Float::withdrawn).
+    pub withdrawn: Decimal,
+    /// See [`Float::reversed()`](crate::treasury::Float::reversed).
+    pub reversed: Decimal,
+    /// See [`Float::charged_back()`](crate::treasury::Float::charged_back).
+    pub charged_back: Decimal,
+    /// See [`Float::balance()`](crate::treasury::Float::balance).
+    pub balance: Decimal,
+}
+
+/// Row format of the summary of a run, see [`Summary`](crate::reporting::Summary).
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize)]
+pub struct SummaryCsvRecord {
+    /// The number of events, without malformed records.
+    pub events: u64,
+    /// The number of deposit events.
+    pub deposits: u64,
+    /// The number of withdrawal events.
+    pub withdrawals: u64,
+    /// The number of transfer events.
+    pub transfers: u64,
+    /// The number of dispute events.
+    pub disputes: u64,
+    /// The number of resolve events.
+    pub resolves: u64,
+    /// The number of chargeback events.
+    pub chargebacks: u64,
+    /// The number of unlock events.
+    pub unlocks: u64,
+    /// The number of events that were applied.
+    pub applied: u64,
+    /// The number of events that were rejected by the rules or the state.
+    pub rejected: u64,
+    /// The sum of all applied deposits.
+    pub deposited: Decimal,
+    /// The sum of all applied withdrawals.
+    pub withdrawn: Decimal,
+    /// The number of frozen clients at the end.
+    pub frozen_clients: usize,
+    /// The number of transactions that are still disputed at the end.
+    pub open_disputes: usize,
+}
+
+/// Row format of an event in the history of a client, see [`State::journal()`](crate::State::journal).
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct HistoryCsvRecord {
+    /// Index of the event in the input stream.
+    pub index: EventIndex,
+    /// See [`EventCsvRecord::ty`].
+    #[serde(rename = "type")]
+    pub ty: &'static str,
+    /// See [`EventCsvRecord::tx`], empty for admin events.
+    pub tx: Option<TxId>,
+    /// The amount of the event, or of the transaction that a dispute, resolve or chargeback refers to.
+    pub amount: Option<Decimal>,
+    /// The available funds of the client after the event.
+    pub available: Decimal,
+    /// The held funds of the client after the event.
+    pub held: Decimal,
+}
+
+/// Row format of a mapping from an external client identifier to a client id, see [`IdMap`](crate::idmap::IdMap).
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct IdMapCsvRecord {
+    /// The identifier of the client in the input.
+    pub external: String,
+    /// The id of the client in the output.
+    pub client: ClientId,
 }
 
 #[cfg(test)]
@@ -55,12 +459,16 @@ mod test {
 
     impl EventCsvRecord {
         /// Helper function to create input rows for test.
-        fn new(ty: &str, client: ClientId, tx: TxId, amount: Decimal) -> Self {
+        fn new(ty: &str, client: ClientId, tx: TxId, amount: Option<Decimal>) -> Self {
             Self {
                 ty: ty.into(),
                 client,
                 tx,
                 amount,
+                counterparty: None,
+                related_tx: None,
+                to: None,
+                group: None,
             }
         }
     }
@@ -68,32 +476,84 @@ mod test {
     #[test]
     fn deserialize() -> Result<(), csv::Error> {
         let input = [
+            "type,client,tx,amount",
             "deposit,0,1,1234.5678",
             "withdrawal,0,1,-42.42",
-            "dispute,0,1  ,0", // Whitespace
-            "resolve,0,1,",    // Missing amount number
-            "chargeback,0,1",  // Missing comma
+            "dispute,0,1,0",
+            "resolve,0,1,", // Missing amount
         ];
 
         let expected = [
-            EventCsvRecord::new("deposit", 0, 1, dec!(1234.5678)),
-            EventCsvRecord::new("withdrawal", 0, 1, dec!(-42.42)),
-            EventCsvRecord::new("dispute", 0, 1, dec!(0)),
-            EventCsvRecord::new("resolve", 0, 1, dec!(0)),
-            EventCsvRecord::new("chargeback", 0, 1, dec!(0)),
+            EventCsvRecord::new("deposit", 0, 1, Some(dec!(1234.5678))),
+            EventCsvRecord::new("withdrawal", 0, 1, Some(dec!(-42.42))),
+            EventCsvRecord::new("dispute", 0, 1, Some(dec!(0))),
+            EventCsvRecord::new("resolve", 0, 1, None),
         ];
 
-        // `.zip()` ends when the first iterator returns `None`, so we need to check for the correct length.
-        assert_eq!(input.len(), expected.len());
+        let input = input.join("\n");
+        let records = csv::Reader::from_reader(input.as_bytes())
+            .deserialize()
+            .collect::<Result<Vec<EventCsvRecord>, _>>()?;
+        assert_eq!(records, expected);
 
-        let input = input.join("\\n");
+        Ok(())
+    }
 
-        let mut rdr = csv::Reader::from_reader(input.as_bytes());
-        for (record, expected) in rdr.deserialize().zip(expected.into_iter()) {
-            let record: EventCsvRecord = record?;
-            assert_eq!(record, expected);
-        }
+    #[test]
+    fn json() -> Result<(), Box<dyn std::error::Error>> {
+        let record: EventJsonRecord =
+            serde_json::from_str(r#"{"type":"deposit","client":1,"tx":2,"amount":"1.2345"}"#)?;
+        assert_eq!(Event::try_from(record)?, Event::deposit(1, 2, dec!(1.2345)));
+
+        let record: EventJsonRecord = serde_json::from_str(r#"{"type":"withdrawal","client":1,"tx":3,"amount":0.5}"#)?;
+        assert_eq!(Event::try_from(record)?, Event::withdrawal(1, 3, dec!(0.5)));
+
+        let record: EventJsonRecord = serde_json::from_str(r#"{"type":"dispute","client":1,"tx":2}"#)?;
+        assert_eq!(Event::try_from(record)?, Event::dispute(1, 2));
+
+        let record: EventJsonRecord = serde_json::from_str(r#"{"type":"deposit","client":1,"tx":4}"#)?;
+        assert!(matches!(Event::try_from(record), Err(Error::MissingAmount(4))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn transfer() -> Result<(), Box<dyn std::error::Error>> {
+        let record: EventJsonRecord =
+            serde_json::from_str(r#"{"type":"transfer","client":1,"tx":5,"amount":"2","to":3}"#)?;
+        assert_eq!(Event::try_from(record)?, Event::transfer(1, 3, 5, dec!(2)));
+
+        let record = EventCsvRecord::new("transfer", 1, 6, Some(dec!(2)));
+        assert!(matches!(Event::try_from(record), Err(Error::MissingRecipient(6))));
 
         Ok(())
     }
+
+    #[test]
+    fn non_positive_amounts() {
+        let record = EventCsvRecord::new("withdrawal", 0, 1, Some(dec!(-42.42)));
+        assert!(matches!(Event::try_from(record), Err(Error::NonPositiveAmount(1, _))));
+
+        let record = EventCsvRecord::new("deposit", 0, 2, Some(dec!(0.0)));
+        assert!(matches!(Event::try_from(record), Err(Error::NonPositiveAmount(2, _))));
+
+        // The amount of other events is ignored.
+        let record = EventCsvRecord::new("dispute", 0, 1, Some(dec!(0)));
+        assert!(matches!(
+            Event::try_from(record),
+            Ok(Event::Dispute { client: 0, tx: 1 })
+        ));
+    }
+
+    #[test]
+    fn missing_amounts() {
+        let record = EventCsvRecord::new("deposit", 0, 3, None);
+        assert!(matches!(Event::try_from(record), Err(Error::MissingAmount(3))));
+
+        let record = EventCsvRecord::new("chargeback", 0, 3, None);
+        assert!(matches!(
+            Event::try_from(record),
+            Ok(Event::Chargeback { client: 0, tx: 3 })
+        ));
+    }
 }
@@ -0,0 +1,758 @@
+//! Aggregated reports that are derived from the retained transactions.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    io,
+};
+
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::{
+    client::FreezeReason,
+    event::Event,
+    records::{
+        ClientCsvRecord, ClientDiffCsvRecord, CounterpartyCsvRecord, FrozenClientCsvRecord, HistoryCsvRecord,
+        LargeTransactionCsvRecord, LedgerCsvRecord, OpenDisputeCsvRecord, RejectionCsvRecord, SummaryCsvRecord,
+    },
+    state::{self, Outcome, Rejection, State},
+    transaction::{Deposit, DisputeStatus, Transaction, Transfer, Withdrawal},
+    ClientId, EventIndex, TxId,
+};
+
+/// Errors that can happen while writing reports during processing.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Applying an event failed.
+    #[error(transparent)]
+    State(#[from] state::Error),
+    /// Writing the report failed.
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+}
+
+/// Aggregates deposits, withdrawals and disputes by counterparty, ordered by the name of the counterparty.
+///
+/// Transactions without a counterparty are not part of the report.
+pub fn counterparty_report(state: &State) -> Vec<CounterpartyCsvRecord> {
+    let mut report = BTreeMap::new();
+
+    for (_, transaction) in state.transactions() {
+        let (counterparty, amount, disputes, deposit) = match transaction {
+            Transaction::Deposit(Deposit {
+                counterparty: Some(counterparty),
+                amount,
+                disputes,
+                ..
+            }) => (counterparty, *amount, disputes, true),
+            Transaction::Withdrawal(Withdrawal {
+                counterparty: Some(counterparty),
+                amount,
+                disputes,
+                ..
+            }) => (counterparty, *amount, disputes, false),
+            _ => continue,
+        };
+
+        let record = report.entry(counterparty).or_insert_with(|| CounterpartyCsvRecord {
+            counterparty: counterparty.clone(),
+            deposits: 0,
+            deposited: Decimal::ZERO,
+            withdrawals: 0,
+            withdrawn: Decimal::ZERO,
+            disputes: 0,
+            dispute_rate: Decimal::ZERO,
+        });
+
+        if deposit {
+            record.deposits += 1;
+            record.deposited += amount;
+        } else {
+            record.withdrawals += 1;
+            record.withdrawn += amount;
+        }
+        record.disputes += u64::from(*disputes);
+    }
+
+    report
+        .into_values()
+        .map(|mut record| {
+            let transactions = Decimal::from(record.deposits + record.withdrawals);
+            record.dispute_rate = (Decimal::from(record.disputes) / transactions).round_dp(4).normalize();
+            record
+        })
+        .collect()
+}
+
+/// Lists all retained transactions with their final status, ordered by transaction id.
+///
+/// The status is the first that applies of:
+///
+/// * `charged_back`: the deposit was charged back.
+/// * `disputed`: the transaction is still disputed.
+/// * `refunded`: a later transaction refers to it, e.g. a refund of a withdrawal.
+/// * `resolved`: the last dispute of the transaction was resolved.
+/// * `clean`: the transaction has never been disputed.
+pub fn ledger(state: &State) -> Vec<LedgerCsvRecord> {
+    let referenced: HashSet<TxId> = state
+        .transactions()
+        .filter_map(|(_, transaction)| match transaction {
+            Transaction::Deposit(Deposit { related, .. }) | Transaction::Withdrawal(Withdrawal { related, .. }) => {
+                *related
+            }
+            Transaction::Transfer(_) => None,
+        })
+        .collect();
+
+    let mut ledger: Vec<_> = state
+        .transactions()
+        .map(|(&tx, transaction)| {
+            let (ty, client, amount, counterparty, related_tx, dispute, disputes, fee) = match transaction {
+                Transaction::Deposit(deposit) => (
+                    "deposit",
+                    deposit.client,
+                    deposit.amount,
+                    &deposit.counterparty,
+                    deposit.related,
+                    deposit.dispute,
+                    deposit.disputes,
+                    None,
+                ),
+                Transaction::Withdrawal(withdrawal) => (
+                    "withdrawal",
+                    withdrawal.client,
+                    withdrawal.amount,
+                    &withdrawal.counterparty,
+                    withdrawal.related,
+                    withdrawal.dispute,
+                    withdrawal.disputes,
+                    withdrawal.fee,
+                ),
+                // Transfers are listed with the recipient, whose funds a dispute holds.
+                Transaction::Transfer(transfer) => (
+                    "transfer",
+                    transfer.to,
+                    transfer.amount,
+                    &None,
+                    None,
+                    transfer.dispute,
+                    transfer.disputes,
+                    None,
+                ),
+            };
+            let status = match dispute {
+                DisputeStatus::ChargedBack => "charged_back",
+                DisputeStatus::Disputed => "disputed",
+                _ if referenced.contains(&tx) => "refunded",
+                DisputeStatus::Resolved => "resolved",
+                DisputeStatus::None => "clean",
+            };
+
+            LedgerCsvRecord {
+                tx,
+                ty,
+                client,
+                amount,
+                counterparty: counterparty.clone(),
+                related_tx,
+                disputes,
+                status,
+                fee: fee.map(|fee| fee.amount),
+                fee_remainder: fee.map(|fee| fee.remainder),
+            }
+        })
+        .collect();
+
+    ledger.sort_by_key(|record| record.tx);
+    ledger
+}
+
+/// Lists all transactions that are still disputed, ordered by the index of the event that opened the dispute.
+pub fn open_disputes(state: &State) -> Vec<OpenDisputeCsvRecord> {
+    let mut disputes: Vec<_> = state
+        .transactions()
+        .filter_map(|(&tx, transaction)| {
+            let (ty, client, amount, opened_at) = match transaction {
+                Transaction::Deposit(deposit) => ("deposit", deposit.client, deposit.amount, deposit.disputed_at?),
+                Transaction::Withdrawal(withdrawal) => (
+                    "withdrawal",
+                    withdrawal.client,
+                    withdrawal.amount,
+                    withdrawal.disputed_at?,
+                ),
+                Transaction::Transfer(transfer) => ("transfer", transfer.to, transfer.amount, transfer.disputed_at?),
+            };
+            Some(OpenDisputeCsvRecord {
+                client,
+                tx,
+                ty,
+                amount,
+                opened_at,
+            })
+        })
+        .collect();
+
+    disputes.sort_by_key(|record| record.opened_at);
+    disputes
+}
+
+/// Lists all frozen clients with the chargeback that froze them, ordered by client.
+///
+/// Frozen clients reject all further events, so their current balances are the balances at the time of the freeze.
+pub fn frozen_clients(state: &State) -> Vec<FrozenClientCsvRecord> {
+    let mut frozen: Vec<_> = state
+        .client_states()
+        .filter_map(|(&client, client_state)| {
+            let freeze = client_state.freeze()?;
+            let FreezeReason::Chargeback(tx) = freeze.reason;
+            let amount = match state.transaction(tx) {
+                Some(Transaction::Deposit(Deposit { amount, .. }) | Transaction::Transfer(Transfer { amount, .. })) => {
+                    Some(*amount)
+                }
+                _ => None,
+            };
+            Some(FrozenClientCsvRecord {
+                client,
+                tx,
+                amount,
+                available: client_state.available(),
+                held: client_state.held(),
+                total: client_state.total(),
+                frozen_at: freeze.at,
+            })
+        })
+        .collect();
+
+    frozen.sort_by_key(|record| record.client);
+    frozen
+}
+
+/// Lists the events that changed the state of `client` with its balances after each of them, in the order they were
+/// applied.
+///
+/// The state has to be created with [`State::with_journal()`], otherwise the history is empty.
+pub fn history(state: &State, client: ClientId) -> Vec<HistoryCsvRecord> {
+    state
+        .journal(client)
+        .iter()
+        .map(|entry| HistoryCsvRecord {
+            index: entry.index,
+            ty: match entry.event {
+                Event::Deposit { .. } => "deposit",
+                Event::Withdrawal { .. } => "withdrawal",
+                Event::Transfer { .. } => "transfer",
+                Event::Dispute { .. } => "dispute",
+                Event::Resolve { .. } => "resolve",
+                Event::Chargeback { .. } => "chargeback",
+                Event::Unlock { .. } => "unlock",
+            },
+            tx: entry.event.tx(),
+            amount: entry.amount,
+            available: entry.available,
+            held: entry.held,
+        })
+        .collect()
+}
+
+/// Compares the rows of a previous output with the current ones and returns the changes, ordered by client.
+///
+/// Clients whose row is unchanged are not part of the result.
+pub fn client_diff(
+    previous: impl IntoIterator<Item = ClientCsvRecord>,
+    current: impl IntoIterator<Item = ClientCsvRecord>,
+) -> Vec<ClientDiffCsvRecord> {
+    let mut previous: BTreeMap<_, _> = previous.into_iter().map(|record| (record.client, record)).collect();
+    let mut diff = BTreeMap::new();
+
+    for record in current {
+        match previous.remove(&record.client) {
+            Some(old) if old == record => {}
+            Some(_) => {
+                diff.insert(record.client, ClientDiffCsvRecord::new("changed", record));
+            }
+            None => {
+                diff.insert(record.client, ClientDiffCsvRecord::new("added", record));
+            }
+        }
+    }
+    for client in previous.into_keys() {
+        diff.insert(client, ClientDiffCsvRecord::erased(client));
+    }
+
+    diff.into_values().collect()
+}
+
+/// Writes every applied deposit or withdrawal above a threshold while the input is being processed.
+pub struct LargeTransactionReport<W: io::Write> {
+    threshold: Decimal,
+    writer: csv::Writer<W>,
+}
+
+impl<W: io::Write> LargeTransactionReport<W> {
+    /// Creates a report of all transactions whose amount exceeds `threshold`.
+    pub fn new(threshold: Decimal, writer: W) -> Self {
+        let writer = csv::WriterBuilder::new().has_headers(true).from_writer(writer);
+        Self { threshold, writer }
+    }
+
+    /// Writes the buffered rows, which otherwise happens when the report is dropped.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Handles `event` and adds it to the report if it is a large transaction that was actually applied.
+    pub fn handle(&mut self, state: &mut State, event: Event) -> Result<Outcome, Error> {
+        let record = self.record(state.next_index(), &event);
+        let outcome = state.handle(event)?;
+
+        if let (Some(record), Outcome::Applied) = (record, outcome) {
+            self.writer.serialize(record)?;
+        }
+        Ok(outcome)
+    }
+
+    /// Handles `event` with [`State::handle_synthetic()`] and adds it to the report like [`Self::handle()`].
+    pub fn handle_synthetic(&mut self, state: &mut State, event: Event) -> Result<Outcome, Error> {
+        let record = self.record(state.next_index().saturating_sub(1), &event);
+        let outcome = state.handle_synthetic(event)?;
+
+        if let (Some(record), Outcome::Applied) = (record, outcome) {
+            self.writer.serialize(record)?;
+        }
+        Ok(outcome)
+    }
+
+    /// Handles the atomic group `events` with [`State::handle_group()`] and adds its large transactions to the report
+    /// if the group was applied.
+    pub fn handle_group(&mut self, state: &mut State, events: Vec<Event>) -> Result<Vec<Outcome>, Error> {
+        let first = state.next_index();
+        let records: Vec<_> = (first..)
+            .zip(&events)
+            .filter_map(|(index, event)| self.record(index, event))
+            .collect();
+        let outcomes = state.handle_group(events)?;
+
+        // Either all events of a group are applied or none.
+        if outcomes.iter().all(|outcome| *outcome == Outcome::Applied) {
+            for record in records {
+                self.writer.serialize(record)?;
+            }
+        }
+        Ok(outcomes)
+    }
+
+    /// Returns the record of `event` at `index` if it is a large transaction.
+    fn record(&self, index: EventIndex, event: &Event) -> Option<LargeTransactionCsvRecord> {
+        match event {
+            Event::Deposit {
+                client,
+                tx,
+                amount,
+                counterparty,
+                ..
+            }
+            | Event::Withdrawal {
+                client,
+                tx,
+                amount,
+                counterparty,
+                ..
+            } if *amount > self.threshold => Some(LargeTransactionCsvRecord {
+                index,
+                ty: if matches!(event, Event::Deposit { .. }) {
+                    "deposit"
+                } else {
+                    "withdrawal"
+                },
+                client: *client,
+                tx: *tx,
+                amount: *amount,
+                counterparty: counterparty.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Writes every rejected event together with the reason, for compliance review.
+pub struct RejectionReport<W: io::Write> {
+    writer: csv::Writer<W>,
+}
+
+impl<W: io::Write> RejectionReport<W> {
+    /// Creates a report that writes to `writer`.
+    pub fn new(writer: W) -> Self {
+        let writer = csv::WriterBuilder::new().has_headers(true).from_writer(writer);
+        Self { writer }
+    }
+
+    /// Writes the buffered rows, which otherwise happens when the report is dropped.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Adds `event`, which was the event at `index` in the input stream, to the report.
+    pub fn write(&mut self, index: EventIndex, event: &Event, rejection: Rejection) -> Result<(), Error> {
+        let (ty, amount, counterparty, related_tx, to) = match event {
+            Event::Deposit {
+                amount,
+                counterparty,
+                related,
+                ..
+            } => ("deposit", Some(*amount), counterparty.clone(), *related, None),
+            Event::Withdrawal {
+                amount,
+                counterparty,
+                related,
+                ..
+            } => ("withdrawal", Some(*amount), counterparty.clone(), *related, None),
+            Event::Dispute { .. } => ("dispute", None, None, None, None),
+            Event::Resolve { .. } => ("resolve", None, None, None, None),
+            Event::Transfer { amount, to, .. } => ("transfer", Some(*amount), None, None, Some(*to)),
+            Event::Chargeback { .. } => ("chargeback", None, None, None, None),
+            Event::Unlock { .. } => ("unlock", None, None, None, None),
+        };
+        self.writer.serialize(RejectionCsvRecord {
+            index,
+            ty,
+            client: event.client(),
+            tx: event.tx(),
+            amount,
+            counterparty,
+            related_tx,
+            to,
+            reason: rejection.reason(),
+        })?;
+        Ok(())
+    }
+}
+
+/// Counts the events of a run by type and outcome while the input is being processed.
+#[derive(Clone, Debug, Default)]
+pub struct Summary {
+    record: SummaryCsvRecord,
+}
+
+impl Summary {
+    /// Creates an empty summary.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `event`, which had the `outcome`, to the summary.
+    pub fn add(&mut self, event: &Event, outcome: Outcome) {
+        let record = &mut self.record;
+        record.events += 1;
+        let applied = outcome == Outcome::Applied;
+        match *event {
+            Event::Deposit { amount, .. } => {
+                record.deposits += 1;
+                if applied {
+                    record.deposited += amount;
+                }
+            }
+            Event::Withdrawal { amount, .. } => {
+                record.withdrawals += 1;
+                if applied {
+                    record.withdrawn += amount;
+                }
+            }
+            Event::Transfer { .. } => record.transfers += 1,
+            Event::Dispute { .. } => record.disputes += 1,
+            Event::Resolve { .. } => record.resolves += 1,
+            Event::Chargeback { .. } => record.chargebacks += 1,
+            Event::Unlock { .. } => record.unlocks += 1,
+        }
+        match applied {
+            true => record.applied += 1,
+            false => record.rejected += 1,
+        }
+    }
+
+    /// Returns the summary, with the frozen clients and open disputes of `state` at the end of the run.
+    pub fn finish(self, state: &State) -> SummaryCsvRecord {
+        SummaryCsvRecord {
+            frozen_clients: state.client_states().filter(|(_, client)| client.frozen()).count(),
+            open_disputes: open_disputes(state).len(),
+            ..self.record
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::ClientId;
+
+    fn deposit(client: ClientId, tx: TxId, amount: Decimal, counterparty: &str) -> Event {
+        Event::Deposit {
+            client,
+            tx,
+            amount,
+            counterparty: Some(counterparty.into()),
+            related: None,
+        }
+    }
+
+    #[test]
+    fn by_counterparty() -> Result<(), state::Error> {
+        let mut state = State::new();
+
+        for event in [
+            deposit(0, 0, dec!(10), "acme"),
+            deposit(1, 1, dec!(5), "acme"),
+            deposit(1, 2, dec!(7), "zenith"),
+            Event::deposit(1, 3, dec!(100)), // no counterparty
+            Event::Withdrawal {
+                client: 1,
+                tx: 4,
+                amount: dec!(2),
+                counterparty: Some("acme".into()),
+                related: None,
+            },
+            Event::dispute(0, 0),
+        ] {
+            state.handle(event)?;
+        }
+
+        let expected = [
+            CounterpartyCsvRecord {
+                counterparty: "acme".into(),
+                deposits: 2,
+                deposited: dec!(15),
+                withdrawals: 1,
+                withdrawn: dec!(2),
+                disputes: 1,
+                dispute_rate: dec!(0.3333),
+            },
+            CounterpartyCsvRecord {
+                counterparty: "zenith".into(),
+                deposits: 1,
+                deposited: dec!(7),
+                withdrawals: 0,
+                withdrawn: dec!(0),
+                disputes: 0,
+                dispute_rate: dec!(0),
+            },
+        ];
+        assert_eq!(counterparty_report(&state), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn ledger_status() -> Result<(), state::Error> {
+        let mut state = State::new();
+        state.handle(Event::deposit(0, 0, dec!(10)))?;
+        state.handle(Event::deposit(0, 1, dec!(10)))?;
+        state.handle(Event::deposit(0, 2, dec!(10)))?;
+        state.handle(Event::withdrawal(0, 3, dec!(5)))?;
+        state.handle(Event::Deposit {
+            client: 0,
+            tx: 4,
+            amount: dec!(5),
+            counterparty: None,
+            related: Some(3),
+        })?;
+        state.handle(Event::dispute(0, 1))?;
+        state.handle(Event::resolve(0, 1))?;
+        state.handle(Event::dispute(0, 2))?;
+        state.handle(Event::deposit(1, 5, dec!(1)))?;
+        state.handle(Event::dispute(1, 5))?;
+        state.handle(Event::chargeback(1, 5))?;
+
+        let statuses: Vec<_> = ledger(&state).iter().map(|record| (record.tx, record.status)).collect();
+        let expected = [
+            (0, "clean"),
+            (1, "resolved"),
+            (2, "disputed"),
+            (3, "refunded"),
+            (4, "clean"),
+            (5, "charged_back"),
+        ];
+        assert_eq!(statuses, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn summary() -> Result<(), state::Error> {
+        let events = [
+            Event::deposit(1, 1, dec!(10)),
+            Event::deposit(2, 2, dec!(5)),
+            Event::withdrawal(1, 3, dec!(20)),
+            Event::withdrawal(1, 4, dec!(4)),
+            Event::dispute(2, 2),
+            // The client already withdrew part of the deposit, so neither of these is applied.
+            Event::dispute(1, 1),
+            Event::chargeback(1, 1),
+        ];
+        let mut state = State::new();
+        let mut summary = Summary::new();
+        for event in events {
+            let outcome = state.handle(event.clone())?;
+            summary.add(&event, outcome);
+        }
+
+        let expected = SummaryCsvRecord {
+            events: 7,
+            deposits: 2,
+            withdrawals: 2,
+            disputes: 2,
+            chargebacks: 1,
+            applied: 4,
+            rejected: 3,
+            deposited: dec!(15),
+            withdrawn: dec!(4),
+            frozen_clients: 0,
+            open_disputes: 1,
+            ..SummaryCsvRecord::default()
+        };
+        assert_eq!(summary.finish(&state), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn open_disputes_report() -> Result<(), state::Error> {
+        let mut state = State::new();
+        state.handle(Event::deposit(0, 0, dec!(4)))?;
+        state.handle(Event::withdrawal(0, 1, dec!(4)))?;
+        state.handle(Event::deposit(0, 2, dec!(10)))?;
+        state.handle(Event::deposit(1, 3, dec!(1)))?;
+        state.handle(Event::dispute(0, 1))?;
+        state.handle(Event::dispute(0, 2))?;
+        state.handle(Event::resolve(0, 2))?;
+        state.handle(Event::dispute(0, 0))?;
+        state.handle(Event::dispute(1, 3))?;
+        state.handle(Event::chargeback(1, 3))?;
+
+        let expected = [
+            OpenDisputeCsvRecord {
+                client: 0,
+                tx: 1,
+                ty: "withdrawal",
+                amount: dec!(4),
+                opened_at: 4,
+            },
+            OpenDisputeCsvRecord {
+                client: 0,
+                tx: 0,
+                ty: "deposit",
+                amount: dec!(4),
+                opened_at: 7,
+            },
+        ];
+        assert_eq!(open_disputes(&state), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn frozen_clients_report() -> Result<(), state::Error> {
+        let mut state = State::new();
+        state.handle(Event::deposit(0, 0, dec!(10)))?;
+        state.handle(Event::deposit(1, 1, dec!(3)))?;
+        state.handle(Event::deposit(1, 2, dec!(4)))?;
+        state.handle(Event::dispute(1, 1))?;
+        state.handle(Event::dispute(1, 2))?;
+        state.handle(Event::chargeback(1, 1))?;
+        state.handle(Event::deposit(1, 3, dec!(5)))?;
+
+        let expected = [FrozenClientCsvRecord {
+            client: 1,
+            tx: 1,
+            amount: Some(dec!(3)),
+            available: dec!(0),
+            held: dec!(4),
+            total: dec!(4),
+            frozen_at: 5,
+        }];
+        assert_eq!(frozen_clients(&state), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn diff() {
+        let record = |client, available| ClientCsvRecord {
+            client,
+            available,
+            held: dec!(0),
+            total: available,
+            locked: false,
+            lock_reason: None,
+            locked_at: None,
+        };
+        let previous = [record(1, dec!(10)), record(2, dec!(5)), record(3, dec!(1.50))];
+        let current = [record(4, dec!(1)), record(3, dec!(1.5)), record(2, dec!(6))];
+
+        let expected = [
+            ClientDiffCsvRecord::erased(1),
+            ClientDiffCsvRecord::new("changed", record(2, dec!(6))),
+            ClientDiffCsvRecord::new("added", record(4, dec!(1))),
+        ];
+        assert_eq!(client_diff(previous, current), expected);
+    }
+
+    #[test]
+    fn rejections() -> Result<(), Box<dyn std::error::Error>> {
+        let mut state = State::new();
+        let mut output = Vec::new();
+        let mut report = RejectionReport::new(&mut output);
+
+        for event in [
+            Event::deposit(0, 0, dec!(10)),
+            Event::withdrawal(0, 1, dec!(20)),
+            Event::dispute(1, 0),
+            deposit(0, 2, dec!(5), "acme"),
+            Event::transfer(0, 1, 3, dec!(50)),
+        ] {
+            let index = state.next_index();
+            if let Outcome::Rejected(rejection) = state.handle(event.clone())? {
+                report.write(index, &event, rejection)?;
+            }
+        }
+        report.write(state.skip(), &Event::resolve(0, 0), Rejection::Rule)?;
+        drop(report);
+
+        let expected = "\
+index,type,client,tx,amount,counterparty,related_tx,to,reason
+1,withdrawal,0,1,20,,,,insufficient_funds
+2,dispute,1,0,,,,,client_mismatch
+4,transfer,0,3,50,,,1,insufficient_funds
+5,resolve,0,0,,,,,rule
+";
+        assert_eq!(String::from_utf8(output)?, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn large_transactions() -> Result<(), Box<dyn std::error::Error>> {
+        let mut state = State::new();
+        let mut output = Vec::new();
+        let mut report = LargeTransactionReport::new(dec!(100), &mut output);
+
+        for event in [
+            deposit(0, 0, dec!(100), "acme"),
+            deposit(0, 1, dec!(100.01), "acme"),
+            Event::withdrawal(0, 2, dec!(250)), // insufficient funds
+            Event::withdrawal(0, 3, dec!(150)),
+        ] {
+            report.handle(&mut state, event)?;
+        }
+        drop(report);
+
+        let expected = "\
+index,type,client,tx,amount,counterparty
+1,deposit,0,1,100.01,acme
+3,withdrawal,0,3,150,
+";
+        assert_eq!(String::from_utf8(output)?, expected);
+
+        Ok(())
+    }
+}
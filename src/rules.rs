@@ -0,0 +1,106 @@
+//! Custom rules that can reject events before they are applied to the state.
+
+use std::rc::Rc;
+
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::{client::ClientState, event::Event, state::State};
+
+/// Errors that can happen while evaluating rules.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// A script rule failed.
+    #[cfg(feature = "scripting")]
+    #[error(transparent)]
+    Script(#[from] crate::script::Error),
+}
+
+/// A rule that decides whether an event is applied or rejected.
+pub trait Rule {
+    /// Returns `true` if `event` should be rejected.
+    ///
+    /// `client` is the current state of the client that the event refers to, or `None` if the client is unknown.
+    fn rejects(&self, event: &Event, client: Option<&ClientState>) -> Result<bool, Error>;
+
+    /// Returns `true` if an event should be rejected because it credits `amount` to another client than its own, see
+    /// [`State::credited_counterpart()`].
+    ///
+    /// `counterpart` is the current state of that client, or `None` if the client is unknown. Rules only look at the
+    /// client of the event unless they override this.
+    fn rejects_credit(&self, counterpart: Option<&ClientState>, amount: Decimal) -> Result<bool, Error> {
+        let _ = (counterpart, amount);
+        Ok(false)
+    }
+}
+
+// Allows keeping access to a rule after handing it to [`Rules`], e.g. to read statistics.
+impl<R: Rule + ?Sized> Rule for Rc<R> {
+    fn rejects(&self, event: &Event, client: Option<&ClientState>) -> Result<bool, Error> {
+        (**self).rejects(event, client)
+    }
+
+    fn rejects_credit(&self, counterpart: Option<&ClientState>, amount: Decimal) -> Result<bool, Error> {
+        (**self).rejects_credit(counterpart, amount)
+    }
+}
+
+/// An ordered collection of [`Rule`]s, which rejects an event as soon as one of its rules does.
+#[derive(Default)]
+pub struct Rules(Vec<Box<dyn Rule>>);
+
+impl Rules {
+    /// Adds a rule that is evaluated after all previously added rules.
+    pub fn push(&mut self, rule: Box<dyn Rule>) {
+        self.0.push(rule);
+    }
+
+    /// Returns `true` if any of the rules rejects `event`, or the credit that it makes to another client, in the
+    /// current `state`.
+    pub fn rejects(&self, event: &Event, state: &State) -> Result<bool, Error> {
+        let client = state.client_state(event.client());
+        let credit = state.credited_counterpart(event);
+        for rule in &self.0 {
+            if rule.rejects(event, client)? {
+                return Ok(true);
+            }
+            if let Some((counterpart, amount)) = credit {
+                if rule.rejects_credit(state.client_state(counterpart), amount)? {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::ClientId;
+
+    struct RejectClient(ClientId);
+
+    impl Rule for RejectClient {
+        fn rejects(&self, event: &Event, _: Option<&ClientState>) -> Result<bool, Error> {
+            Ok(event.client() == self.0)
+        }
+    }
+
+    #[test]
+    fn first_rejection_wins() -> Result<(), Error> {
+        let state = State::new();
+        let mut rules = Rules::default();
+        assert!(!rules.rejects(&Event::deposit(1, 0, dec!(1)), &state)?);
+
+        rules.push(Box::new(RejectClient(1)));
+        rules.push(Box::new(RejectClient(2)));
+        assert!(rules.rejects(&Event::deposit(1, 0, dec!(1)), &state)?);
+        assert!(rules.rejects(&Event::deposit(2, 0, dec!(1)), &state)?);
+        assert!(!rules.rejects(&Event::deposit(3, 0, dec!(1)), &state)?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+//! Validation rules written as [rhai](https://rhai.rs) scripts.
+//!
+//! A script is a single expression that evaluates to a boolean, where `true` rejects the event. Two object maps are in
+//! scope:
+//!
+//! * `event` with the fields `type`, `client`, `tx` and `amount` (`()` for events without a transaction or amount).
+//! * `client` with the fields `available`, `held`, `total` and `locked` of the client before the event is applied.
+//!
+//! For example `event.type == "withdrawal" && event.amount > client.available / 2` rejects withdrawals of more than
+//! half of the available funds.
+//!
+//! Scripts run with conservative limits on their operations, call depth and string sizes, so that a script can't hang
+//! the engine or exhaust the memory of the host. A script that exceeds them fails with [`Error::Limit`].
+
+use rhai::{Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+use thiserror::Error;
+
+use crate::{
+    client::ClientState,
+    event::Event,
+    rules::{self, Rule},
+};
+
+/// Errors that can happen while compiling or evaluating a script.
+///
+/// The messages of rhai are kept as strings because its error types are neither `Send` nor `Sync`.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The script is not valid rhai.
+    #[error("failed to compile rule script: {0}")]
+    Compile(String),
+    /// The script failed at runtime or did not evaluate to a boolean.
+    #[error("failed to evaluate rule script: {0}")]
+    Eval(String),
+    /// The script exceeded one of the limits of the engine.
+    #[error("rule script exceeded its limits: {0}")]
+    Limit(String),
+}
+
+/// The number of operations that a script may run for a single event.
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// The depth of nested function calls.
+const MAX_CALL_LEVELS: usize = 32;
+
+/// The length of strings, in bytes.
+const MAX_STRING_SIZE: usize = 4096;
+
+/// A [`Rule`] backed by a compiled rhai script.
+pub struct ScriptRule {
+    engine: Engine,
+    ast: AST,
+}
+
+impl ScriptRule {
+    /// Compiles the script in `source`.
+    pub fn compile(source: &str) -> Result<Self, Error> {
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(MAX_OPERATIONS)
+            .set_max_call_levels(MAX_CALL_LEVELS)
+            .set_max_string_size(MAX_STRING_SIZE);
+        let ast = engine.compile(source).map_err(|err| Error::Compile(err.to_string()))?;
+        Ok(Self { engine, ast })
+    }
+}
+
+impl Rule for ScriptRule {
+    fn rejects(&self, event: &Event, client: Option<&ClientState>) -> Result<bool, rules::Error> {
+        let mut scope = Scope::new();
+        scope.push_constant("event", event_map(event));
+        scope.push_constant("client", client_map(client.cloned().unwrap_or_default()));
+
+        let rejects = self
+            .engine
+            .eval_ast_with_scope::<bool>(&mut scope, &self.ast)
+            .map_err(|err| match *err {
+                EvalAltResult::ErrorTooManyOperations(..)
+                | EvalAltResult::ErrorStackOverflow(..)
+                | EvalAltResult::ErrorDataTooLarge(..) => Error::Limit(err.to_string()),
+                _ => Error::Eval(err.to_string()),
+            })?;
+        Ok(rejects)
+    }
+}
+
+fn event_map(event: &Event) -> Map {
+    let (ty, amount) = match *event {
+        Event::Deposit { amount, .. } => ("deposit", Dynamic::from_decimal(amount)),
+        Event::Withdrawal { amount, .. } => ("withdrawal", Dynamic::from_decimal(amount)),
+        Event::Transfer { amount, .. } => ("transfer", Dynamic::from_decimal(amount)),
+        Event::Dispute { .. } => ("dispute", Dynamic::UNIT),
+        Event::Resolve { .. } => ("resolve", Dynamic::UNIT),
+        Event::Chargeback { .. } => ("chargeback", Dynamic::UNIT),
+        Event::Unlock { .. } => ("unlock", Dynamic::UNIT),
+    };
+    let tx = event.tx().map_or(Dynamic::UNIT, |tx| Dynamic::from_int(tx.into()));
+
+    let mut map = Map::new();
+    map.insert("type".into(), ty.into());
+    map.insert("client".into(), Dynamic::from_int(event.client().into()));
+    map.insert("tx".into(), tx);
+    map.insert("amount".into(), amount);
+    map
+}
+
+fn client_map(client: ClientState) -> Map {
+    let mut map = Map::new();
+    map.insert("available".into(), Dynamic::from_decimal(client.available()));
+    map.insert("held".into(), Dynamic::from_decimal(client.held()));
+    map.insert("total".into(), Dynamic::from_decimal(client.total()));
+    map.insert("locked".into(), client.frozen().into());
+    map
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn large_withdrawals() -> Result<(), rules::Error> {
+        let rule = ScriptRule::compile(r#"event.type == "withdrawal" && event.amount > 10000"#)?;
+        assert!(rule.rejects(&Event::withdrawal(0, 0, dec!(10000.01)), None)?);
+        assert!(!rule.rejects(&Event::withdrawal(0, 0, dec!(10000)), None)?);
+        assert!(!rule.rejects(&Event::deposit(0, 0, dec!(20000)), None)?);
+        assert!(!rule.rejects(&Event::dispute(0, 0), None)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn client_fields() -> Result<(), rules::Error> {
+        let rule = ScriptRule::compile("event.amount > client.available / 2")?;
+        let client = ClientState::new(None, dec!(100), dec!(0));
+        assert!(rule.rejects(&Event::withdrawal(0, 0, dec!(51)), Some(&client))?);
+        assert!(!rule.rejects(&Event::withdrawal(0, 0, dec!(50)), Some(&client))?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_scripts() -> Result<(), rules::Error> {
+        assert!(matches!(ScriptRule::compile("event.amount >"), Err(Error::Compile(_))));
+        let rule = ScriptRule::compile("event.amount")?;
+        assert!(rule.rejects(&Event::deposit(0, 0, dec!(1)), None).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn limits() -> Result<(), rules::Error> {
+        let event = Event::deposit(0, 0, dec!(1));
+        let scripts = [
+            "loop {}",
+            "fn deeper(n) { deeper(n + 1) } deeper(0)",
+            r#"let s = "x"; loop { s += s }"#,
+        ];
+        for script in scripts {
+            let rule = ScriptRule::compile(script)?;
+            let result = rule.rejects(&event, None);
+            assert!(
+                matches!(result, Err(rules::Error::Script(Error::Limit(_)))),
+                "{script}: {result:?}"
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+//! A self-test that runs a synthetic dataset through the whole pipeline and checks the results.
+//!
+//! The dataset is generated in memory as CSV, so the test covers parsing as well as processing. It contains deposits,
+//! withdrawals (some of which exceed the available funds) and disputes that are resolved right away, which makes the
+//! expected balance of every client known up front.
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::{
+    source::{self, CsvSource, EventSource},
+    state::{self, State},
+    ClientId, TxId,
+};
+
+/// The number of distinct clients in the generated dataset.
+const CLIENTS: u64 = 1000;
+
+/// Errors that can happen during the self-test.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The number of events exceeds the range of transaction ids.
+    #[error("too many events: {0}")]
+    TooManyEvents(u64),
+    /// The generated input could not be read.
+    #[error(transparent)]
+    Source(#[from] source::Error),
+    /// An event could not be applied.
+    #[error(transparent)]
+    State(#[from] state::Error),
+    /// The balance of a client differs from the expected one.
+    #[error("client `{client}` has {found} available funds, expected {expected}")]
+    Balance {
+        /// The client with the wrong balance.
+        client: ClientId,
+        /// The expected available funds.
+        expected: Decimal,
+        /// The computed available funds.
+        found: Decimal,
+    },
+    /// An invariant of the state does not hold.
+    #[error("invariant violated: {0}")]
+    Invariant(String),
+}
+
+/// The result of a successful self-test.
+#[derive(Clone, Copy, Debug)]
+pub struct Report {
+    /// The number of processed events.
+    pub events: u64,
+    /// The number of clients in the resulting state.
+    pub clients: usize,
+    /// The time it took to parse and apply all events.
+    pub elapsed: Duration,
+}
+
+impl Report {
+    /// Returns the number of events that were processed per second.
+    pub fn throughput(&self) -> f64 {
+        self.events as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// A small xorshift generator, so the dataset is the same on every run without depending on a random number crate.
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Returns an amount between 0.0001 and 1000 with up to four decimal places.
+    fn amount(&mut self) -> Decimal {
+        Decimal::new((self.next() % 10_000_000 + 1) as i64, 4)
+    }
+}
+
+/// Generates about `events` events as CSV and returns them together with the expected available funds per client.
+fn generate(events: u64) -> Result<(String, BTreeMap<ClientId, Decimal>), Error> {
+    if events > u64::from(TxId::MAX) {
+        return Err(Error::TooManyEvents(events));
+    }
+
+    let mut random = Random(0x2545_f491_4f6c_dd1d);
+    let mut expected = BTreeMap::new();
+    let mut csv = String::from("type,client,tx,amount\n");
+
+    let mut tx: TxId = 0;
+    while u64::from(tx) < events {
+        let client = (random.next() % CLIENTS) as ClientId;
+        let available: &mut Decimal = expected.entry(client).or_default();
+        let amount = random.amount();
+
+        csv += &match random.next() % 10 {
+            0..=5 => {
+                *available += amount;
+                format!("deposit,{client},{tx},{amount}\n")
+            }
+            6..=8 => {
+                if amount <= *available {
+                    *available -= amount;
+                }
+                format!("withdrawal,{client},{tx},{amount}\n")
+            }
+            _ => {
+                *available += amount;
+                format!("deposit,{client},{tx},{amount}\ndispute,{client},{tx},0\nresolve,{client},{tx},0\n")
+            }
+        };
+        tx += 1;
+    }
+
+    Ok((csv, expected))
+}
+
+/// Runs the self-test with about `events` events.
+pub fn run(events: u64) -> Result<Report, Error> {
+    let (csv, expected) = generate(events)?;
+
+    let start = Instant::now();
+    let mut source = CsvSource::new(csv.as_bytes());
+    let mut state = State::new();
+    while let Some(event) = source.next_event() {
+        state.handle(event?)?;
+    }
+    let elapsed = start.elapsed();
+
+    let mut total = Decimal::ZERO;
+    for (&client, &expected) in &expected {
+        let (available, held) = state
+            .client_state(client)
+            .map_or((Decimal::ZERO, Decimal::ZERO), |state| {
+                (state.available(), state.held())
+            });
+        if available != expected {
+            return Err(Error::Balance {
+                client,
+                expected,
+                found: available,
+            });
+        }
+        if !held.is_zero() {
+            return Err(Error::Invariant(format!("client `{client}` still holds {held}")));
+        }
+        total += available;
+    }
+    if state.client_states().count() != expected.len() {
+        return Err(Error::Invariant("unexpected clients in the state".into()));
+    }
+    if total != state.float().balance() {
+        let balance = state.float().balance();
+        return Err(Error::Invariant(format!(
+            "clients hold {total} in total, but the float balance is {balance}"
+        )));
+    }
+
+    Ok(Report {
+        events: state.next_index(),
+        clients: expected.len(),
+        elapsed,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn passes() -> Result<(), Error> {
+        let report = run(10_000)?;
+        assert!(report.events >= 10_000);
+        assert_eq!(report.clients, CLIENTS as usize);
+
+        Ok(())
+    }
+}
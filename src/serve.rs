@@ -0,0 +1,573 @@
+//! A TCP server that applies the events of all its connections to a single state, see `txh serve`.
+//!
+//! [`State`] is not safe to share between threads, so it is owned by an engine thread. Every connection has a thread
+//! that parses its lines and sends the events to the engine over a bounded channel, and the engine applies them in the
+//! order in which they arrive. Events of one connection keep their order, events of different connections are
+//! interleaved. A connection that sends faster than the engine applies waits until there is room in the channel. The
+//! engine checks the events against the same limits and rules as a run of the command line tool, see [`Config`].
+//!
+//! The engine never writes to a connection itself: it hands the answers to a writer thread of the connection over a
+//! small bounded channel, so that a client that doesn't read its answers can't stall the other connections. Such a
+//! client is disconnected once its channel is full or an answer couldn't be written for a while. The number of
+//! connections, and with it the number of threads, is capped, and further connections are refused.
+//!
+//! The server uses plain threads instead of an async runtime like tokio: the engine is a single thread either way, the
+//! connection cap bounds the number of threads, and the crate doesn't have to pull in a runtime for a single command.
+//!
+//! A connection sends one record per line in the input format of the server, starting with the header for CSV. A line
+//! `#snapshot` asks for the current client states, which are written back once all events that the connection sent
+//! before were applied, in the same format and followed by an empty line. Malformed records and events that can't be
+//! applied are answered with a line `error: <message>`, and rejected events with a line `rejected: tx <tx>: <reason>`
+//! (`client <client>` for events without a transaction), where the reason is the one of the rejection report. Applied
+//! events are not answered.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use txh::{
+    event::Event,
+    precision::{self, InputPrecision, Rounding},
+    rules::{Rule, Rules},
+    source::{self, EventSource, Grouped},
+    state::Outcome,
+    GroupId, State,
+};
+
+use crate::{
+    follow::Feed,
+    output::{self, RecordWriter},
+    Reports,
+};
+
+/// How long an answer may stay unwritten before its connection is closed.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The number of requests that can be queued for the engine before the connections wait.
+const CHANNEL_CAPACITY: usize = 4096;
+
+/// The number of answers that can be queued for a connection before it is closed.
+///
+/// Snapshots can be large, so this is kept small.
+const REPLY_CAPACITY: usize = 16;
+
+/// How long the listener waits after a connection could not be accepted, so that e.g. running out of file descriptors
+/// doesn't turn into a busy loop.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// How the server checks and applies the events of its connections.
+pub struct Config {
+    /// Format of the events that the connections send.
+    pub format: source::Format,
+    /// The number of connections that are served at the same time.
+    pub max_connections: usize,
+    /// How amounts with too many decimal places are handled.
+    pub input_precision: InputPrecision,
+    /// How amounts are rounded to four decimal places.
+    pub rounding: Rounding,
+    /// The state that the events are applied to, with its limits and policies.
+    pub state: State,
+    /// Rules that every event has to pass before it is applied, in order.
+    pub rules: Vec<Box<dyn Rule + Send>>,
+}
+
+/// The answers to a connection, which are written by its writer thread.
+#[derive(Clone)]
+struct Reply {
+    answers: SyncSender<Vec<u8>>,
+    stream: Arc<TcpStream>,
+    /// Whether the connection is gone, so that e.g. its snapshots aren't built anymore.
+    closed: Arc<AtomicBool>,
+}
+
+impl Reply {
+    /// Hands `bytes` to the writer thread, or closes the connection if it has too many unwritten answers.
+    ///
+    /// A connection that is gone is not answered anymore.
+    fn send(&self, bytes: Vec<u8>) {
+        match self.answers.try_send(bytes) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                self.closed.store(true, Ordering::SeqCst);
+                let _ = self.stream.shutdown(Shutdown::Both);
+            }
+            Err(TrySendError::Disconnected(_)) => self.closed.store(true, Ordering::SeqCst),
+        }
+    }
+
+    /// Returns whether the connection is gone.
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Writes `line` and a newline.
+    fn line(&self, line: &str) {
+        self.send(format!("{line}\n").into_bytes());
+    }
+}
+
+/// What a connection asks the engine for.
+enum Request {
+    /// Apply an event or an atomic group.
+    Events(Grouped),
+    /// Write the client states back to the connection.
+    Snapshot,
+}
+
+/// A request together with the connection that sent it.
+struct Message {
+    request: Request,
+    reply: Reply,
+}
+
+/// Frees a place for another connection when it is dropped.
+struct Slot(Arc<AtomicUsize>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Accepts connections on `listener` and applies their events according to `config`.
+///
+/// Connections that can't be accepted are reported on stderr and don't stop the server. Connections beyond
+/// [`Config::max_connections`] are answered with an error and closed.
+pub fn run(listener: TcpListener, config: Config) {
+    let Config {
+        format,
+        max_connections,
+        input_precision,
+        rounding,
+        state,
+        rules,
+    } = config;
+    let (engine, messages) = mpsc::sync_channel(CHANNEL_CAPACITY);
+    thread::spawn(move || run_engine(messages, state, rules, format, rounding));
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                eprintln!("Warning: failed to accept a connection: {err}");
+                thread::sleep(ACCEPT_BACKOFF);
+                continue;
+            }
+        };
+        if connections.fetch_add(1, Ordering::SeqCst) >= max_connections {
+            connections.fetch_sub(1, Ordering::SeqCst);
+            // The refused client may not read either.
+            let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+            let _ = stream.write_all(b"error: too many connections\n");
+            continue;
+        }
+        let slot = Slot(Arc::clone(&connections));
+        let engine = engine.clone();
+        thread::spawn(move || {
+            let _slot = slot;
+            let peer = stream
+                .peer_addr()
+                .map_or_else(|_| "unknown".into(), |peer| peer.to_string());
+            let checks = (input_precision, rounding);
+            if let Err(err) = handle_connection(stream, format, checks, engine) {
+                eprintln!("Warning: connection from {peer} failed: {err}");
+            }
+        });
+    }
+}
+
+/// Applies the events of all connections to `state` if the `rules` accept them, until all connections and the
+/// listener are gone.
+///
+/// Snapshots are written in the input `format`, with amounts rounded with `rounding`.
+fn run_engine(
+    messages: Receiver<Message>,
+    mut state: State,
+    rules: Vec<Box<dyn Rule + Send>>,
+    format: source::Format,
+    rounding: Rounding,
+) {
+    let mut all = Rules::default();
+    for rule in rules {
+        all.push(rule);
+    }
+    let rules = all;
+    for Message { request, reply } in messages {
+        let (names, result) = match request {
+            Request::Events(Grouped::Single(event)) => {
+                let name = name(&event);
+                let result = crate::handle_event(&rules, &mut state, &mut Reports::default(), event, false);
+                (vec![name], result.map(|outcome| vec![outcome]))
+            }
+            Request::Events(Grouped::Group(events)) => {
+                let names = events.iter().map(name).collect();
+                let result = crate::handle_group(&rules, &mut state, &mut Reports::default(), events, false);
+                (names, result)
+            }
+            Request::Events(Grouped::Incomplete(events)) => {
+                let names = events.iter().map(name).collect();
+                let result = crate::handle_group(&rules, &mut state, &mut Reports::default(), events, true);
+                (names, result)
+            }
+            // The events of a connection that is gone are still applied, but nobody waits for its snapshots.
+            Request::Snapshot if reply.is_closed() => continue,
+            Request::Snapshot => {
+                write_snapshot(&state, format, rounding, &reply);
+                continue;
+            }
+        };
+        match result {
+            Ok(outcomes) => {
+                for (name, outcome) in names.iter().zip(outcomes) {
+                    if let Outcome::Rejected(rejection) = outcome {
+                        reply.line(&format!("rejected: {name}: {}", rejection.reason()));
+                    }
+                }
+            }
+            Err(err) => reply.line(&format!("error: {err:#}")),
+        }
+    }
+}
+
+/// Returns how an answer refers to `event`.
+fn name(event: &Event) -> String {
+    match event.tx() {
+        Some(tx) => format!("tx {tx}"),
+        None => format!("client {}", event.client()),
+    }
+}
+
+/// Writes the client states to `reply` in `format`, followed by an empty line.
+fn write_snapshot(state: &State, format: source::Format, rounding: Rounding, reply: &Reply) {
+    let format = match format {
+        source::Format::Csv => output::Format::Csv,
+        source::Format::Ndjson => output::Format::Json,
+    };
+    let mut writer = RecordWriter::new(format, true, Vec::new());
+    let written = crate::client_records(state.client_states_sorted(), rounding)
+        .try_for_each(|record| writer.write(record))
+        .and_then(|()| writer.finish());
+    match written {
+        Ok(mut snapshot) => {
+            snapshot.push(b'\n');
+            reply.send(snapshot);
+        }
+        Err(err) => reply.line(&format!("error: {err}")),
+    }
+}
+
+/// Writes the `answers` to `stream` until the connection is gone or an answer could not be written in time.
+///
+/// The connection is then shut down, which also ends its reading thread.
+fn write_answers(mut stream: TcpStream, answers: Receiver<Vec<u8>>) {
+    for answer in answers {
+        if stream.write_all(&answer).is_err() {
+            let _ = stream.shutdown(Shutdown::Both);
+            return;
+        }
+    }
+}
+
+/// Reads the lines of `stream` and sends its events and commands to the `engine`.
+///
+/// The amounts are checked with the input precision and rounding of `checks`. The rows of an atomic group are sent
+/// once the next line or the end of the connection shows that the group is complete.
+fn handle_connection(
+    stream: TcpStream,
+    format: source::Format,
+    checks: (InputPrecision, Rounding),
+    engine: SyncSender<Message>,
+) -> io::Result<()> {
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    let (answers, receiver) = mpsc::sync_channel(REPLY_CAPACITY);
+    let reply = Reply {
+        answers,
+        stream: Arc::new(stream.try_clone()?),
+        closed: Arc::default(),
+    };
+    let writer = stream.try_clone()?;
+    thread::spawn(move || write_answers(writer, receiver));
+    let send = |request| {
+        let reply = reply.clone();
+        engine
+            .send(Message { request, reply })
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the engine stopped"))
+    };
+
+    let feed = Feed::default();
+    let (input_precision, rounding) = checks;
+    let mut source = precision::Checked::new(
+        crate::read_source(Box::new(feed.clone()), format, None),
+        input_precision,
+        rounding,
+    );
+    let mut header = format == source::Format::Csv;
+    // The current group, and whether one of its lines was malformed.
+    let mut group: Option<(GroupId, Vec<Event>, bool)> = None;
+    let flush = |group: &mut Option<(GroupId, Vec<Event>, bool)>| match group.take() {
+        Some((_, events, false)) => send(Request::Events(Grouped::Group(events))),
+        Some((_, events, true)) => send(Request::Events(Grouped::Incomplete(events))),
+        None => Ok(()),
+    };
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        // The source reads the header together with the first record.
+        if header {
+            feed.push(format!("{line}\n").as_bytes());
+            header = false;
+            continue;
+        }
+        if let Some(command) = line.strip_prefix('#') {
+            flush(&mut group)?;
+            match command.trim() {
+                "snapshot" => send(Request::Snapshot)?,
+                command => reply.line(&format!("error: unknown command `{command}`")),
+            }
+            continue;
+        }
+
+        feed.push(format!("{line}\n").as_bytes());
+        let event = match source.next_event() {
+            Some(Ok(event)) => event,
+            Some(Err(err)) => {
+                reply.line(&format!("error: {err}"));
+                // A malformed line of a group rejects the whole group.
+                match (source.group(), &mut group) {
+                    (Some(next), Some((current, _, incomplete))) if next == *current => *incomplete = true,
+                    (Some(next), _) => {
+                        flush(&mut group)?;
+                        group = Some((next, Vec::new(), true));
+                    }
+                    (None, _) => {}
+                }
+                continue;
+            }
+            None => continue,
+        };
+        match (source.group(), &mut group) {
+            (Some(next), Some((current, events, _))) if next == *current => events.push(event),
+            (next, _) => {
+                flush(&mut group)?;
+                match next {
+                    Some(next) => group = Some((next, vec![event], false)),
+                    None => send(Request::Events(Grouped::Single(event)))?,
+                }
+            }
+        }
+    }
+    flush(&mut group)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Read, net::SocketAddr};
+
+    use txh::state::Limits;
+
+    use super::*;
+
+    /// Returns a configuration without limits or rules.
+    fn config(format: source::Format) -> Config {
+        Config {
+            format,
+            max_connections: 64,
+            input_precision: InputPrecision::default(),
+            rounding: Rounding::default(),
+            state: State::new(),
+            rules: Vec::new(),
+        }
+    }
+
+    /// Starts a server with `config` on a free port and returns its address.
+    fn start(config: Config) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        thread::spawn(move || run(listener, config));
+        Ok(address)
+    }
+
+    /// Sends `lines` to a new server and returns everything that it answers until the connection is closed.
+    fn exchange(format: source::Format, lines: &str) -> io::Result<String> {
+        exchange_with(start(config(format))?, lines)
+    }
+
+    /// Sends `lines` to the server at `address` and returns everything that it answers up to the last snapshot.
+    fn exchange_with(address: SocketAddr, lines: &str) -> io::Result<String> {
+        let mut stream = TcpStream::connect(address)?;
+        stream.write_all(lines.as_bytes())?;
+        stream.shutdown(std::net::Shutdown::Write)?;
+        let mut answer = String::new();
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        // The server keeps the connection open, so everything up to the empty line after the last snapshot is read.
+        let mut reader = BufReader::new(stream);
+        while !answer.ends_with("\n\n") {
+            if reader.read_line(&mut answer)? == 0 {
+                break;
+            }
+        }
+        Ok(answer)
+    }
+
+    #[test]
+    fn csv() -> io::Result<()> {
+        let answer = exchange(
+            source::Format::Csv,
+            "type,client,tx,amount,group\n\
+             deposit,1,1,10,\n\
+             deposit,x,2,1,\n\
+             deposit,2,3,5,7\n\
+             withdrawal,2,4,1,7\n\
+             deposit,3,5,5,9\n\
+             deposit,3,6,x,9\n\
+             withdrawal,1,7,20,\n\
+             #snapshot\n",
+        )?;
+        let lines: Vec<_> = answer.lines().collect();
+        assert!(lines[0].starts_with("error: "), "{answer}");
+        assert!(lines[1].starts_with("error: "), "{answer}");
+        assert_eq!(
+            lines[2..5],
+            [
+                "rejected: tx 5: group_incomplete",
+                "rejected: tx 7: insufficient_funds",
+                "client,available,held,total,locked,lock_reason,locked_at"
+            ]
+        );
+        assert!(answer.ends_with("\n1,10,0,10,false,,\n2,4,0,4,false,,\n\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn ndjson() -> io::Result<()> {
+        let answer = exchange(
+            source::Format::Ndjson,
+            "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"2.5\"}\n#status\n#snapshot\n",
+        )?;
+        let expected = "error: unknown command `status`\n\
+                        {\"client\":1,\"available\":\"2.5\",\"held\":\"0\",\"total\":\"2.5\",\"locked\":false,\
+                        \"lock_reason\":null,\"locked_at\":null}\n\n";
+        assert_eq!(answer, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn limits() -> io::Result<()> {
+        let policy = "max_deposit = 5"
+            .parse::<txh::policy::Policy>()
+            .map_err(io::Error::other)?;
+        let config = Config {
+            input_precision: InputPrecision::Reject,
+            state: State::with_limits(Limits {
+                max_clients: Some(1),
+                max_transactions: None,
+            }),
+            rules: vec![Box::new(policy)],
+            ..config(source::Format::Csv)
+        };
+        let answer = exchange_with(
+            start(config)?,
+            "type,client,tx,amount\ndeposit,1,1,5\ndeposit,1,2,6\ndeposit,1,3,0.00001\ndeposit,2,4,1\n#snapshot\n",
+        )?;
+        // Malformed records are answered by the connection and the rest by the engine, so their order is open.
+        let lines: Vec<_> = answer.lines().collect();
+        assert!(lines[..3].contains(&"rejected: tx 2: rule"), "{answer}");
+        assert!(lines[..3]
+            .iter()
+            .any(|line| line.starts_with("error: ") && line.contains("decimal places")));
+        assert!(lines[..3]
+            .iter()
+            .any(|line| line.starts_with("error: ") && line.contains("clients")));
+        assert!(answer.ends_with("\n1,5,0,5,false,,\n\n"), "{answer}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn max_connections() -> io::Result<()> {
+        let address = start(Config {
+            max_connections: 1,
+            ..config(source::Format::Csv)
+        })?;
+        let first = TcpStream::connect(address)?;
+        // The first connection holds the only place until it is closed.
+        let mut answer = String::new();
+        TcpStream::connect(address)?.read_to_string(&mut answer)?;
+        assert_eq!(answer, "error: too many connections\n");
+        drop(first);
+
+        // The place is only freed once the server noticed that the connection is gone, and a refused connection may
+        // be reset.
+        for _ in 0..50 {
+            match exchange_with(address, "type,client,tx,amount\ndeposit,1,1,1\n#snapshot\n") {
+                Ok(result) if !result.starts_with("error") => {
+                    answer = result;
+                    break;
+                }
+                _ => thread::sleep(Duration::from_millis(20)),
+            }
+        }
+        assert!(answer.ends_with("\n1,1,0,1,false,,\n\n"), "{answer}");
+
+        Ok(())
+    }
+
+    /// Returns a connection to `address` that sent deposits for 1000 clients and then asks for 2000 snapshots.
+    fn request_snapshots(address: SocketAddr) -> io::Result<TcpStream> {
+        let mut lines = String::from("type,client,tx,amount\n");
+        for client in 1..=1000 {
+            lines.push_str(&format!("deposit,{client},{client},1\n"));
+        }
+        lines.push_str(&"#snapshot\n".repeat(2000));
+        let mut stream = TcpStream::connect(address)?;
+        stream.write_all(lines.as_bytes())?;
+        Ok(stream)
+    }
+
+    #[test]
+    fn slow_reader() -> io::Result<()> {
+        let address = start(config(source::Format::Csv))?;
+
+        // A connection that asks for large snapshots but never reads them.
+        let slow = request_snapshots(address)?;
+        // Give the snapshots of the slow connection a head start in the engine.
+        thread::sleep(Duration::from_millis(100));
+
+        let answer = exchange_with(address, "type,client,tx,amount\ndeposit,1000,1000,1\n#snapshot\n")?;
+        assert!(answer.ends_with("1000,1,0,1,false,,\n\n"), "{answer}");
+        drop(slow);
+
+        Ok(())
+    }
+
+    #[test]
+    fn unread_answers() -> io::Result<()> {
+        let address = start(config(source::Format::Csv))?;
+        let slow = request_snapshots(address)?;
+        // Nobody reads the answers, so they pile up and the connection is closed.
+        thread::sleep(Duration::from_secs(1));
+
+        slow.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let mut answer = Vec::new();
+        match BufReader::new(slow).read_to_end(&mut answer) {
+            Err(err) if err.kind() == io::ErrorKind::ConnectionReset => {}
+            result => _ = result?,
+        }
+        let snapshots = answer.windows(2).filter(|window| window == b"\n\n").count();
+        assert!(snapshots < 2000, "{snapshots}");
+
+        Ok(())
+    }
+}
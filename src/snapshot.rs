@@ -0,0 +1,34 @@
+//! A versioned on-disk format of a [`State`](crate::State), so that an interrupted run can be resumed.
+//!
+//! A snapshot starts with the line `txh-snapshot <version>`, followed by the state as JSON. The version is bumped
+//! whenever the layout of the state changes, so that an old snapshot is refused instead of being misread.
+
+use std::io;
+
+use thiserror::Error;
+
+/// The first word of every snapshot.
+pub(crate) const MAGIC: &str = "txh-snapshot";
+
+/// The version of the snapshot format that this build reads and writes.
+pub const VERSION: u32 = 7;
+
+/// Errors that can happen while writing or reading a snapshot.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The snapshot could not be written or read.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The state could not be encoded or decoded.
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    /// The input doesn't start with a snapshot header.
+    #[error("not a snapshot")]
+    NotASnapshot,
+    /// The snapshot was written in another version of the format.
+    #[error("unsupported snapshot version {found}, expected {VERSION}")]
+    Version {
+        /// The version of the snapshot.
+        found: u32,
+    },
+}
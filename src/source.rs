@@ -0,0 +1,768 @@
+//! Sources that produce the events of the input stream.
+//!
+//! The command line tool reads CSV files with [`CsvSource`] and newline-delimited JSON with [`NdjsonSource`]. Other
+//! inputs, e.g. a message queue or a database cursor, can be fed into the same pipeline by implementing
+//! [`EventSource`].
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fmt,
+    io::{self, BufRead},
+    num::IntErrorKind,
+    rc::Rc,
+    str::{self, FromStr},
+};
+
+use thiserror::Error;
+
+use crate::{
+    event::Event,
+    idmap::{self, IdMap},
+    records::{self, EventCsvRecord, EventJsonRecord},
+    ClientId, GroupId,
+};
+
+/// Errors that can happen while reading events from a source.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The input is not valid CSV or a row doesn't match [`EventCsvRecord`].
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    /// A line of NDJSON input is not a valid [`EventJsonRecord`], lines start at one.
+    #[error("line {line}: {source}")]
+    Json {
+        /// The line of the invalid record.
+        line: usize,
+        /// The reason why the record is invalid.
+        source: serde_json::Error,
+    },
+    /// Reading the input failed.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// A record can't be converted into an [`Event`].
+    #[error(transparent)]
+    Record(#[from] records::Error),
+    /// The client of a record can't be mapped to a client id.
+    #[error(transparent)]
+    IdMap(#[from] idmap::Error),
+    /// An error of a custom source.
+    #[error(transparent)]
+    Other(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl Error {
+    /// Returns whether only a single record is malformed, so that the source can continue with the next one.
+    ///
+    /// Failures to read the input are not malformed records, as retrying them would most likely fail again.
+    pub fn is_malformed(&self) -> bool {
+        match self {
+            Error::Csv(err) => !matches!(err.kind(), csv::ErrorKind::Io(_)),
+            Error::Json { .. } | Error::Record(_) => true,
+            Error::Io(_) | Error::IdMap(_) | Error::Other(_) => false,
+        }
+    }
+}
+
+/// The supported input formats.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// CSV with a header row, see [`CsvSource`].
+    #[default]
+    Csv,
+    /// One JSON object per line, see [`NdjsonSource`].
+    Ndjson,
+}
+
+impl FromStr for Format {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "ndjson" => Ok(Format::Ndjson),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A stream of events that ends once the source is exhausted.
+pub trait EventSource {
+    /// Returns the next event, or `None` if there are no more events.
+    ///
+    /// A source may continue after returning an error, but callers usually stop at the first one.
+    fn next_event(&mut self) -> Option<Result<Event, Error>>;
+
+    /// Returns the line of the input that the last event or error was read from, if the source knows it.
+    ///
+    /// Lines start at one.
+    fn line(&self) -> Option<u64> {
+        None
+    }
+
+    /// Returns the atomic group of the last event, if the source supports groups and the event belongs to one.
+    fn group(&self) -> Option<GroupId> {
+        None
+    }
+
+    /// Returns whether a record of the atomic group of the last event was skipped as malformed, see [`Lenient`].
+    ///
+    /// The remaining events of such a group must not be applied either.
+    fn group_incomplete(&self) -> bool {
+        false
+    }
+}
+
+impl<S: EventSource + ?Sized> EventSource for &mut S {
+    fn next_event(&mut self) -> Option<Result<Event, Error>> {
+        (**self).next_event()
+    }
+
+    fn line(&self) -> Option<u64> {
+        (**self).line()
+    }
+
+    fn group(&self) -> Option<GroupId> {
+        (**self).group()
+    }
+
+    fn group_incomplete(&self) -> bool {
+        (**self).group_incomplete()
+    }
+}
+
+impl<S: EventSource + ?Sized> EventSource for Box<S> {
+    fn next_event(&mut self) -> Option<Result<Event, Error>> {
+        (**self).next_event()
+    }
+
+    fn line(&self) -> Option<u64> {
+        (**self).line()
+    }
+
+    fn group(&self) -> Option<GroupId> {
+        (**self).group()
+    }
+
+    fn group_incomplete(&self) -> bool {
+        (**self).group_incomplete()
+    }
+}
+
+/// Reads the events of several sources one after the other.
+pub struct Chain<S: EventSource> {
+    sources: VecDeque<S>,
+}
+
+impl<S: EventSource> Chain<S> {
+    /// Creates a source that yields all events of the first source, then all of the second and so on.
+    pub fn new(sources: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            sources: sources.into_iter().collect(),
+        }
+    }
+}
+
+impl<S: EventSource> EventSource for Chain<S> {
+    fn next_event(&mut self) -> Option<Result<Event, Error>> {
+        loop {
+            match self.sources.front_mut()?.next_event() {
+                Some(event) => return Some(event),
+                None => {
+                    self.sources.pop_front();
+                }
+            }
+        }
+    }
+
+    fn line(&self) -> Option<u64> {
+        // A source is only removed after it is exhausted, so the last event came from the current one.
+        self.sources.front()?.line()
+    }
+
+    fn group(&self) -> Option<GroupId> {
+        self.sources.front()?.group()
+    }
+
+    fn group_incomplete(&self) -> bool {
+        self.sources.front().is_some_and(EventSource::group_incomplete)
+    }
+}
+
+/// A record that [`Lenient`] skipped.
+#[derive(Debug)]
+pub struct Malformed {
+    /// The line of the record, if the source knows it.
+    pub line: Option<u64>,
+    /// The reason why the record was skipped.
+    pub error: Error,
+}
+
+impl fmt::Display for Malformed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, &self.error) {
+            // The other errors already name their position.
+            (Some(line), Error::Record(err)) => write!(f, "line {line}: {err}"),
+            _ => write!(f, "{}", self.error),
+        }
+    }
+}
+
+/// Skips the malformed records of a source and passes them to a callback instead of returning them.
+///
+/// Errors that are not [malformed](Error::is_malformed) records are still returned. If a skipped record belongs to an
+/// atomic group, the other events of the group are still returned, but marked as
+/// [incomplete](EventSource::group_incomplete), so that the group can be rejected as a whole. The events of a group are
+/// therefore read ahead until the group ends.
+pub struct Lenient<S, F> {
+    source: S,
+    on_malformed: F,
+    /// The events of the current group that were read ahead, with their lines.
+    pending: VecDeque<(Event, Option<u64>)>,
+    /// The record after the current group, which was read to find the end of the group.
+    next: Option<Read>,
+    line: Option<u64>,
+    group: Option<GroupId>,
+    incomplete: bool,
+}
+
+/// A record that [`Lenient`] read from its source.
+enum Read {
+    /// An event with its group and line.
+    Event(Event, Option<GroupId>, Option<u64>),
+    /// A malformed record that was skipped, with its group if it is known.
+    Skipped(Option<GroupId>),
+    /// An error that is not a malformed record, with its line.
+    Error(Error, Option<u64>),
+}
+
+impl<S: EventSource, F: FnMut(Malformed)> Lenient<S, F> {
+    /// Creates a source that yields the events of `source` and calls `on_malformed` for every malformed record.
+    pub fn new(source: S, on_malformed: F) -> Self {
+        Self {
+            source,
+            on_malformed,
+            pending: VecDeque::new(),
+            next: None,
+            line: None,
+            group: None,
+            incomplete: false,
+        }
+    }
+
+    /// Reads the next record of the source, and passes it to the callback if it is malformed.
+    fn read(&mut self) -> Option<Read> {
+        let read = match self.source.next_event()? {
+            Ok(event) => Read::Event(event, self.source.group(), self.source.line()),
+            Err(error) if error.is_malformed() => {
+                let group = self.source.group();
+                (self.on_malformed)(Malformed {
+                    line: self.source.line(),
+                    error,
+                });
+                Read::Skipped(group)
+            }
+            Err(error) => Read::Error(error, self.source.line()),
+        };
+        Some(read)
+    }
+
+    /// Reads the rest of `group`, which started with `first`, into the pending events.
+    ///
+    /// Skipped records whose group is unknown don't end the group.
+    fn read_group(&mut self, group: GroupId, first: Read) {
+        self.group = Some(group);
+        self.incomplete = false;
+        let mut read = Some(first);
+        while let Some(record) = read {
+            match record {
+                Read::Event(event, Some(next), line) if next == group => self.pending.push_back((event, line)),
+                Read::Skipped(Some(next)) if next == group => self.incomplete = true,
+                Read::Skipped(None) => {}
+                record => {
+                    self.next = Some(record);
+                    return;
+                }
+            }
+            read = self.read();
+        }
+    }
+}
+
+impl<S: EventSource, F: FnMut(Malformed)> EventSource for Lenient<S, F> {
+    fn next_event(&mut self) -> Option<Result<Event, Error>> {
+        loop {
+            if let Some((event, line)) = self.pending.pop_front() {
+                self.line = line;
+                return Some(Ok(event));
+            }
+            self.group = None;
+            self.incomplete = false;
+            let record = match self.next.take() {
+                Some(record) => record,
+                None => self.read()?,
+            };
+            match record {
+                Read::Event(event, None, line) => {
+                    self.line = line;
+                    return Some(Ok(event));
+                }
+                Read::Error(error, line) => {
+                    self.line = line;
+                    return Some(Err(error));
+                }
+                Read::Skipped(None) => {}
+                Read::Event(_, Some(group), _) | Read::Skipped(Some(group)) => self.read_group(group, record),
+            }
+        }
+    }
+
+    fn line(&self) -> Option<u64> {
+        self.line
+    }
+
+    fn group(&self) -> Option<GroupId> {
+        self.group
+    }
+
+    fn group_incomplete(&self) -> bool {
+        self.incomplete
+    }
+}
+
+/// An event or an atomic group of events, see [`Groups`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Grouped {
+    /// An event that doesn't belong to a group.
+    Single(Event),
+    /// The events of a group, in input order.
+    Group(Vec<Event>),
+    /// The events of a group that can't be applied, because one of its records was skipped as malformed, see
+    /// [`EventSource::group_incomplete()`].
+    Incomplete(Vec<Event>),
+}
+
+/// Collects consecutive events of a source with the same [`EventSource::group()`] into atomic groups.
+pub struct Groups<S> {
+    source: S,
+    /// The first event after a group, which was read to find the end of the group, with its group and whether that is
+    /// incomplete.
+    next: Option<(Event, Option<GroupId>, bool)>,
+}
+
+impl<S: EventSource> Groups<S> {
+    /// Creates groups of the events of `source`.
+    pub fn new(source: S) -> Self {
+        Self { source, next: None }
+    }
+
+    /// Returns the next event or group, or `None` if there are no more events.
+    pub fn next_grouped(&mut self) -> Option<Result<Grouped, Error>> {
+        let (event, group, mut incomplete) = match self.next.take() {
+            Some(next) => next,
+            None => match self.source.next_event()? {
+                Ok(event) => (event, self.source.group(), self.source.group_incomplete()),
+                Err(err) => return Some(Err(err)),
+            },
+        };
+        let Some(group) = group else {
+            return Some(Ok(Grouped::Single(event)));
+        };
+
+        let mut events = vec![event];
+        while let Some(event) = self.source.next_event() {
+            let event = match event {
+                Ok(event) => event,
+                Err(err) => return Some(Err(err)),
+            };
+            match self.source.group() {
+                Some(next) if next == group => {
+                    events.push(event);
+                    incomplete |= self.source.group_incomplete();
+                }
+                next => {
+                    self.next = Some((event, next, self.source.group_incomplete()));
+                    break;
+                }
+            }
+        }
+        match incomplete {
+            true => Some(Ok(Grouped::Incomplete(events))),
+            false => Some(Ok(Grouped::Group(events))),
+        }
+    }
+}
+
+/// Reads events from CSV with a header row and the columns of [`EventCsvRecord`].
+pub struct CsvSource<R: io::Read> {
+    reader: csv::Reader<R>,
+    headers: Option<csv::ByteRecord>,
+    // Reused for every row, so that reading doesn't allocate.
+    record: csv::ByteRecord,
+    line: Option<u64>,
+    group: Option<GroupId>,
+    id_map: Option<Rc<RefCell<IdMap>>>,
+}
+
+impl<R: io::Read> CsvSource<R> {
+    /// Creates a source that reads CSV from `reader`.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: csv::ReaderBuilder::new().has_headers(true).from_reader(reader),
+            headers: None,
+            record: csv::ByteRecord::new(),
+            line: None,
+            group: None,
+            id_map: None,
+        }
+    }
+
+    /// Creates a source that reads CSV from `reader`, whose `client` column contains external identifiers that are
+    /// mapped to client ids with `id_map`.
+    ///
+    /// The mapping can be shared by the sources of several inputs, so that they assign the same ids.
+    pub fn with_id_map(reader: R, id_map: Rc<RefCell<IdMap>>) -> Self {
+        Self {
+            id_map: Some(id_map),
+            ..Self::new(reader)
+        }
+    }
+
+    /// Replaces the external identifiers in the `client` and `to` columns of the current record with their client ids.
+    fn map_clients(&mut self, id_map: &RefCell<IdMap>) -> Result<(), Error> {
+        let Some(headers) = &self.headers else {
+            return Ok(());
+        };
+        let mut id_map = id_map.borrow_mut();
+        // Deserializing reports a missing `client` column, and an empty `to` column is only allowed for other types.
+        let mut record = csv::ByteRecord::new();
+        for (header, field) in headers.iter().zip(&self.record) {
+            let external = String::from_utf8_lossy(field);
+            let external = external.trim();
+            if header == b"client" || (header == b"to" && !external.is_empty()) {
+                record.push_field(id_map.assign(external)?.to_string().as_bytes());
+            } else {
+                record.push_field(field);
+            }
+        }
+        record.set_position(self.record.position().cloned());
+        self.record = record;
+        Ok(())
+    }
+
+    /// Returns the value of the `group` column of the current record, if it has a valid one.
+    fn raw_group(&self) -> Option<GroupId> {
+        let headers = self.headers.as_ref()?;
+        let position = headers.iter().position(|header| header == b"group")?;
+        str::from_utf8(self.record.get(position)?).ok()?.trim().parse().ok()
+    }
+
+    /// Fails if the `client` or `to` column of the current record is out of range or not in its canonical spelling.
+    ///
+    /// Otherwise e.g. `007` and `7` would silently be the same client. Fields that aren't numbers at all are reported
+    /// by deserializing.
+    fn check_clients(&self) -> Result<(), records::Error> {
+        let Some(headers) = &self.headers else {
+            return Ok(());
+        };
+        for (header, field) in headers.iter().zip(&self.record) {
+            if header != b"client" && header != b"to" {
+                continue;
+            }
+            let Ok(field) = str::from_utf8(field) else {
+                continue;
+            };
+            match field.parse::<ClientId>() {
+                Ok(client) if client.to_string() != field => {
+                    return Err(records::Error::ClientCollision(field.to_owned(), client));
+                }
+                Err(err) if *err.kind() == IntErrorKind::PosOverflow => {
+                    return Err(records::Error::ClientOutOfRange(field.to_owned()));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: io::Read> EventSource for CsvSource<R> {
+    fn next_event(&mut self) -> Option<Result<Event, Error>> {
+        self.group = None;
+        if self.headers.is_none() {
+            match self.reader.byte_headers() {
+                Ok(headers) => self.headers = Some(headers.clone()),
+                Err(err) => return Some(Err(err.into())),
+            }
+        }
+        match self.reader.read_byte_record(&mut self.record) {
+            Ok(true) => {
+                self.line = self.record.position().map(csv::Position::line);
+                // Known before the record is checked, so that a malformed record still names its group.
+                self.group = self.raw_group();
+            }
+            Ok(false) => return None,
+            Err(err) => {
+                self.line = err.position().map(csv::Position::line);
+                return Some(Err(err.into()));
+            }
+        }
+        let checked = match self.id_map.clone() {
+            Some(id_map) => self.map_clients(&id_map),
+            None => self.check_clients().map_err(Error::from),
+        };
+        if let Err(err) = checked {
+            return Some(Err(err));
+        }
+        let record = match self.record.deserialize::<EventCsvRecord>(self.headers.as_ref()) {
+            Ok(record) => record,
+            Err(err) => return Some(Err(err.into())),
+        };
+        self.group = record.group;
+        Some(Event::try_from(record).map_err(Error::from))
+    }
+
+    fn line(&self) -> Option<u64> {
+        self.line
+    }
+
+    fn group(&self) -> Option<GroupId> {
+        self.group
+    }
+}
+
+/// Reads events from newline-delimited JSON, where each non-empty line is an [`EventJsonRecord`].
+pub struct NdjsonSource<R: BufRead> {
+    lines: io::Lines<R>,
+    line: usize,
+    group: Option<GroupId>,
+}
+
+impl<R: BufRead> NdjsonSource<R> {
+    /// Creates a source that reads NDJSON from `reader`.
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+            line: 0,
+            group: None,
+        }
+    }
+}
+
+impl<R: BufRead> EventSource for NdjsonSource<R> {
+    fn next_event(&mut self) -> Option<Result<Event, Error>> {
+        loop {
+            let content = match self.lines.next()? {
+                Ok(content) => content,
+                Err(err) => return Some(Err(err.into())),
+            };
+            self.line += 1;
+            if content.trim().is_empty() {
+                continue;
+            }
+
+            let line = self.line;
+            let record = match serde_json::from_str::<EventJsonRecord>(&content) {
+                Ok(record) => record,
+                Err(source) => {
+                    // A record of the wrong shape may still name its group.
+                    self.group = serde_json::from_str::<serde_json::Value>(&content)
+                        .ok()
+                        .and_then(|value| value.get("group")?.as_u64());
+                    return Some(Err(Error::Json { line, source }));
+                }
+            };
+            self.group = record.group;
+            return Some(Event::try_from(record).map_err(Error::from));
+        }
+    }
+
+    fn line(&self) -> Option<u64> {
+        (self.line > 0).then_some(self.line as u64)
+    }
+
+    fn group(&self) -> Option<GroupId> {
+        self.group
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn csv() -> Result<(), Error> {
+        let input = "type,client,tx,amount\ndeposit,1,1,2.5\ndispute,1,1,0\nrefund,1,2,1\n";
+        let mut source = CsvSource::new(input.as_bytes());
+
+        assert_eq!(source.next_event().transpose()?, Some(Event::deposit(1, 1, dec!(2.5))));
+        assert_eq!(source.next_event().transpose()?, Some(Event::dispute(1, 1)));
+        assert!(matches!(source.next_event(), Some(Err(Error::Record(_)))));
+        assert_eq!(source.line(), Some(4));
+        assert!(source.next_event().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn ndjson() -> Result<(), Error> {
+        let input = r#"{"type":"deposit","client":1,"tx":1,"amount":"2.5"}
+
+{"type":"dispute","client":1,"tx":1}
+{"type":"dispute","client":1}
+"#;
+        let mut source = NdjsonSource::new(input.as_bytes());
+
+        assert_eq!(source.next_event().transpose()?, Some(Event::deposit(1, 1, dec!(2.5))));
+        assert_eq!(source.next_event().transpose()?, Some(Event::dispute(1, 1)));
+        assert!(matches!(source.next_event(), Some(Err(Error::Json { line: 4, .. }))));
+        assert!(source.next_event().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn lenient() -> Result<(), Error> {
+        let input = "type,client,tx,amount\ndeposit,1,1,2\ndeposit,x,2,3\nrefund,1,3,1\ndispute,1,1,0\n";
+        let mut malformed = Vec::new();
+        let mut source = Lenient::new(CsvSource::new(input.as_bytes()), |record: Malformed| {
+            malformed.push(record.line)
+        });
+
+        assert_eq!(source.next_event().transpose()?, Some(Event::deposit(1, 1, dec!(2))));
+        assert_eq!(source.next_event().transpose()?, Some(Event::dispute(1, 1)));
+        assert!(source.next_event().is_none());
+        drop(source);
+        assert_eq!(malformed, [Some(3), Some(4)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn id_map() -> Result<(), Error> {
+        let input = "type,client,tx,amount,to\ndeposit,acc-7,1,2,\ndeposit, 3f2a ,2,3,\ndispute,acc-7,1,,\ntransfer,acc-7,3,1,acc-9\n";
+        let id_map = Rc::new(RefCell::new(IdMap::new()));
+        let mut source = CsvSource::with_id_map(input.as_bytes(), Rc::clone(&id_map));
+
+        assert_eq!(source.next_event().transpose()?, Some(Event::deposit(0, 1, dec!(2))));
+        assert_eq!(source.next_event().transpose()?, Some(Event::deposit(1, 2, dec!(3))));
+        assert_eq!(source.next_event().transpose()?, Some(Event::dispute(0, 1)));
+        assert_eq!(source.line(), Some(4));
+        assert_eq!(
+            source.next_event().transpose()?,
+            Some(Event::transfer(0, 2, 3, dec!(1)))
+        );
+        assert_eq!(id_map.borrow().get("3f2a"), Some(1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn client_ids() -> Result<(), Error> {
+        let input = "type,client,tx,amount,to\ndeposit,70000,1,2,\ndeposit,007,2,2,\ntransfer,7,3,1,+8\n\
+                     deposit,4294967296,4,2,\n";
+        let mut source = CsvSource::new(input.as_bytes());
+
+        assert_eq!(
+            source.next_event().transpose()?,
+            Some(Event::deposit(70000, 1, dec!(2)))
+        );
+        assert!(matches!(
+            source.next_event(),
+            Some(Err(Error::Record(records::Error::ClientCollision(_, 7))))
+        ));
+        assert!(matches!(
+            source.next_event(),
+            Some(Err(Error::Record(records::Error::ClientCollision(_, 8))))
+        ));
+        assert!(matches!(
+            source.next_event(),
+            Some(Err(Error::Record(records::Error::ClientOutOfRange(_))))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn groups() -> Result<(), Error> {
+        let input = "type,client,tx,amount,group\ndeposit,1,1,2,\nwithdrawal,1,2,1,7\nwithdrawal,1,3,1,7\n\
+                     deposit,2,4,1,8\ndeposit,2,5,1,\n";
+        let mut groups = Groups::new(CsvSource::new(input.as_bytes()));
+
+        assert_eq!(
+            groups.next_grouped().transpose()?,
+            Some(Grouped::Single(Event::deposit(1, 1, dec!(2))))
+        );
+        assert_eq!(
+            groups.next_grouped().transpose()?,
+            Some(Grouped::Group(vec![
+                Event::withdrawal(1, 2, dec!(1)),
+                Event::withdrawal(1, 3, dec!(1))
+            ]))
+        );
+        assert_eq!(
+            groups.next_grouped().transpose()?,
+            Some(Grouped::Group(vec![Event::deposit(2, 4, dec!(1))]))
+        );
+        assert_eq!(
+            groups.next_grouped().transpose()?,
+            Some(Grouped::Single(Event::deposit(2, 5, dec!(1))))
+        );
+        assert!(groups.next_grouped().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn lenient_groups() -> Result<(), Error> {
+        let input = "type,client,tx,amount,group\nwithdrawal,1,1,1,7\ndeposit,x,2,1,7\nwithdrawal,1,3,1,7\n\
+                     deposit,2,4,1,8\ndeposit,2,5,1,8\ndeposit,y,6,1,8\ndeposit,z,7,1,9\ndeposit,2,8,1,\n";
+        let mut malformed = Vec::new();
+        let mut groups = Groups::new(Lenient::new(CsvSource::new(input.as_bytes()), |record: Malformed| {
+            malformed.push(record.line)
+        }));
+
+        // A skipped record rejects its whole group, also if it is the last one.
+        assert_eq!(
+            groups.next_grouped().transpose()?,
+            Some(Grouped::Incomplete(vec![
+                Event::withdrawal(1, 1, dec!(1)),
+                Event::withdrawal(1, 3, dec!(1))
+            ]))
+        );
+        assert_eq!(
+            groups.next_grouped().transpose()?,
+            Some(Grouped::Incomplete(vec![
+                Event::deposit(2, 4, dec!(1)),
+                Event::deposit(2, 5, dec!(1))
+            ]))
+        );
+        // A group without any valid record disappears.
+        assert_eq!(
+            groups.next_grouped().transpose()?,
+            Some(Grouped::Single(Event::deposit(2, 8, dec!(1))))
+        );
+        assert!(groups.next_grouped().is_none());
+        drop(groups);
+        assert_eq!(malformed, [Some(3), Some(7), Some(8)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn chain() -> Result<(), Error> {
+        let first = CsvSource::new("type,client,tx,amount\ndeposit,1,1,2\n".as_bytes());
+        let empty = CsvSource::new("type,client,tx,amount\n".as_bytes());
+        let second = CsvSource::new("type,client,tx,amount\ndeposit,2,2,3\ndispute,2,2,0\n".as_bytes());
+        let mut source = Chain::new([first, empty, second]);
+
+        assert_eq!(source.next_event().transpose()?, Some(Event::deposit(1, 1, dec!(2))));
+        assert_eq!(source.next_event().transpose()?, Some(Event::deposit(2, 2, dec!(3))));
+        assert_eq!(source.next_event().transpose()?, Some(Event::dispute(2, 2)));
+        assert!(source.next_event().is_none());
+
+        Ok(())
+    }
+}
@@ -1,146 +1,1286 @@
 //! The main business logic of our application.
 
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    io::{BufRead, Write},
+    iter, mem,
+    str::FromStr,
+    sync::mpsc::{self, Receiver, Sender},
+};
 
+use rust_decimal::Decimal;
 use thiserror::Error;
 
 use crate::{
-    client::{ClientState, Transition},
+    client::{self, ClientState, Transition},
     event::Event,
-    transaction::{Deposit, Transaction, Withdrawal},
-    ClientId, TxId,
+    fees::{FeeSchedule, FeeSummary},
+    snapshot::{self, MAGIC, VERSION},
+    transaction::{Deposit, DisputeStatus, Transaction, Transfer, Withdrawal},
+    treasury::Float,
+    ClientId, EventIndex, TxId,
 };
 
 /// Errors that can happen during processing.
 #[derive(Clone, Debug, Error)]
 pub enum Error {
+    /// A deposit or withdrawal reused the id of an earlier transaction.
     #[error("duplicate transaction id: `{0}`")]
     DuplicateTxId(TxId),
+    /// [`State::recompute_client()`] was given an event of another client.
+    #[error("event for client `{found}` while recomputing client `{expected}`")]
+    ForeignEvent {
+        /// The client that is recomputed.
+        expected: ClientId,
+        /// The client of the event.
+        found: ClientId,
+    },
+    /// [`State::recompute_client()`] was given a client that sent or received a transfer, which can't be replayed
+    /// without changing the other client.
+    #[error("client `{client}` can't be recomputed on its own, as the transfer `{tx}` also changed another client")]
+    SharedTransfer {
+        /// The client that is recomputed.
+        client: ClientId,
+        /// The transfer of the client.
+        tx: TxId,
+    },
+    /// [`State::recompute_client()`] was given the fee account, whose state also holds the fees of other clients.
+    #[error("client `{0}` can't be recomputed on its own, as it collects the fees of other clients")]
+    FeeAccount(ClientId),
+    /// [`State::recompute_client()`] couldn't take the fees of the client back from the fee account, because it is
+    /// frozen or already paid them out.
+    #[error("the fee account `{0}` can't pay back the fees of the recomputed client")]
+    FeeRefund(ClientId),
+    /// [`State::merge()`] was given two states that both contain the client.
+    #[error("client `{0}` is in both merged states")]
+    DuplicateClient(ClientId),
+    /// The event would exceed [`Limits::max_clients`].
+    #[error("refusing to add more than {0} clients")]
+    ClientLimit(usize),
+    /// The event would exceed [`Limits::max_transactions`].
+    #[error("refusing to store more than {0} transactions")]
+    TransactionLimit(usize),
+}
+
+/// The result of handling an event that is consistent with the input stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The event changed the state.
+    Applied,
+    /// The event was valid input, but could not be applied to the current state.
+    Rejected(Rejection),
+}
+
+/// The reasons for rejecting an event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rejection {
+    /// The account of the client is frozen.
+    ClientFrozen,
+    /// The available funds don't cover the amount.
+    InsufficientFunds,
+    /// The event refers to a transaction that doesn't exist.
+    UnknownTransaction,
+    /// The event refers to a transaction of another client.
+    ClientMismatch,
+    /// Only deposits can be charged back.
+    NotADeposit,
+    /// The transaction is already disputed.
+    AlreadyDisputed,
+    /// The transaction is not disputed, so there is nothing to resolve or charge back.
+    NotDisputed,
+    /// The transaction was charged back, which ends its dispute lifecycle.
+    ChargedBack,
+    /// A deposit or withdrawal reused the id of an earlier transaction, see [`DuplicatePolicy::SkipAndWarn`].
+    DuplicateTxId,
+    /// A rule rejected the event before it reached the state, see [`State::skip()`].
+    Rule,
+    /// Only frozen clients can be unlocked.
+    NotFrozen,
+    /// Admin events are not allowed, see [`State::allow_admin_events()`].
+    AdminEvent,
+    /// The sender and the recipient of a transfer are the same client.
+    SelfTransfer,
+    /// Another event of the same atomic group was rejected, see [`State::handle_group()`].
+    GroupRejected,
+    /// The fee account of a withdrawal is frozen, see [`State::set_fee_schedule()`].
+    FeeAccountFrozen,
+    /// Another record of the same atomic group was malformed and skipped, see
+    /// [`Grouped::Incomplete`](crate::source::Grouped::Incomplete).
+    GroupIncomplete,
+}
+
+impl Rejection {
+    /// Returns a stable, machine-readable name of the reason.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Rejection::ClientFrozen => "client_frozen",
+            Rejection::InsufficientFunds => "insufficient_funds",
+            Rejection::UnknownTransaction => "unknown_transaction",
+            Rejection::ClientMismatch => "client_mismatch",
+            Rejection::NotADeposit => "not_a_deposit",
+            Rejection::AlreadyDisputed => "already_disputed",
+            Rejection::NotDisputed => "not_disputed",
+            Rejection::ChargedBack => "charged_back",
+            Rejection::DuplicateTxId => "duplicate_tx_id",
+            Rejection::Rule => "rule",
+            Rejection::NotFrozen => "not_frozen",
+            Rejection::AdminEvent => "admin_event",
+            Rejection::SelfTransfer => "self_transfer",
+            Rejection::GroupRejected => "group_rejected",
+            Rejection::FeeAccountFrozen => "fee_account_frozen",
+            Rejection::GroupIncomplete => "group_incomplete",
+        }
+    }
+}
+
+impl From<client::Error> for Rejection {
+    fn from(err: client::Error) -> Self {
+        match err {
+            client::Error::ClientFrozen => Rejection::ClientFrozen,
+            client::Error::InsufficientFunds => Rejection::InsufficientFunds,
+            client::Error::NotFrozen => Rejection::NotFrozen,
+        }
+    }
+}
+
+/// Notifies subscribers about an event that changed the state of a client, see [`State::subscribe()`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientStateChanged {
+    /// The client whose state changed.
+    pub client: ClientId,
+    /// The state before the event, or `None` if the event created the client.
+    pub before: Option<ClientState>,
+    /// The state after the event.
+    pub after: ClientState,
+    /// The event that changed the state.
+    pub cause: Event,
+    /// Index of the event in the input stream.
+    pub index: EventIndex,
+}
+
+/// An event that changed the state of a client, see [`State::journal()`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JournalEntry {
+    /// Index of the event in the input stream.
+    pub index: EventIndex,
+    /// The event that changed the state.
+    pub event: Event,
+    /// The amount of the event, or of the transaction that a dispute, resolve or chargeback refers to.
+    pub amount: Option<Decimal>,
+    /// The available funds of the client after the event.
+    pub available: Decimal,
+    /// The held funds of the client after the event.
+    pub held: Decimal,
+}
+
+/// How a deposit or withdrawal is handled that reuses the id of a stored transaction.
+///
+/// The policy is applied before the event changes any client, so the state stays consistent in all cases.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Fail with [`Error::DuplicateTxId`].
+    #[default]
+    Error,
+    /// Reject the event with [`Rejection::DuplicateTxId`], so the caller can warn about it.
+    SkipAndWarn,
+    /// Apply the event and replace the stored transaction, so later disputes refer to the new one.
+    ///
+    /// The replaced transaction still counts towards the balance. A transaction that is currently disputed is not
+    /// replaced but fails like with [`DuplicatePolicy::Error`], as nothing could release the funds that its dispute
+    /// holds anymore.
+    Overwrite,
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(DuplicatePolicy::Error),
+            "skip" => Ok(DuplicatePolicy::SkipAndWarn),
+            "overwrite" => Ok(DuplicatePolicy::Overwrite),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Safety limits that make processing fail cleanly instead of exhausting the memory of the host.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// The maximum number of distinct clients.
+    pub max_clients: Option<usize>,
+    /// The maximum number of stored deposits and withdrawals.
+    pub max_transactions: Option<usize>,
+}
+
+/// Approximate memory used by the data structures of a [`State`], in bytes.
+///
+/// This is computed from the capacity of the collections and the size of their entries, without the overhead of the
+/// allocator. As the collections never shrink, it is also the high-water mark.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Client states and their last activity.
+    pub clients: usize,
+    /// Stored deposits and withdrawals, including the names of their counterparties.
+    pub transactions: usize,
+    /// The optional index from clients to their transactions.
+    pub client_index: usize,
+}
+
+impl MemoryUsage {
+    /// Returns the sum of all parts.
+    pub fn total(&self) -> usize {
+        self.clients + self.transactions + self.client_index
+    }
 }
 
 /// Stores all the information that is required to compute the client state.
 ///
 /// Note that this implmentation is not safe to be used in a concurrent environment.
+#[derive(serde::Serialize, serde::Deserialize)]
 pub struct State {
     // This duplicates the `TxId` because it is also contained in `Transfer`.
     transfers: HashMap<TxId, Transaction>,
     client_states: HashMap<ClientId, ClientState>,
+    /// Index of the last event that referred to each client.
+    last_activity: HashMap<ClientId, EventIndex>,
+    /// Index of the next event that will be handled, which is also the number of events read from the input stream.
+    next_index: EventIndex,
+    /// The number of events that were derived from the input stream instead of read from it, see
+    /// [`State::handle_synthetic()`].
+    synthetic: u64,
+    /// The operator's account that mirrors all money movements.
+    float: Float,
+    /// The withdrawal fees collected so far, see [`State::set_fee_schedule()`].
+    fees_collected: FeeSummary,
+    /// Optional secondary index of the transactions of each client, see [`State::with_client_index()`].
+    client_index: Option<HashMap<ClientId, Vec<TxId>>>,
+    // Limits are configuration rather than state, so a resumed run can change them.
+    #[serde(skip)]
+    limits: Limits,
+    #[serde(skip)]
+    duplicates: DuplicatePolicy,
+    #[serde(skip)]
+    admin_events: bool,
+    #[serde(skip)]
+    fees: Option<FeeSchedule>,
+    #[serde(skip)]
+    subscribers: Vec<Sender<ClientStateChanged>>,
+    /// Optional history of each client, see [`State::with_journal()`]. It isn't part of snapshots.
+    #[serde(skip)]
+    journal: Option<HashMap<ClientId, Vec<JournalEntry>>>,
+    /// Only set while an atomic group is handled.
+    #[serde(skip)]
+    undo: Option<Undo>,
+}
+
+/// The previous values of everything that the events of an atomic group changed, see [`State::handle_group()`] and
+/// [`State::recompute_client()`].
+///
+/// `None` means that there was no entry before the group.
+#[derive(Default)]
+struct Undo {
+    client_states: HashMap<ClientId, Option<ClientState>>,
+    last_activity: HashMap<ClientId, Option<EventIndex>>,
+    transactions: HashMap<TxId, Option<Transaction>>,
+    client_index: HashMap<ClientId, Option<Vec<TxId>>>,
+    float: Float,
+    fees_collected: FeeSummary,
+    /// Subscribers are only notified once the whole group is applied.
+    changes: Vec<ClientStateChanged>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Merges all states with [`State::merge()`].
+impl FromIterator<State> for Result<State, Error> {
+    fn from_iter<I: IntoIterator<Item = State>>(states: I) -> Self {
+        let mut merged = State::new();
+        for state in states {
+            merged.merge(state)?;
+        }
+        Ok(merged)
+    }
 }
 
 impl State {
+    /// Creates an empty state without limits.
     pub fn new() -> Self {
         Self {
             transfers: HashMap::new(),
             client_states: HashMap::new(),
+            last_activity: HashMap::new(),
+            next_index: 0,
+            synthetic: 0,
+            float: Float::default(),
+            fees_collected: FeeSummary::default(),
+            client_index: None,
+            limits: Limits::default(),
+            duplicates: DuplicatePolicy::default(),
+            admin_events: false,
+            fees: None,
+            subscribers: Vec::new(),
+            journal: None,
+            undo: None,
         }
     }
 
-    pub fn handle(&mut self, event: Event) -> Result<(), Error> {
-        match event {
-            Event::Deposit { client, amount, tx } => {
-                let state = self.client_states.entry(client).or_default();
+    /// Creates a state that refuses events which would exceed `limits`.
+    pub fn with_limits(limits: Limits) -> Self {
+        Self { limits, ..Self::new() }
+    }
+
+    /// Creates a state that handles duplicate transaction ids according to `policy`.
+    pub fn with_policy(policy: DuplicatePolicy) -> Self {
+        Self {
+            duplicates: policy,
+            ..Self::new()
+        }
+    }
+
+    /// Creates a state that additionally maintains an index from each client to its transactions.
+    ///
+    /// This costs some memory per transaction, but operations on a single client like
+    /// [`State::client_transactions()`] and [`State::recompute_client()`] don't need to scan all transactions.
+    pub fn with_client_index() -> Self {
+        Self {
+            client_index: Some(HashMap::new()),
+            ..Self::new()
+        }
+    }
+
+    /// Creates a state that additionally records every change of a client in its journal, see [`State::journal()`].
+    ///
+    /// The journal grows with every applied event and is not written to snapshots, so a resumed state only records the
+    /// events after the snapshot.
+    pub fn with_journal() -> Self {
+        Self {
+            journal: Some(HashMap::new()),
+            ..Self::new()
+        }
+    }
+
+    /// Replaces the policy for duplicate transaction ids, e.g. after the state was restored from a snapshot.
+    pub fn set_duplicate_policy(&mut self, policy: DuplicatePolicy) {
+        self.duplicates = policy;
+    }
+
+    /// Allows or forbids admin events like [`Event::Unlock`], which are rejected with [`Rejection::AdminEvent`] by
+    /// default.
+    pub fn allow_admin_events(&mut self, allow: bool) {
+        self.admin_events = allow;
+    }
 
-                if let Ok(next_state) = state.clone().apply(Transition::Deposit(amount)) {
-                    *state = next_state;
+    /// Charges the fees of `schedule` for later withdrawals, or no fees if it is `None`.
+    ///
+    /// The fee is debited from the client together with the withdrawn amount and credited to the fee account, so a
+    /// withdrawal is rejected unless the client can pay both. Like the limits, the schedule isn't part of snapshots.
+    ///
+    /// The credit to the fee account is intentionally not checked by any [`Rule`](crate::rules::Rule), e.g. not by
+    /// the `max_balance` of a [`Policy`](crate::policy::Policy): the account belongs to the operator, and a client's
+    /// withdrawal shouldn't be rejected because of how much the operator has collected.
+    pub fn set_fee_schedule(&mut self, schedule: Option<FeeSchedule>) {
+        self.fees = schedule;
+    }
+
+    /// Returns a channel that receives every change of a client state from now on.
+    ///
+    /// Only applied events that actually change a client are sent, including the events that
+    /// [`State::recompute_client()`] replays. A subscriber unsubscribes by dropping the receiver.
+    pub fn subscribe(&mut self) -> Receiver<ClientStateChanged> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Replaces the limits of the state, e.g. after it was restored from a snapshot.
+    ///
+    /// The limits only apply to later events, so the state may already exceed them.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Writes the state to `writer` in the [`snapshot`] format.
+    pub fn snapshot(&self, mut writer: impl Write) -> Result<(), snapshot::Error> {
+        writeln!(writer, "{MAGIC} {VERSION}")?;
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Reads a state that was written with [`State::snapshot()`], without any limits.
+    pub fn restore(mut reader: impl BufRead) -> Result<Self, snapshot::Error> {
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let version = match header.trim_end().split_once(' ') {
+            Some((MAGIC, version)) => version.parse().map_err(|_| snapshot::Error::NotASnapshot)?,
+            _ => return Err(snapshot::Error::NotASnapshot),
+        };
+        if version != VERSION {
+            return Err(snapshot::Error::Version { found: version });
+        }
+
+        Ok(serde_json::from_reader(reader)?)
+    }
 
-                    match self.transfers.insert(tx, Transaction::deposit(client, amount)) {
-                        Some(_) => Err(Error::DuplicateTxId(tx)),
-                        None => Ok(()),
-                    }?;
+    /// Reserves capacity for at least `clients` more clients and `transactions` more transactions, but never beyond
+    /// the limits of the state.
+    ///
+    /// Sizing the state up front avoids repeatedly growing and rehashing it while a large input is processed.
+    pub fn reserve(&mut self, clients: usize, transactions: usize) {
+        let clients = match self.limits.max_clients {
+            Some(max) => clients.min(max.saturating_sub(self.client_states.len())),
+            None => clients,
+        };
+        let transactions = match self.limits.max_transactions {
+            Some(max) => transactions.min(max.saturating_sub(self.transfers.len())),
+            None => transactions,
+        };
+        self.client_states.reserve(clients);
+        self.last_activity.reserve(clients);
+        self.transfers.reserve(transactions);
+    }
+
+    /// Applies `event` as the next event of the input stream.
+    ///
+    /// Events that are invalid for the current state, e.g. withdrawals that exceed the available funds, are rejected
+    /// without changing the state, and the outcome tells why. Only inconsistencies of the input stream itself are
+    /// reported as errors.
+    pub fn handle(&mut self, event: Event) -> Result<Outcome, Error> {
+        let index = self.next_index;
+        self.next_index += 1;
+        self.handle_at(event, index)
+    }
+
+    /// Applies `event`, which the caller derived from the last event of the input stream instead of reading it, e.g. a
+    /// sweep.
+    ///
+    /// The event shares the index of that last event, so the indices of the following events stay in line with their
+    /// position in the input stream, and a run that resumes from a snapshot skips exactly the events that were read.
+    pub fn handle_synthetic(&mut self, event: Event) -> Result<Outcome, Error> {
+        let index = self.next_index.saturating_sub(1);
+        let outcome = self.handle_at(event, index)?;
+        self.synthetic += 1;
+        Ok(outcome)
+    }
+
+    /// Applies `event` with its `index` in an input stream that is shared with other states, e.g. the shards of
+    /// [`parallel::process()`](crate::parallel::process).
+    ///
+    /// The indices of the events of a state must increase, but they don't need to be consecutive.
+    pub fn handle_indexed(&mut self, index: EventIndex, event: Event) -> Result<Outcome, Error> {
+        self.next_index = index + 1;
+        self.handle_at(event, index)
+    }
+
+    /// Applies `events` as the next events of the input stream, either all of them or none.
+    ///
+    /// The events are applied in order until one of them is rejected. In that case the changes of the events before it
+    /// are rolled back and the events after it are not applied, and all events except the rejected one have the outcome
+    /// [`Rejection::GroupRejected`]. Each event takes up an index either way. If an error is returned, the changes of
+    /// the group are rolled back as well. Subscribers are only notified once all events are applied.
+    pub fn handle_group(&mut self, events: impl IntoIterator<Item = Event>) -> Result<Vec<Outcome>, Error> {
+        self.undo = Some(Undo {
+            float: self.float,
+            fees_collected: self.fees_collected,
+            ..Undo::default()
+        });
+        let mut outcomes = Vec::new();
+        let mut rejected = false;
+        for event in events {
+            if rejected {
+                self.skip();
+                outcomes.push(Outcome::Rejected(Rejection::GroupRejected));
+                continue;
+            }
+            match self.handle(event) {
+                Ok(Outcome::Applied) => outcomes.push(Outcome::Applied),
+                Ok(outcome @ Outcome::Rejected(_)) => {
+                    rejected = true;
+                    outcomes.push(outcome);
+                }
+                Err(err) => {
+                    self.roll_back();
+                    return Err(err);
                 }
             }
-            Event::Withdrawal { client, amount, tx } => {
-                let state = self.client_states.entry(client).or_default();
-                if let Ok(next_state) = state.clone().apply(Transition::Withdrawal(amount)) {
-                    *state = next_state;
+        }
 
-                    match self.transfers.insert(tx, Transaction::withdrawal(client, amount)) {
-                        Some(_) => Err(Error::DuplicateTxId(tx)),
-                        None => Ok(()),
-                    }?;
+        if rejected {
+            self.roll_back();
+            for outcome in &mut outcomes {
+                if *outcome == Outcome::Applied {
+                    *outcome = Outcome::Rejected(Rejection::GroupRejected);
                 }
             }
-            Event::Chargeback { client, tx } => {
-                // Assumption: Chargebacks only make sense for Deposits
-                if let Some(Transaction::Deposit(deposit)) = self.transfers.get(&tx) {
-                    // Skip processing if the transfer and chargeback client don't match.
-                    if client != deposit.client {
-                        return Ok(());
-                    }
+        } else if let Some(undo) = self.undo.take() {
+            for change in undo.changes {
+                self.publish(change);
+            }
+        }
+        Ok(outcomes)
+    }
 
-                    if let Some(state) = self.client_states.get_mut(&client) {
-                        if let Ok(next_state) = state.clone().apply(Transition::Chargeback) {
-                            *state = next_state;
-                        }
+    /// Restores the values from before the current atomic group.
+    fn roll_back(&mut self) {
+        let Some(undo) = self.undo.take() else {
+            return;
+        };
+        fn restore<K: Eq + Hash, V>(map: &mut HashMap<K, V>, previous: HashMap<K, Option<V>>) {
+            for (key, value) in previous {
+                match value {
+                    Some(value) => map.insert(key, value),
+                    None => map.remove(&key),
+                };
+            }
+        }
+        restore(&mut self.client_states, undo.client_states);
+        restore(&mut self.last_activity, undo.last_activity);
+        restore(&mut self.transfers, undo.transactions);
+        if let Some(index) = &mut self.client_index {
+            restore(index, undo.client_index);
+        }
+        self.float = undo.float;
+        self.fees_collected = undo.fees_collected;
+    }
+
+    /// Adds the clients and transactions of `other`, which was computed from another partition of the clients.
+    ///
+    /// Fails without changing the state if both states contain the same client or transaction. The next index is the
+    /// higher one of both states, and the limits of this state are kept.
+    pub fn merge(&mut self, other: State) -> Result<(), Error> {
+        if let Some(&client) = other
+            .client_states
+            .keys()
+            .find(|client| self.client_states.contains_key(client))
+        {
+            return Err(Error::DuplicateClient(client));
+        }
+        if let Some(&tx) = other.transfers.keys().find(|tx| self.transfers.contains_key(tx)) {
+            return Err(Error::DuplicateTxId(tx));
+        }
+
+        if let Some(index) = &mut self.client_index {
+            for (&tx, transaction) in &other.transfers {
+                for client in transaction.parties() {
+                    index.entry(client).or_default().push(tx);
+                }
+            }
+        }
+        if let (Some(journal), Some(other)) = (&mut self.journal, other.journal) {
+            journal.extend(other);
+        }
+        self.transfers.extend(other.transfers);
+        self.client_states.extend(other.client_states);
+        self.last_activity.extend(other.last_activity);
+        self.next_index = self.next_index.max(other.next_index);
+        self.synthetic += other.synthetic;
+        self.float.merge(&other.float);
+        self.fees_collected.merge(&other.fees_collected);
+        Ok(())
+    }
+
+    /// Accounts for an event that was rejected before it reached the state, e.g. by a rule, and returns its index.
+    ///
+    /// This keeps the indices of the following events in line with their position in the input stream.
+    pub fn skip(&mut self) -> EventIndex {
+        let index = self.next_index;
+        self.next_index += 1;
+        index
+    }
+
+    /// Discards all state of `client` and recomputes it from `events`, which are paired with their original index in
+    /// the input stream.
+    ///
+    /// This is the building block for targeted corrections: the complete, corrected history of a single client can be
+    /// replayed without touching any other client. All events have to refer to `client` only, so transfers can't be
+    /// replayed, and a client that sent or received a transfer can't be recomputed. Neither can the fee account, and
+    /// the fees of the removed withdrawals are taken back from it before the replay charges them again. Otherwise
+    /// nothing is changed.
+    ///
+    /// The replay is rolled back like an atomic group if an event fails, e.g. because it reuses the id of another
+    /// client's transaction, so nothing is changed in that case either. Subscribers are notified once all events are
+    /// replayed.
+    pub fn recompute_client(
+        &mut self,
+        client: ClientId,
+        events: impl IntoIterator<Item = (EventIndex, Event)>,
+    ) -> Result<(), Error> {
+        let events: Vec<_> = events.into_iter().collect();
+        let foreign = events.iter().find_map(|(_, event)| {
+            let (first, other) = match *event {
+                Event::Transfer { from, to, .. } => (from, Some(to)),
+                _ => (event.client(), None),
+            };
+            iter::once(first).chain(other).find(|&found| found != client)
+        });
+        if let Some(found) = foreign {
+            return Err(Error::ForeignEvent {
+                expected: client,
+                found,
+            });
+        }
+        // Removing a transfer would leave the other client with only one side of it.
+        let transfer = self
+            .client_transactions(client)
+            .into_iter()
+            .find(|(_, transaction)| matches!(transaction, Transaction::Transfer(_)));
+        if let Some((tx, _)) = transfer {
+            return Err(Error::SharedTransfer { client, tx });
+        }
+        if self.fees.is_some_and(|fees| fees.account == client) {
+            return Err(Error::FeeAccount(client));
+        }
+
+        let removed: Vec<_> = self.client_transactions(client).into_iter().map(|(tx, _)| tx).collect();
+        let mut undo = Undo {
+            float: self.float,
+            fees_collected: self.fees_collected,
+            ..Undo::default()
+        };
+        undo.client_states.insert(client, self.client_states.remove(&client));
+        undo.last_activity.insert(client, self.last_activity.remove(&client));
+        if let Some(index) = &mut self.client_index {
+            undo.client_index.insert(client, index.remove(&client));
+        }
+        let mut fees = Vec::new();
+        for tx in removed {
+            let transaction = self.transfers.remove(&tx);
+            // The replay posts the transactions to the float and charges their fees again.
+            if let Some(transaction) = &transaction {
+                self.float.remove(transaction);
+                if let Transaction::Withdrawal(Withdrawal { fee: Some(fee), .. }) = transaction {
+                    fees.push(*fee);
+                }
+            }
+            undo.transactions.insert(tx, transaction);
+        }
+        for fee in fees {
+            self.fees_collected.refund(&fee);
+            if fee.amount.is_zero() {
+                continue;
+            }
+            let previous = self.client_states.get(&fee.account).cloned();
+            undo.client_states
+                .entry(fee.account)
+                .or_insert_with(|| previous.clone());
+            match previous.unwrap_or_default().apply(Transition::Withdrawal(fee.amount)) {
+                Ok(account) => {
+                    self.client_states.insert(fee.account, account);
+                }
+                Err(_) => {
+                    self.undo = Some(undo);
+                    self.roll_back();
+                    return Err(Error::FeeRefund(fee.account));
+                }
+            }
+        }
+        self.undo = Some(undo);
+
+        for (index, event) in events {
+            if let Err(err) = self.handle_at(event, index) {
+                self.roll_back();
+                return Err(err);
+            }
+        }
+
+        if let Some(journal) = &mut self.journal {
+            journal.remove(&client);
+        }
+        if let Some(undo) = self.undo.take() {
+            for change in undo.changes {
+                self.publish(change);
+            }
+        }
+        Ok(())
+    }
+
+    /// Stores a transaction and keeps the client index of all its parties up to date.
+    ///
+    /// A previous transaction with the same id is replaced, see [`DuplicatePolicy::Overwrite`].
+    fn insert_transaction(&mut self, tx: TxId, transaction: Transaction) {
+        let clients: Vec<_> = transaction.parties().collect();
+        let previous = self.transfers.insert(tx, transaction);
+
+        if let Some(index) = &mut self.client_index {
+            for client in previous.iter().flat_map(Transaction::parties) {
+                if let Some(txs) = index.get_mut(&client) {
+                    txs.retain(|&other| other != tx);
+                }
+            }
+            for client in clients {
+                index.entry(client).or_default().push(tx);
+            }
+        }
+    }
+
+    /// Applies the [`DuplicatePolicy`] if a transaction with the id `tx` is already stored, and returns the outcome if
+    /// the event must not be applied.
+    fn check_duplicate(&self, tx: TxId) -> Result<Option<Outcome>, Error> {
+        let Some(previous) = self.transfers.get(&tx) else {
+            return Ok(None);
+        };
+        match self.duplicates {
+            DuplicatePolicy::Error => Err(Error::DuplicateTxId(tx)),
+            DuplicatePolicy::SkipAndWarn => Ok(Some(Outcome::Rejected(Rejection::DuplicateTxId))),
+            DuplicatePolicy::Overwrite if previous.dispute() == DisputeStatus::Disputed => {
+                Err(Error::DuplicateTxId(tx))
+            }
+            DuplicatePolicy::Overwrite => Ok(None),
+        }
+    }
+
+    fn handle_at(&mut self, event: Event, index: EventIndex) -> Result<Outcome, Error> {
+        self.check_limits(&event)?;
+        let (client, other) = self.affected_clients(&event);
+        let clients = iter::once(client).chain(other);
+        if let Some(undo) = &mut self.undo {
+            for client in clients.clone() {
+                undo.client_states
+                    .entry(client)
+                    .or_insert_with(|| self.client_states.get(&client).cloned());
+                undo.last_activity
+                    .entry(client)
+                    .or_insert_with(|| self.last_activity.get(&client).copied());
+            }
+            if let Some(tx) = event.tx() {
+                let previous = self.transfers.get(&tx);
+                // An overwritten transaction can belong to another client.
+                if let Some(index) = &self.client_index {
+                    for client in clients
+                        .clone()
+                        .chain(previous.into_iter().flat_map(Transaction::parties))
+                    {
+                        undo.client_index
+                            .entry(client)
+                            .or_insert_with(|| index.get(&client).cloned());
                     }
                 }
+                undo.transactions.entry(tx).or_insert_with(|| previous.cloned());
             }
-            Event::Dispute { client, tx } => {
-                if let Some(Transaction::Deposit(deposit)) = self.transfers.get_mut(&tx) {
-                    // Skip processing if the transfer and chargeback client don't match.
-                    if client != deposit.client || deposit.has_dispute {
-                        return Ok(());
+        }
+        // Only subscribers and the journal need the previous states and a copy of the event.
+        let change = (!self.subscribers.is_empty() || self.journal.is_some()).then(|| {
+            let before: Vec<_> = clients
+                .clone()
+                .map(|client| self.client_states.get(&client).cloned())
+                .collect();
+            (before, event.clone())
+        });
+        let result = self.apply(event, index);
+        // Any event counts as activity once the client exists, even if it could not be applied.
+        for client in clients.clone() {
+            if self.client_states.contains_key(&client) {
+                self.last_activity.insert(client, index);
+            }
+        }
+        if let (Some((before, cause)), Ok(Outcome::Applied)) = (change, &result) {
+            for (client, before) in clients.zip(before) {
+                let Some(change) = self.change(client, before, cause.clone(), index) else {
+                    continue;
+                };
+                match &mut self.undo {
+                    Some(undo) => undo.changes.push(change),
+                    None => self.publish(change),
+                }
+            }
+        }
+        result
+    }
+
+    /// Returns the client of `event` and the other client whose state it can change, if any.
+    fn affected_clients(&self, event: &Event) -> (ClientId, Option<ClientId>) {
+        match *event {
+            Event::Transfer { from, to, .. } => (from, Some(to)),
+            Event::Withdrawal { client, .. } => (client, self.fee_account(client)),
+            // The chargeback of a transfer returns the funds to the sender.
+            Event::Chargeback { client, tx } => match self.transfers.get(&tx) {
+                Some(Transaction::Transfer(transfer)) => (client, Some(transfer.from)),
+                _ => (client, None),
+            },
+            _ => (event.client(), None),
+        }
+    }
+
+    /// Returns the change of `client` for subscribers and the journal, unless `cause` left its state as it was.
+    fn change(
+        &self,
+        client: ClientId,
+        before: Option<ClientState>,
+        cause: Event,
+        index: EventIndex,
+    ) -> Option<ClientStateChanged> {
+        let after = self.client_states.get(&client)?;
+        (before.as_ref() != Some(after)).then(|| ClientStateChanged {
+            client,
+            before,
+            after: after.clone(),
+            cause,
+            index,
+        })
+    }
+
+    /// Returns the fee account if withdrawals of `client` can pay a fee into it.
+    fn fee_account(&self, client: ClientId) -> Option<ClientId> {
+        self.fees.map(|fees| fees.account).filter(|&account| account != client)
+    }
+
+    /// Appends `change` to the journal and sends it to all subscribers.
+    fn publish(&mut self, change: ClientStateChanged) {
+        if let Some(journal) = &mut self.journal {
+            let amount = match change.cause {
+                Event::Deposit { amount, .. } | Event::Withdrawal { amount, .. } | Event::Transfer { amount, .. } => {
+                    Some(amount)
+                }
+                Event::Dispute { tx, .. } | Event::Resolve { tx, .. } | Event::Chargeback { tx, .. } => {
+                    self.transfers.get(&tx).map(Transaction::amount)
+                }
+                Event::Unlock { .. } => None,
+            };
+            journal.entry(change.client).or_default().push(JournalEntry {
+                index: change.index,
+                event: change.cause.clone(),
+                amount,
+                available: change.after.available(),
+                held: change.after.held(),
+            });
+        }
+        // Subscribers that dropped their receiver are removed.
+        self.subscribers
+            .retain(|subscriber| subscriber.send(change.clone()).is_ok());
+    }
+
+    /// Fails if `event` could add a client or transaction beyond the limits.
+    fn check_limits(&self, event: &Event) -> Result<(), Error> {
+        let (clients, tx) = match *event {
+            Event::Deposit { client, tx, .. } => ([Some(client), None], tx),
+            Event::Withdrawal { client, tx, .. } => ([Some(client), self.fee_account(client)], tx),
+            Event::Transfer { from, to, tx, .. } => ([Some(from), Some(to)], tx),
+            _ => return Ok(()),
+        };
+        if let Some(max) = self.limits.max_clients {
+            let new = clients
+                .into_iter()
+                .flatten()
+                .filter(|client| !self.client_states.contains_key(client))
+                .count();
+            if self.client_states.len() + new > max {
+                return Err(Error::ClientLimit(max));
+            }
+        }
+        if let Some(max) = self.limits.max_transactions {
+            if self.transfers.len() >= max && !self.transfers.contains_key(&tx) {
+                return Err(Error::TransactionLimit(max));
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, event: Event, index: EventIndex) -> Result<Outcome, Error> {
+        match event {
+            Event::Deposit {
+                client,
+                amount,
+                tx,
+                counterparty,
+                related,
+            } => {
+                if let Some(outcome) = self.check_duplicate(tx)? {
+                    return Ok(outcome);
+                }
+                let state = self.client_states.entry(client).or_default();
+                *state = match state.clone().apply(Transition::Deposit(amount)) {
+                    Ok(next_state) => next_state,
+                    Err(err) => return Ok(Outcome::Rejected(err.into())),
+                };
+                self.float.deposit(amount);
+
+                self.insert_transaction(tx, Transaction::deposit(client, amount, counterparty, related));
+            }
+            Event::Withdrawal {
+                client,
+                amount,
+                tx,
+                counterparty,
+                related,
+            } => {
+                if let Some(outcome) = self.check_duplicate(tx)? {
+                    return Ok(outcome);
+                }
+                let fee = self.fees.and_then(|fees| fees.fee(client, amount));
+                let charged = fee.map_or(Decimal::ZERO, |fee| fee.amount);
+                let state = self.client_states.entry(client).or_default();
+                let next_state = match state.clone().apply(Transition::Withdrawal(amount + charged)) {
+                    Ok(next_state) => next_state,
+                    Err(err) => return Ok(Outcome::Rejected(err.into())),
+                };
+                if let Some(fee) = &fee {
+                    if charged > Decimal::ZERO {
+                        let account = self.client_states.get(&fee.account).cloned().unwrap_or_default();
+                        let account = match account.apply(Transition::Deposit(charged)) {
+                            Ok(account) => account,
+                            Err(_) => return Ok(Outcome::Rejected(Rejection::FeeAccountFrozen)),
+                        };
+                        self.client_states.insert(fee.account, account);
                     }
+                    self.fees_collected.collect(fee);
+                }
+                self.client_states.insert(client, next_state);
+                // The fee stays with the operator, so only the withdrawn amount leaves the float.
+                self.float.withdrawal(amount);
+
+                self.insert_transaction(tx, Transaction::withdrawal(client, amount, counterparty, related, fee));
+            }
+            Event::Chargeback { client, tx } => {
+                // Assumption: Chargebacks only make sense for Deposits and the transfers that a client received
+                let (owner, amount, dispute, disputed_at, sender) = match self.transfers.get_mut(&tx) {
+                    Some(Transaction::Deposit(Deposit {
+                        client,
+                        amount,
+                        dispute,
+                        disputed_at,
+                        ..
+                    })) => (*client, *amount, dispute, disputed_at, None),
+                    Some(Transaction::Transfer(Transfer {
+                        from,
+                        to,
+                        amount,
+                        dispute,
+                        disputed_at,
+                        ..
+                    })) => (*to, *amount, dispute, disputed_at, Some(*from)),
+                    Some(Transaction::Withdrawal(_)) => return Ok(Outcome::Rejected(Rejection::NotADeposit)),
+                    None => return Ok(Outcome::Rejected(Rejection::UnknownTransaction)),
+                };
+                if client != owner {
+                    return Ok(Outcome::Rejected(Rejection::ClientMismatch));
+                }
+                // Only the funds that are held by an active dispute can be charged back.
+                let next_dispute = match dispute.charge_back() {
+                    Ok(next_dispute) => next_dispute,
+                    Err(rejection) => return Ok(Outcome::Rejected(rejection)),
+                };
 
-                    if let Some(state) = self.client_states.get_mut(&client) {
-                        if let Ok(next_state) = state.clone().apply(Transition::DisputeDeposit(deposit.amount)) {
-                            *state = next_state;
-                            deposit.has_dispute = true;
+                let Some(state) = self.client_states.get(&client) else {
+                    return Ok(Outcome::Rejected(Rejection::UnknownTransaction));
+                };
+                let transition = Transition::Chargeback { amount, tx, at: index };
+                let next_state = match state.clone().apply(transition) {
+                    Ok(next_state) => next_state,
+                    Err(err) => return Ok(Outcome::Rejected(err.into())),
+                };
+                // Both clients have to accept the chargeback of a transfer before either of them changes.
+                let next_sender = match sender {
+                    Some(from) => {
+                        let state = self.client_states.get(&from).cloned().unwrap_or_default();
+                        match state.apply(Transition::Deposit(amount)) {
+                            Ok(next_state) => Some((from, next_state)),
+                            Err(err) => return Ok(Outcome::Rejected(err.into())),
                         }
                     }
-                } else if let Some(Transaction::Withdrawal(withdrawal)) = self.transfers.get_mut(&tx) {
-                    // Skip processing if the transfer and chargeback client don't match.
-                    if client != withdrawal.client || withdrawal.has_dispute {
-                        return Ok(());
-                    }
+                    None => None,
+                };
 
-                    if let Some(state) = self.client_states.get_mut(&client) {
-                        if let Ok(next_state) = state.clone().apply(Transition::DisputeWithdrawal(withdrawal.amount)) {
-                            *state = next_state;
-                            withdrawal.has_dispute = true;
-                        }
+                self.client_states.insert(client, next_state);
+                *dispute = next_dispute;
+                *disputed_at = None;
+                match next_sender {
+                    Some((from, next_state)) => {
+                        self.client_states.insert(from, next_state);
                     }
+                    // Money only leaves the operator's account for chargebacks of deposits.
+                    None => self.float.chargeback(amount),
+                }
+            }
+            Event::Transfer { from, to, tx, amount } => {
+                if from == to {
+                    return Ok(Outcome::Rejected(Rejection::SelfTransfer));
                 }
+                if let Some(outcome) = self.check_duplicate(tx)? {
+                    return Ok(outcome);
+                }
+                // Both clients have to accept the transfer before either of them changes.
+                let sender = self.client_states.get(&from).cloned().unwrap_or_default();
+                let recipient = self.client_states.get(&to).cloned().unwrap_or_default();
+                let (sender, recipient) = match (
+                    sender.apply(Transition::Withdrawal(amount)),
+                    recipient.apply(Transition::Deposit(amount)),
+                ) {
+                    (Ok(sender), Ok(recipient)) => (sender, recipient),
+                    (Err(err), _) | (_, Err(err)) => return Ok(Outcome::Rejected(err.into())),
+                };
+                self.client_states.insert(from, sender);
+                self.client_states.insert(to, recipient);
+                // The money stays with the operator, so the float doesn't change.
+                self.insert_transaction(tx, Transaction::transfer(from, to, amount));
+            }
+            Event::Dispute { client, tx } => {
+                let (transition, owner, dispute, disputed_at, disputes) = match self.transfers.get_mut(&tx) {
+                    Some(Transaction::Deposit(Deposit {
+                        client,
+                        amount,
+                        dispute,
+                        disputed_at,
+                        disputes,
+                        ..
+                    })) => (
+                        Transition::DisputeDeposit(*amount),
+                        *client,
+                        dispute,
+                        disputed_at,
+                        disputes,
+                    ),
+                    Some(Transaction::Withdrawal(Withdrawal {
+                        client,
+                        amount,
+                        dispute,
+                        disputed_at,
+                        disputes,
+                        ..
+                    })) => (
+                        Transition::DisputeWithdrawal(*amount),
+                        *client,
+                        dispute,
+                        disputed_at,
+                        disputes,
+                    ),
+                    Some(Transaction::Transfer(Transfer {
+                        to,
+                        amount,
+                        dispute,
+                        disputed_at,
+                        disputes,
+                        ..
+                    })) => (Transition::DisputeDeposit(*amount), *to, dispute, disputed_at, disputes),
+                    None => return Ok(Outcome::Rejected(Rejection::UnknownTransaction)),
+                };
+                if client != owner {
+                    return Ok(Outcome::Rejected(Rejection::ClientMismatch));
+                }
+                let next_dispute = match dispute.dispute() {
+                    Ok(next_dispute) => next_dispute,
+                    Err(rejection) => return Ok(Outcome::Rejected(rejection)),
+                };
+
+                let Some(state) = self.client_states.get_mut(&client) else {
+                    return Ok(Outcome::Rejected(Rejection::UnknownTransaction));
+                };
+                *state = match state.clone().apply(transition) {
+                    Ok(next_state) => next_state,
+                    Err(err) => return Ok(Outcome::Rejected(err.into())),
+                };
+                *dispute = next_dispute;
+                *disputed_at = Some(index);
+                *disputes += 1;
             }
             Event::Resolve {
                 client: resolve_client,
                 tx,
             } => {
-                match self.transfers.get_mut(&tx) {
-                    Some(
-                        Transaction::Deposit(Deposit {
-                            client,
-                            has_dispute,
-                            amount,
-                        })
-                        | Transaction::Withdrawal(Withdrawal {
-                            client,
-                            has_dispute,
-                            amount,
-                        }),
-                    ) => {
-                        // Skip processing if the tx and chargeback client don't match or if there is no active dispute.
-                        if resolve_client != *client || !*has_dispute {
-                            return Ok(());
-                        }
+                let Some(transaction) = self.transfers.get_mut(&tx) else {
+                    return Ok(Outcome::Rejected(Rejection::UnknownTransaction));
+                };
+                let is_withdrawal = matches!(transaction, Transaction::Withdrawal(_));
+                let (Transaction::Deposit(Deposit {
+                    client,
+                    dispute,
+                    disputed_at,
+                    amount,
+                    ..
+                })
+                | Transaction::Withdrawal(Withdrawal {
+                    client,
+                    dispute,
+                    disputed_at,
+                    amount,
+                    ..
+                })
+                | Transaction::Transfer(Transfer {
+                    to: client,
+                    dispute,
+                    disputed_at,
+                    amount,
+                    ..
+                })) = transaction;
+                if resolve_client != *client {
+                    return Ok(Outcome::Rejected(Rejection::ClientMismatch));
+                }
+                let next_dispute = match dispute.resolve() {
+                    Ok(next_dispute) => next_dispute,
+                    Err(rejection) => return Ok(Outcome::Rejected(rejection)),
+                };
 
-                        if let Some(state) = self.client_states.get_mut(&client) {
-                            if let Ok(next_state) = state.clone().apply(Transition::Resolve(*amount)) {
-                                *state = next_state;
-                                *has_dispute = false;
-                            }
-                        }
-                    }
-                    None => (),
+                let Some(state) = self.client_states.get_mut(client) else {
+                    return Ok(Outcome::Rejected(Rejection::UnknownTransaction));
+                };
+                *state = match state.clone().apply(Transition::Resolve(*amount)) {
+                    Ok(next_state) => next_state,
+                    Err(err) => return Ok(Outcome::Rejected(err.into())),
+                };
+                *dispute = next_dispute;
+                *disputed_at = None;
+                // The funds of a resolved withdrawal dispute are returned to the client.
+                if is_withdrawal {
+                    self.float.reversal(*amount);
                 }
             }
+            Event::Unlock { client } => {
+                if !self.admin_events {
+                    return Ok(Outcome::Rejected(Rejection::AdminEvent));
+                }
+                let Some(state) = self.client_states.get_mut(&client) else {
+                    return Ok(Outcome::Rejected(Rejection::NotFrozen));
+                };
+                *state = match state.clone().apply(Transition::Unfreeze) {
+                    Ok(next_state) => next_state,
+                    Err(err) => return Ok(Outcome::Rejected(err.into())),
+                };
+            }
+        }
+        Ok(Outcome::Applied)
+    }
+
+    /// Returns the client other than the one of `event` that `event` would credit, together with the amount.
+    ///
+    /// These are the recipient of a transfer and the sender of a transfer that is charged back. Fees are not included,
+    /// see [`State::set_fee_schedule()`].
+    pub fn credited_counterpart(&self, event: &Event) -> Option<(ClientId, Decimal)> {
+        match *event {
+            Event::Transfer { to, amount, .. } => Some((to, amount)),
+            // The chargeback of a transfer returns the funds to the sender.
+            Event::Chargeback { tx, .. } => match self.transfers.get(&tx) {
+                Some(Transaction::Transfer(transfer)) => Some((transfer.from, transfer.amount)),
+                _ => None,
+            },
+            _ => None,
         }
-        Ok(())
     }
 
+    /// Returns the current state of `client`, if any of its events have been applied.
+    pub fn client_state(&self, client: ClientId) -> Option<&ClientState> {
+        self.client_states.get(&client)
+    }
+
+    /// Returns the states of all clients, in no particular order.
     pub fn client_states(&self) -> impl Iterator<Item = (&ClientId, &ClientState)> {
         self.client_states.iter()
     }
+
+    /// Returns the states of all clients, ordered by their id.
+    pub fn client_states_sorted(&self) -> impl Iterator<Item = (&ClientId, &ClientState)> {
+        let mut clients: Vec<_> = self.client_states().collect();
+        clients.sort_unstable_by_key(|&(client, _)| client);
+        clients.into_iter()
+    }
+
+    /// Returns the withdrawal fees that were collected so far.
+    pub fn fees_collected(&self) -> &FeeSummary {
+        &self.fees_collected
+    }
+
+    /// Returns the operator's float account.
+    pub fn float(&self) -> &Float {
+        &self.float
+    }
+
+    /// Returns the index that the next handled event will get.
+    pub fn next_index(&self) -> EventIndex {
+        self.next_index
+    }
+
+    /// Returns the number of events that were handled with [`State::handle_synthetic()`].
+    pub fn synthetic_events(&self) -> u64 {
+        self.synthetic
+    }
+
+    /// Returns the deposit or withdrawal with the id `tx`, if it has been applied.
+    pub fn transaction(&self, tx: TxId) -> Option<&Transaction> {
+        self.transfers.get(&tx)
+    }
+
+    /// Returns all deposits and withdrawals that have been applied.
+    pub fn transactions(&self) -> impl Iterator<Item = (&TxId, &Transaction)> {
+        self.transfers.iter()
+    }
+
+    /// Returns the events that changed the state of `client` in the order they were applied.
+    ///
+    /// This is empty unless the state was created with [`State::with_journal()`].
+    pub fn journal(&self, client: ClientId) -> &[JournalEntry] {
+        self.journal
+            .as_ref()
+            .and_then(|journal| journal.get(&client))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the transactions of `client`, including the transfers that it sent, ordered by transaction id.
+    ///
+    /// This is a lookup if the state maintains a client index, and a scan of all transactions otherwise.
+    pub fn client_transactions(&self, client: ClientId) -> Vec<(TxId, &Transaction)> {
+        let mut transactions: Vec<_> = match &self.client_index {
+            Some(index) => index
+                .get(&client)
+                .into_iter()
+                .flatten()
+                .filter_map(|tx| Some((*tx, self.transfers.get(tx)?)))
+                .collect(),
+            None => self
+                .transfers
+                .iter()
+                .filter(|(_, transaction)| transaction.parties().any(|party| party == client))
+                .map(|(&tx, transaction)| (tx, transaction))
+                .collect(),
+        };
+        transactions.sort_by_key(|&(tx, _)| tx);
+        transactions
+    }
+
+    /// Returns an estimate of the memory used by this state.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let clients = self.client_states.capacity() * mem::size_of::<(ClientId, ClientState)>()
+            + self.last_activity.capacity() * mem::size_of::<(ClientId, EventIndex)>();
+
+        let counterparties: usize = self
+            .transfers
+            .values()
+            .filter_map(|transaction| match transaction {
+                Transaction::Deposit(Deposit { counterparty, .. })
+                | Transaction::Withdrawal(Withdrawal { counterparty, .. }) => counterparty.as_ref(),
+                Transaction::Transfer(_) => None,
+            })
+            .map(String::capacity)
+            .sum();
+        let transactions = self.transfers.capacity() * mem::size_of::<(TxId, Transaction)>() + counterparties;
+
+        let client_index = self.client_index.as_ref().map_or(0, |index| {
+            index.capacity() * mem::size_of::<(ClientId, Vec<TxId>)>()
+                + index
+                    .values()
+                    .map(|txs| txs.capacity() * mem::size_of::<TxId>())
+                    .sum::<usize>()
+        });
+
+        MemoryUsage {
+            clients,
+            transactions,
+            client_index,
+        }
+    }
+
+    /// Returns the clients that still hold funds but whose last activity was followed by at least `idle` other events,
+    /// together with the index of their last activity.
+    pub fn dormant_clients(&self, idle: u64) -> impl Iterator<Item = (ClientId, &ClientState, EventIndex)> {
+        self.client_states.iter().filter_map(move |(&client, state)| {
+            let last_activity = *self.last_activity.get(&client)?;
+            let dormant = !state.total().is_zero() && self.next_index - last_activity > idle;
+            dormant.then_some((client, state, last_activity))
+        })
+    }
 }
 
 #[cfg(test)]
@@ -148,6 +1288,7 @@ mod test {
     use rust_decimal_macros::dec;
 
     use super::*;
+    use crate::client::{Freeze, FreezeReason};
 
     impl State {
         fn handle_multiple(&mut self, stream: impl IntoIterator<Item = Event>) -> Result<(), Error> {
@@ -169,7 +1310,7 @@ mod test {
         ])?;
 
         let expected: HashMap<ClientId, ClientState> =
-            [(0, ClientState::new(false, dec!(8), dec!(0)))].into_iter().collect();
+            [(0, ClientState::new(None, dec!(8), dec!(0)))].into_iter().collect();
 
         assert_eq!(state.client_states, expected);
 
@@ -186,7 +1327,7 @@ mod test {
             Event::withdrawal(0, 1, dec!(18)),
         ])?;
 
-        let expected = HashMap::from([(0, ClientState::new(false, dec!(17), dec!(0)))]);
+        let expected = HashMap::from([(0, ClientState::new(None, dec!(17), dec!(0)))]);
 
         assert_eq!(state.client_states, expected);
 
@@ -210,21 +1351,35 @@ mod test {
         ])?;
 
         let expected = HashMap::from([
-            (client_a, ClientState::new(false, dec!(1), dec!(0))),
-            (client_b, ClientState::new(false, dec!(12), dec!(0))),
+            (client_a, ClientState::new(None, dec!(1), dec!(0))),
+            (client_b, ClientState::new(None, dec!(12), dec!(0))),
         ]);
         assert_eq!(state.client_states, expected);
 
         state.handle_multiple([
-            Event::chargeback(client_a, tx_a),              // freezing `client_a`
-            Event::deposit(client_a, tx_a + 2, dec!(2)),    // no effect
-            Event::withdrawal(client_a, tx_a + 3, dec!(2)), // no effect
-            Event::dispute(client_a, tx_a),                 // no effect
+            Event::chargeback(client_b, tx_b),              // can't chargeback without a dispute
+            Event::dispute(client_b, tx_b),                 // holding the funds of `tx_b`
+            Event::chargeback(client_b, tx_b),              // freezing `client_b`
+            Event::deposit(client_b, tx_b + 2, dec!(2)),    // no effect
+            Event::withdrawal(client_b, tx_b + 3, dec!(2)), // no effect
+            Event::dispute(client_b, tx_b),                 // no effect
         ])?;
 
+        assert_eq!(state.float.balance(), dec!(1));
+
         let expected = HashMap::from([
-            (client_a, ClientState::new(true, dec!(1), dec!(0))),
-            (client_b, ClientState::new(false, dec!(12), dec!(0))),
+            (client_a, ClientState::new(None, dec!(1), dec!(0))),
+            (
+                client_b,
+                ClientState::new(
+                    Some(Freeze {
+                        reason: FreezeReason::Chargeback(tx_b),
+                        at: 8,
+                    }),
+                    dec!(0),
+                    dec!(0),
+                ),
+            ),
         ]);
         assert_eq!(state.client_states, expected);
 
@@ -241,7 +1396,7 @@ mod test {
             Event::dispute(0, 0),
         ])?;
 
-        let expected = HashMap::from([(0, ClientState::new(false, dec!(42), dec!(17)))]);
+        let expected = HashMap::from([(0, ClientState::new(None, dec!(42), dec!(17)))]);
         assert_eq!(state.client_states, expected);
 
         state.handle_multiple([
@@ -252,7 +1407,7 @@ mod test {
             Event::resolve(0, 0),
         ])?;
 
-        let expected = HashMap::from([(0, ClientState::new(false, dec!(59), dec!(0)))]);
+        let expected = HashMap::from([(0, ClientState::new(None, dec!(59), dec!(0)))]);
         assert_eq!(state.client_states, expected);
 
         state.handle_multiple([
@@ -261,15 +1416,16 @@ mod test {
             Event::dispute(0, 2),              // dispute a withdrawal
         ])?;
 
-        let expected = HashMap::from([(0, ClientState::new(false, dec!(16), dec!(43)))]);
+        let expected = HashMap::from([(0, ClientState::new(None, dec!(16), dec!(43)))]);
         assert_eq!(state.client_states, expected);
 
         state.handle_multiple([
             Event::resolve(0, 2), // resolve the withdrawal
         ])?;
 
-        let expected = HashMap::from([(0, ClientState::new(false, dec!(59), dec!(0)))]);
+        let expected = HashMap::from([(0, ClientState::new(None, dec!(59), dec!(0)))]);
         assert_eq!(state.client_states, expected);
+        assert_eq!(state.float.balance(), dec!(59));
 
         Ok(())
     }
@@ -284,12 +1440,126 @@ mod test {
             Event::dispute(0, 0), // can't dispute if to much funds have been withdrawn
         ])?;
 
-        let expected = HashMap::from([(0, ClientState::new(false, dec!(1), dec!(0)))]);
+        let expected = HashMap::from([(0, ClientState::new(None, dec!(1), dec!(0)))]);
         assert_eq!(state.client_states, expected);
 
         Ok(())
     }
 
+    #[test]
+    fn dormant_clients() -> Result<(), Error> {
+        let mut state = State::new();
+
+        state.handle_multiple([
+            Event::deposit(0, 0, dec!(10)),
+            Event::deposit(1, 1, dec!(10)),
+            Event::withdrawal(1, 2, dec!(10)), // no funds left
+            Event::deposit(2, 3, dec!(10)),
+            Event::withdrawal(0, 4, dec!(20)), // rejected, but still counts as activity
+            Event::deposit(3, 5, dec!(10)),
+        ])?;
+
+        let mut dormant: Vec<_> = state.dormant_clients(1).map(|(client, _, at)| (client, at)).collect();
+        dormant.sort();
+        assert_eq!(dormant, [(0, 4), (2, 3)]);
+
+        let dormant: Vec<_> = state.dormant_clients(2).map(|(client, _, at)| (client, at)).collect();
+        assert_eq!(dormant, [(2, 3)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn recompute_client() -> Result<(), Error> {
+        let mut state = State::new();
+
+        state.handle_multiple([
+            Event::deposit(0, 0, dec!(10)),
+            Event::deposit(1, 1, dec!(20)),
+            Event::withdrawal(0, 2, dec!(5)),
+            Event::dispute(1, 1),
+        ])?;
+
+        // The withdrawal of client 0 turned out to be a deposit.
+        state.recompute_client(
+            0,
+            [(0, Event::deposit(0, 0, dec!(10))), (2, Event::deposit(0, 2, dec!(5)))],
+        )?;
+
+        let expected = HashMap::from([
+            (0, ClientState::new(None, dec!(15), dec!(0))),
+            (1, ClientState::new(None, dec!(0), dec!(20))),
+        ]);
+        assert_eq!(state.client_states, expected);
+        assert_eq!(state.last_activity, HashMap::from([(0, 2), (1, 3)]));
+        // The removed transactions are taken back from the float before they are replayed.
+        assert_eq!(state.float().deposited(), dec!(35));
+        assert_eq!(state.float().balance(), dec!(35));
+
+        // Nothing changes if an event belongs to a different client.
+        let result = state.recompute_client(0, [(0, Event::deposit(1, 0, dec!(10)))]);
+        assert!(matches!(result, Err(Error::ForeignEvent { expected: 0, found: 1 })));
+        let result = state.recompute_client(0, [(0, Event::transfer(0, 1, 0, dec!(10)))]);
+        assert!(matches!(result, Err(Error::ForeignEvent { expected: 0, found: 1 })));
+        assert_eq!(state.client_states, expected);
+
+        // Neither side of a transfer can be recomputed on its own.
+        state.handle(Event::transfer(0, 2, 4, dec!(4)))?;
+        for client in [0, 2] {
+            let result = state.recompute_client(client, []);
+            assert!(matches!(result, Err(Error::SharedTransfer { tx: 4, .. })));
+        }
+        assert_eq!(state.client_state(2), Some(&ClientState::new(None, dec!(4), dec!(0))));
+
+        // A replay that fails is rolled back.
+        let float = *state.float();
+        let result = state.recompute_client(
+            1,
+            [(1, Event::deposit(1, 1, dec!(20))), (5, Event::deposit(1, 2, dec!(1)))],
+        );
+        assert!(matches!(result, Err(Error::DuplicateTxId(2))));
+        assert_eq!(state.client_state(1), Some(&ClientState::new(None, dec!(0), dec!(20))));
+        assert_eq!(state.last_activity.get(&1), Some(&3));
+        assert!(matches!(state.transaction(1), Some(Transaction::Deposit(deposit)) if deposit.disputes == 1));
+        assert_eq!(*state.float(), float);
+
+        Ok(())
+    }
+
+    #[test]
+    fn client_index() -> Result<(), Error> {
+        let events = [
+            Event::deposit(0, 3, dec!(10)),
+            Event::deposit(1, 1, dec!(20)),
+            Event::withdrawal(0, 2, dec!(5)),
+            Event::withdrawal(1, 4, dec!(50)), // insufficient funds
+            Event::transfer(1, 2, 6, dec!(5)),
+        ];
+
+        for mut state in [State::new(), State::with_client_index()] {
+            state.handle_multiple(events.clone())?;
+
+            let txs = |state: &State, client| -> Vec<TxId> {
+                state
+                    .client_transactions(client)
+                    .into_iter()
+                    .map(|(tx, _)| tx)
+                    .collect()
+            };
+            assert_eq!(txs(&state, 0), [2, 3]);
+            // Transfers are listed for both clients.
+            assert_eq!(txs(&state, 1), [1, 6]);
+            assert_eq!(txs(&state, 2), [6]);
+            assert!(txs(&state, 3).is_empty());
+
+            state.recompute_client(0, [(0, Event::deposit(0, 5, dec!(1)))])?;
+            assert_eq!(txs(&state, 0), [5]);
+            assert_eq!(state.transfers.len(), 3);
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn only_invalid() -> Result<(), Error> {
         let mut state = State::new();
@@ -302,4 +1572,439 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn limits() -> Result<(), Error> {
+        let mut state = State::with_limits(Limits {
+            max_clients: Some(2),
+            max_transactions: Some(3),
+        });
+
+        state.handle_multiple([
+            Event::deposit(0, 0, dec!(1)),
+            Event::deposit(1, 1, dec!(1)),
+            Event::withdrawal(1, 2, dec!(1)),
+            Event::dispute(2, 0), // does not add a client
+        ])?;
+        assert!(matches!(
+            state.handle(Event::deposit(2, 3, dec!(1))),
+            Err(Error::ClientLimit(2))
+        ));
+        assert!(matches!(
+            state.handle(Event::deposit(0, 3, dec!(1))),
+            Err(Error::TransactionLimit(3))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn memory_usage() -> Result<(), Error> {
+        let mut state = State::with_client_index();
+        assert_eq!(state.memory_usage(), MemoryUsage::default());
+
+        state.handle_multiple([Event::deposit(0, 0, dec!(1)), Event::deposit(1, 1, dec!(1))])?;
+        let usage = state.memory_usage();
+        assert!(usage.clients >= 2 * mem::size_of::<(ClientId, ClientState)>());
+        assert!(usage.transactions >= 2 * mem::size_of::<(TxId, Transaction)>());
+        assert!(usage.client_index >= 2 * mem::size_of::<TxId>());
+        assert_eq!(usage.total(), usage.clients + usage.transactions + usage.client_index);
+
+        // Reservations don't exceed the limits.
+        let mut state = State::with_limits(Limits {
+            max_clients: Some(10),
+            max_transactions: Some(20),
+        });
+        state.reserve(1_000_000, 1_000_000);
+        let usage = state.memory_usage();
+        assert!(usage.clients < 1000 * mem::size_of::<(ClientId, ClientState)>());
+        assert!(usage.transactions < 1000 * mem::size_of::<(TxId, Transaction)>());
+
+        Ok(())
+    }
+
+    #[test]
+    fn outcomes() -> Result<(), Error> {
+        let mut state = State::new();
+        let rejected = Outcome::Rejected;
+
+        assert_eq!(state.handle(Event::deposit(0, 0, dec!(10)))?, Outcome::Applied);
+        assert_eq!(state.handle(Event::deposit(1, 1, dec!(10)))?, Outcome::Applied);
+        assert_eq!(
+            state.handle(Event::withdrawal(0, 2, dec!(11)))?,
+            rejected(Rejection::InsufficientFunds)
+        );
+        assert_eq!(
+            state.handle(Event::dispute(0, 9))?,
+            rejected(Rejection::UnknownTransaction)
+        );
+        assert_eq!(state.handle(Event::dispute(1, 0))?, rejected(Rejection::ClientMismatch));
+        assert_eq!(state.handle(Event::resolve(0, 0))?, rejected(Rejection::NotDisputed));
+        assert_eq!(state.handle(Event::dispute(0, 0))?, Outcome::Applied);
+        assert_eq!(
+            state.handle(Event::dispute(0, 0))?,
+            rejected(Rejection::AlreadyDisputed)
+        );
+        assert_eq!(state.handle(Event::withdrawal(1, 3, dec!(1)))?, Outcome::Applied);
+        assert_eq!(state.handle(Event::chargeback(1, 3))?, rejected(Rejection::NotADeposit));
+        assert_eq!(state.handle(Event::chargeback(1, 1))?, rejected(Rejection::NotDisputed));
+        assert_eq!(state.handle(Event::chargeback(0, 0))?, Outcome::Applied);
+        assert_eq!(state.handle(Event::dispute(0, 0))?, rejected(Rejection::ChargedBack));
+        assert_eq!(
+            state.handle(Event::deposit(0, 4, dec!(1)))?,
+            rejected(Rejection::ClientFrozen)
+        );
+
+        assert_eq!(state.skip(), 14);
+        assert_eq!(state.next_index(), 15);
+
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_restore() -> Result<(), Box<dyn std::error::Error>> {
+        let mut state = State::with_client_index();
+        state.handle_multiple([
+            Event::deposit(0, 0, dec!(10)),
+            Event::withdrawal(0, 1, dec!(4)),
+            Event::dispute(0, 1),
+            Event::deposit(1, 2, dec!(3)),
+            Event::dispute(1, 2),
+            Event::chargeback(1, 2),
+        ])?;
+
+        let mut snapshot = Vec::new();
+        state.snapshot(&mut snapshot)?;
+        let mut restored = State::restore(snapshot.as_slice())?;
+
+        assert_eq!(restored.next_index(), state.next_index());
+        assert_eq!(restored.client_states, state.client_states);
+        assert_eq!(restored.float(), state.float());
+        assert_eq!(restored.client_transactions(0).len(), 2);
+        assert_eq!(restored.handle(Event::resolve(0, 1))?, Outcome::Applied);
+        assert_eq!(restored.handle(Event::dispute(0, 1))?, Outcome::Applied);
+
+        let outdated = format!("{MAGIC} 0\n{{}}");
+        assert!(matches!(
+            State::restore(outdated.as_bytes()),
+            Err(snapshot::Error::Version { found: 0 })
+        ));
+        assert!(matches!(
+            State::restore("type,client,tx,amount\n".as_bytes()),
+            Err(snapshot::Error::NotASnapshot)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge() -> Result<(), Error> {
+        let mut first = State::with_client_index();
+        first.handle_multiple([Event::deposit(0, 0, dec!(10)), Event::withdrawal(0, 1, dec!(4))])?;
+        let mut second = State::new();
+        second.handle_multiple([
+            Event::deposit(1, 2, dec!(5)),
+            Event::deposit(1, 3, dec!(1)),
+            Event::dispute(1, 3),
+            Event::chargeback(1, 3),
+        ])?;
+
+        let mut merged = [first, second].into_iter().collect::<Result<State, _>>()?;
+        assert_eq!(merged.next_index(), 4);
+        assert_eq!(merged.client_states().count(), 2);
+        assert_eq!(merged.transactions().count(), 4);
+        assert_eq!(merged.float().balance(), dec!(11));
+        assert_eq!(merged.client_state(1).map(ClientState::frozen), Some(true));
+
+        let mut other = State::new();
+        other.handle(Event::deposit(2, 1, dec!(1)))?;
+        assert!(matches!(merged.merge(other), Err(Error::DuplicateTxId(1))));
+        let mut other = State::new();
+        other.handle(Event::deposit(0, 9, dec!(1)))?;
+        assert!(matches!(merged.merge(other), Err(Error::DuplicateClient(0))));
+        assert_eq!(merged.transactions().count(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn client_states_sorted() -> Result<(), Error> {
+        let mut state = State::new();
+        for client in [7, 300, 2, 41] {
+            state.handle(Event::deposit(client, client, dec!(1)))?;
+        }
+        let clients: Vec<_> = state.client_states_sorted().map(|(&client, _)| client).collect();
+        assert_eq!(clients, [2, 7, 41, 300]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn subscribe() -> Result<(), Error> {
+        let mut state = State::new();
+        state.handle(Event::deposit(0, 0, dec!(1)))?;
+        let receiver = state.subscribe();
+
+        state.handle_multiple([
+            Event::deposit(0, 1, dec!(10)),
+            Event::withdrawal(0, 2, dec!(20)), // rejected
+            Event::deposit(1, 3, dec!(0)),
+            Event::deposit(1, 4, dec!(0)), // no change
+        ])?;
+
+        let changes: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(
+            changes,
+            [
+                ClientStateChanged {
+                    client: 0,
+                    before: Some(ClientState::new(None, dec!(1), dec!(0))),
+                    after: ClientState::new(None, dec!(11), dec!(0)),
+                    cause: Event::deposit(0, 1, dec!(10)),
+                    index: 1,
+                },
+                ClientStateChanged {
+                    client: 1,
+                    before: None,
+                    after: ClientState::new(None, dec!(0), dec!(0)),
+                    cause: Event::deposit(1, 3, dec!(0)),
+                    index: 3,
+                },
+            ]
+        );
+
+        // Dropped receivers don't keep the state from handling events.
+        drop(receiver);
+        state.handle(Event::deposit(0, 5, dec!(1)))?;
+        assert!(state.subscribers.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn journal() -> Result<(), Error> {
+        let mut state = State::with_journal();
+        state.handle_multiple([
+            Event::deposit(0, 0, dec!(10)),
+            Event::withdrawal(0, 1, dec!(20)), // rejected
+            Event::transfer(0, 1, 2, dec!(4)),
+            Event::dispute(1, 2),
+        ])?;
+        // A rejected group leaves no trace in the journal.
+        state.handle_group([Event::deposit(1, 3, dec!(1)), Event::withdrawal(1, 4, dec!(9))])?;
+
+        let entry = |index, event, amount, available, held| JournalEntry {
+            index,
+            event,
+            amount: Some(amount),
+            available,
+            held,
+        };
+        assert_eq!(
+            state.journal(0),
+            [
+                entry(0, Event::deposit(0, 0, dec!(10)), dec!(10), dec!(10), dec!(0)),
+                entry(2, Event::transfer(0, 1, 2, dec!(4)), dec!(4), dec!(6), dec!(0)),
+            ]
+        );
+        assert_eq!(
+            state.journal(1),
+            [
+                entry(2, Event::transfer(0, 1, 2, dec!(4)), dec!(4), dec!(4), dec!(0)),
+                entry(3, Event::dispute(1, 2), dec!(4), dec!(0), dec!(4)),
+            ]
+        );
+        assert!(State::new().journal(0).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn unlock() -> Result<(), Error> {
+        let mut state = State::new();
+        state.handle_multiple([
+            Event::deposit(0, 0, dec!(10)),
+            Event::dispute(0, 0),
+            Event::chargeback(0, 0),
+        ])?;
+        assert_eq!(
+            state.handle(Event::unlock(0))?,
+            Outcome::Rejected(Rejection::AdminEvent)
+        );
+
+        state.allow_admin_events(true);
+        assert_eq!(state.handle(Event::unlock(0))?, Outcome::Applied);
+        assert_eq!(state.handle(Event::unlock(0))?, Outcome::Rejected(Rejection::NotFrozen));
+        assert_eq!(state.handle(Event::unlock(1))?, Outcome::Rejected(Rejection::NotFrozen));
+        assert_eq!(state.handle(Event::deposit(0, 1, dec!(4)))?, Outcome::Applied);
+        assert_eq!(state.client_state(0).map(ClientState::total), Some(dec!(4)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn transfer() -> Result<(), Error> {
+        let mut state = State::new();
+        let receiver = state.subscribe();
+        state.handle(Event::deposit(0, 0, dec!(10)))?;
+        assert_eq!(
+            state.handle(Event::transfer(0, 1, 1, dec!(11)))?,
+            Outcome::Rejected(Rejection::InsufficientFunds)
+        );
+        assert_eq!(
+            state.handle(Event::transfer(0, 0, 1, dec!(1)))?,
+            Outcome::Rejected(Rejection::SelfTransfer)
+        );
+        assert_eq!(state.handle(Event::transfer(0, 1, 1, dec!(4)))?, Outcome::Applied);
+        assert_eq!(state.client_states[&0], ClientState::new(None, dec!(6), dec!(0)));
+        assert_eq!(state.client_states[&1], ClientState::new(None, dec!(4), dec!(0)));
+        assert!(matches!(
+            state.handle(Event::transfer(0, 1, 1, dec!(1))),
+            Err(Error::DuplicateTxId(1))
+        ));
+
+        // Only the recipient can dispute a transfer, and a chargeback returns the funds to the sender.
+        assert_eq!(
+            state.handle(Event::dispute(0, 1))?,
+            Outcome::Rejected(Rejection::ClientMismatch)
+        );
+        state.handle_multiple([Event::dispute(1, 1), Event::chargeback(1, 1)])?;
+        assert_eq!(state.client_states[&0], ClientState::new(None, dec!(10), dec!(0)));
+        assert!(state.client_states[&1].frozen());
+        assert_eq!(state.client_states[&1].total(), dec!(0));
+        assert_eq!(state.float().balance(), dec!(10));
+
+        let changed: Vec<_> = receiver
+            .try_iter()
+            .map(|change| (change.client, change.index))
+            .collect();
+        assert_eq!(changed, [(0, 0), (0, 3), (1, 3), (1, 6), (1, 7), (0, 7)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn handle_group() -> Result<(), Error> {
+        let mut state = State::with_client_index();
+        let receiver = state.subscribe();
+        state.handle(Event::deposit(0, 0, dec!(10)))?;
+
+        // The second withdrawal exceeds the funds, so the first one and the new client are rolled back.
+        let outcomes = state.handle_group([
+            Event::withdrawal(0, 1, dec!(6)),
+            Event::deposit(1, 2, dec!(6)),
+            Event::withdrawal(0, 3, dec!(6)),
+            Event::deposit(1, 4, dec!(1)),
+        ])?;
+        assert_eq!(
+            outcomes,
+            [
+                Outcome::Rejected(Rejection::GroupRejected),
+                Outcome::Rejected(Rejection::GroupRejected),
+                Outcome::Rejected(Rejection::InsufficientFunds),
+                Outcome::Rejected(Rejection::GroupRejected),
+            ]
+        );
+        assert_eq!(state.client_states[&0], ClientState::new(None, dec!(10), dec!(0)));
+        assert!(state.client_state(1).is_none());
+        assert!(state.transaction(1).is_none());
+        assert_eq!(state.client_transactions(0).len(), 1);
+        assert_eq!(state.float().balance(), dec!(10));
+        assert_eq!(state.next_index(), 5);
+
+        // An error rolls back the group as well.
+        assert!(matches!(
+            state.handle_group([Event::withdrawal(0, 5, dec!(1)), Event::deposit(0, 0, dec!(1))]),
+            Err(Error::DuplicateTxId(0))
+        ));
+        assert_eq!(state.client_states[&0], ClientState::new(None, dec!(10), dec!(0)));
+
+        let outcomes = state.handle_group([Event::withdrawal(0, 5, dec!(6)), Event::deposit(1, 6, dec!(6))])?;
+        assert_eq!(outcomes, [Outcome::Applied, Outcome::Applied]);
+        assert_eq!(state.client_states[&0], ClientState::new(None, dec!(4), dec!(0)));
+        assert_eq!(state.client_states[&1], ClientState::new(None, dec!(6), dec!(0)));
+
+        let changed: Vec<_> = receiver
+            .try_iter()
+            .map(|change| (change.client, change.index))
+            .collect();
+        assert_eq!(changed, [(0, 0), (0, 7), (1, 8)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn fees() -> Result<(), Box<dyn std::error::Error>> {
+        let mut state = State::new();
+        state.set_fee_schedule(Some("account = 9\nflat = 0.5\npercentage = 10".parse()?));
+        state.handle_multiple([Event::deposit(0, 0, dec!(12)), Event::deposit(1, 1, dec!(5))])?;
+
+        assert_eq!(state.handle(Event::withdrawal(0, 2, dec!(10)))?, Outcome::Applied);
+        assert_eq!(state.client_states[&0], ClientState::new(None, dec!(0.5), dec!(0)));
+        assert_eq!(state.client_states[&9], ClientState::new(None, dec!(1.5), dec!(0)));
+        // The client can't pay the fee on top of the amount.
+        assert_eq!(
+            state.handle(Event::withdrawal(1, 3, dec!(5)))?,
+            Outcome::Rejected(Rejection::InsufficientFunds)
+        );
+        // The fee account doesn't pay fees.
+        assert_eq!(state.handle(Event::withdrawal(9, 4, dec!(1.5)))?, Outcome::Applied);
+
+        let fees = state.fees_collected();
+        assert_eq!((fees.withdrawals, fees.collected), (1, dec!(1.5)));
+        assert_eq!(state.float().balance(), dec!(5.5));
+
+        // Recomputing a client refunds its fees before they are charged again, which fails once they are paid out.
+        let history = [
+            (0, Event::deposit(0, 0, dec!(12))),
+            (2, Event::withdrawal(0, 2, dec!(5))),
+        ];
+        assert!(matches!(
+            state.recompute_client(0, history.clone()),
+            Err(Error::FeeRefund(9))
+        ));
+        assert_eq!(state.client_states[&0], ClientState::new(None, dec!(0.5), dec!(0)));
+        state.handle(Event::deposit(9, 5, dec!(2)))?;
+        state.recompute_client(0, history)?;
+        assert_eq!(state.client_states[&0], ClientState::new(None, dec!(6), dec!(0)));
+        assert_eq!(state.client_states[&9], ClientState::new(None, dec!(1.5), dec!(0)));
+        let fees = state.fees_collected();
+        assert_eq!((fees.withdrawals, fees.collected), (1, dec!(1)));
+        assert!(matches!(state.recompute_client(9, []), Err(Error::FeeAccount(9))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_policy() -> Result<(), Error> {
+        let events = || [Event::deposit(0, 0, dec!(10)), Event::deposit(0, 0, dec!(5))];
+
+        let mut state = State::new();
+        assert!(matches!(state.handle_multiple(events()), Err(Error::DuplicateTxId(0))));
+        // The duplicate is refused before it changes the client.
+        assert_eq!(state.client_states[&0], ClientState::new(None, dec!(10), dec!(0)));
+
+        let mut state = State::with_policy(DuplicatePolicy::SkipAndWarn);
+        state.handle(Event::deposit(0, 0, dec!(10)))?;
+        assert_eq!(
+            state.handle(Event::deposit(0, 0, dec!(5)))?,
+            Outcome::Rejected(Rejection::DuplicateTxId)
+        );
+        assert_eq!(state.client_states[&0], ClientState::new(None, dec!(10), dec!(0)));
+
+        let mut state = State::with_policy(DuplicatePolicy::Overwrite);
+        state.handle_multiple(events())?;
+        assert_eq!(state.client_states[&0], ClientState::new(None, dec!(15), dec!(0)));
+        state.handle(Event::dispute(0, 0))?;
+        assert_eq!(state.client_states[&0], ClientState::new(None, dec!(10), dec!(5)));
+
+        // The disputed transaction can't be replaced, as its held funds could never be released.
+        let result = state.handle(Event::deposit(0, 0, dec!(1)));
+        assert!(matches!(result, Err(Error::DuplicateTxId(0))));
+        assert_eq!(state.client_states[&0], ClientState::new(None, dec!(10), dec!(5)));
+        state.handle(Event::resolve(0, 0))?;
+        assert_eq!(state.client_states[&0], ClientState::new(None, dec!(15), dec!(0)));
+
+        Ok(())
+    }
 }
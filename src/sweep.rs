@@ -0,0 +1,213 @@
+//! Sweeps that move the funds of a client above a threshold to the operator's treasury.
+//!
+//! The file consists of [`key = value`](crate::keyvalue) lines like a [policy](crate::policy). Empty lines and lines
+//! starting with `#` are ignored. The following keys are supported, each of them may appear at most once:
+//!
+//! * `threshold`: a client whose available funds exceed this amount is swept, required.
+//! * `keep`: the available funds that remain after a sweep, the threshold by default.
+//! * `counterparty`: the counterparty of the sweep withdrawals, `treasury` by default.
+//!
+//! ```text
+//! # Sweep everything above 10000 down to 5000
+//! threshold = 10000
+//! keep = 5000
+//! counterparty = treasury
+//! ```
+//!
+//! A sweep is an ordinary withdrawal that is handled with [`State::handle_synthetic()`] right after the event that
+//! credited the client, so it shares the index of that event and appears in all reports like any other withdrawal.
+
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::{event::Event, keyvalue, transaction::Transaction, ClientId, State, TxId};
+
+/// Errors that can happen while parsing a sweep, each of them refers to a line in the file (starting at one).
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum Error {
+    /// The line is not a `key = value` pair, or its key is unknown or set more than once.
+    #[error(transparent)]
+    KeyValue(#[from] keyvalue::Error),
+    /// The value of `threshold` or `keep` is not a decimal number.
+    #[error("line {0}: `{1}` is not a valid amount")]
+    InvalidAmount(usize, String),
+    /// The value of `threshold` or `keep` is negative.
+    #[error("line {0}: `{1}` must not be negative")]
+    Negative(usize, String),
+    /// The value of `counterparty` is empty.
+    #[error("line {0}: `counterparty` must not be empty")]
+    EmptyCounterparty(usize),
+    /// The file doesn't set `threshold`.
+    #[error("the sweep `threshold` is not set")]
+    MissingThreshold,
+    /// `keep` is larger than `threshold`, so a sweep would credit the client.
+    #[error("`keep` must not exceed the `threshold`")]
+    KeepAboveThreshold,
+}
+
+/// When and how far the available funds of clients are swept.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sweep {
+    /// A client whose available funds exceed this amount is swept.
+    pub threshold: Decimal,
+    /// The available funds that remain after a sweep.
+    pub keep: Decimal,
+    /// The counterparty of the sweep withdrawals.
+    pub counterparty: String,
+}
+
+impl Sweep {
+    /// Returns the client that `event` can credit, which has to be checked with [`Sweep::trigger()`] once the event is
+    /// handled.
+    pub fn candidate(state: &State, event: &Event) -> Option<ClientId> {
+        match *event {
+            Event::Deposit { client, .. } | Event::Resolve { client, .. } | Event::Unlock { client } => Some(client),
+            Event::Transfer { to, .. } => Some(to),
+            // The chargeback of a transfer returns the funds to the sender.
+            Event::Chargeback { tx, .. } => match state.transaction(tx) {
+                Some(Transaction::Transfer(transfer)) => Some(transfer.from),
+                _ => None,
+            },
+            Event::Withdrawal { .. } | Event::Dispute { .. } => None,
+        }
+    }
+
+    /// Returns the withdrawal that sweeps `client` down to [`Sweep::keep`], if its available funds exceed the
+    /// threshold and it isn't frozen.
+    ///
+    /// The withdrawal is meant to be the next synthetic event of `state`. Sweeps take their transaction ids from the
+    /// top of the range: the id counts down from [`TxId::MAX`] by the number of synthetic events so far, and further
+    /// down past any id that an input already used. An input that reuses the id of a sweep later is a duplicate.
+    pub fn trigger(&self, state: &State, client: ClientId) -> Option<Event> {
+        let client_state = state.client_state(client)?;
+        if client_state.frozen() || client_state.available() <= self.threshold {
+            return None;
+        }
+        let start = TxId::try_from(state.synthetic_events())
+            .ok()
+            .map(|count| TxId::MAX - count)?;
+        let tx = (0..=start).rev().find(|&tx| state.transaction(tx).is_none())?;
+        Some(Event::Withdrawal {
+            client,
+            tx,
+            amount: client_state.available() - self.keep,
+            counterparty: Some(self.counterparty.clone()),
+            related: None,
+        })
+    }
+}
+
+impl FromStr for Sweep {
+    type Err = Error;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let mut threshold = None;
+        let mut keep = None;
+        let mut counterparty = None;
+
+        for pair in keyvalue::pairs(source, &["threshold", "keep", "counterparty"]) {
+            let (line, key, value) = pair?;
+            let amount = match key {
+                "counterparty" => {
+                    if value.is_empty() {
+                        return Err(Error::EmptyCounterparty(line));
+                    }
+                    counterparty = Some(value.to_owned());
+                    continue;
+                }
+                "threshold" => &mut threshold,
+                "keep" => &mut keep,
+                _ => unreachable!("`pairs()` only yields the given keys"),
+            };
+            let value = Decimal::from_str(value).map_err(|_| Error::InvalidAmount(line, value.into()))?;
+            if value < Decimal::ZERO {
+                return Err(Error::Negative(line, key.into()));
+            }
+            *amount = Some(value);
+        }
+
+        let threshold = threshold.ok_or(Error::MissingThreshold)?;
+        let keep = keep.unwrap_or(threshold);
+        if keep > threshold {
+            return Err(Error::KeepAboveThreshold);
+        }
+        Ok(Sweep {
+            threshold,
+            keep,
+            counterparty: counterparty.unwrap_or_else(|| "treasury".into()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn parse() -> Result<(), Error> {
+        let sweep: Sweep = "# Sweeps\nthreshold = 100\n\nkeep = 40\ncounterparty = vault".parse()?;
+        let expected = Sweep {
+            threshold: dec!(100),
+            keep: dec!(40),
+            counterparty: "vault".into(),
+        };
+        assert_eq!(sweep, expected);
+
+        let sweep: Sweep = "threshold = 100".parse()?;
+        assert_eq!(sweep.keep, dec!(100));
+        assert_eq!(sweep.counterparty, "treasury");
+
+        assert_eq!("keep = 1".parse::<Sweep>(), Err(Error::MissingThreshold));
+        assert_eq!(
+            "threshold = 1\nkeep = 2".parse::<Sweep>(),
+            Err(Error::KeepAboveThreshold)
+        );
+        assert_eq!(
+            "threshold = -1".parse::<Sweep>(),
+            Err(Error::Negative(1, "threshold".into()))
+        );
+        assert_eq!(
+            "threshold = 1\nthreshold = 2".parse::<Sweep>(),
+            Err(Error::KeyValue(keyvalue::Error::DuplicateKey(2, "threshold".into())))
+        );
+        assert_eq!("counterparty =".parse::<Sweep>(), Err(Error::EmptyCounterparty(1)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn trigger() -> Result<(), Box<dyn std::error::Error>> {
+        let sweep: Sweep = "threshold = 100\nkeep = 40".parse()?;
+        let mut state = State::new();
+        state.handle(Event::deposit(1, 1, dec!(100)))?;
+        assert_eq!(sweep.trigger(&state, 1), None);
+        assert_eq!(sweep.trigger(&state, 2), None);
+
+        let deposit = Event::deposit(1, 2, dec!(0.5));
+        assert_eq!(Sweep::candidate(&state, &deposit), Some(1));
+        state.handle(deposit)?;
+        let expected = Event::Withdrawal {
+            client: 1,
+            tx: TxId::MAX,
+            amount: dec!(60.5),
+            counterparty: Some("treasury".into()),
+            related: None,
+        };
+        assert_eq!(sweep.trigger(&state, 1), Some(expected.clone()));
+        state.handle_synthetic(expected)?;
+        assert_eq!(sweep.trigger(&state, 1), None);
+        assert_eq!(state.client_state(1).map(|client| client.available()), Some(dec!(40)));
+        assert_eq!(state.next_index(), 2);
+
+        // The next sweep skips the id that an input already used.
+        state.handle(Event::deposit(1, TxId::MAX - 1, dec!(70)))?;
+        let trigger = sweep.trigger(&state, 1);
+        assert!(matches!(trigger, Some(Event::Withdrawal { tx, .. }) if tx == TxId::MAX - 2));
+
+        Ok(())
+    }
+}
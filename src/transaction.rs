@@ -1,47 +1,227 @@
-//! Types that model transactions, i.e. [`Deposit`]s and [`Withdrawal`]s.
+//! Types that model transactions, i.e. [`Deposit`]s, [`Withdrawal`]s and [`Transfer`]s.
+
+use std::iter;
 
 use rust_decimal::Decimal;
 
-use crate::ClientId;
+use crate::{fees::Fee, state::Rejection, ClientId, EventIndex, TxId};
+
+/// The lifecycle of the disputes of a transaction.
+///
+/// A transaction can be disputed again after a dispute was resolved, but a chargeback is final.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum DisputeStatus {
+    /// The transaction has never been disputed.
+    #[default]
+    None,
+    /// The transaction is currently disputed.
+    Disputed,
+    /// The last dispute was resolved in favour of the client.
+    Resolved,
+    /// The last dispute ended with a chargeback.
+    ChargedBack,
+}
+
+impl DisputeStatus {
+    /// Returns the status after a dispute was opened.
+    pub fn dispute(self) -> Result<Self, Rejection> {
+        match self {
+            DisputeStatus::None | DisputeStatus::Resolved => Ok(DisputeStatus::Disputed),
+            DisputeStatus::Disputed => Err(Rejection::AlreadyDisputed),
+            DisputeStatus::ChargedBack => Err(Rejection::ChargedBack),
+        }
+    }
+
+    /// Returns the status after the dispute was resolved.
+    pub fn resolve(self) -> Result<Self, Rejection> {
+        match self {
+            DisputeStatus::Disputed => Ok(DisputeStatus::Resolved),
+            _ => Err(Rejection::NotDisputed),
+        }
+    }
+
+    /// Returns the status after the disputed transaction was charged back.
+    pub fn charge_back(self) -> Result<Self, Rejection> {
+        match self {
+            DisputeStatus::Disputed => Ok(DisputeStatus::ChargedBack),
+            _ => Err(Rejection::NotDisputed),
+        }
+    }
+}
 
 /// Models a deposit.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Deposit {
+    /// The client that the transaction belongs to.
     pub client: ClientId,
+    /// The amount of the transaction.
     pub amount: Decimal,
-    pub has_dispute: bool,
+    /// Where the transaction is in the dispute lifecycle.
+    pub dispute: DisputeStatus,
+    /// Index of the event in the input stream that opened the current dispute.
+    pub disputed_at: Option<EventIndex>,
+    /// Number of disputes that have been opened for this transaction.
+    pub disputes: u32,
+    /// The merchant or other party of the transaction.
+    pub counterparty: Option<String>,
+    /// An earlier transaction that this one refers to.
+    pub related: Option<TxId>,
 }
 
 /// Models a withdrawal.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Withdrawal {
+    /// The client that the transaction belongs to.
     pub client: ClientId,
+    /// The amount of the transaction.
+    pub amount: Decimal,
+    /// Where the transaction is in the dispute lifecycle.
+    pub dispute: DisputeStatus,
+    /// Index of the event in the input stream that opened the current dispute.
+    pub disputed_at: Option<EventIndex>,
+    /// Number of disputes that have been opened for this transaction.
+    pub disputes: u32,
+    /// The merchant or other party of the transaction.
+    pub counterparty: Option<String>,
+    /// An earlier transaction that this one refers to.
+    pub related: Option<TxId>,
+    /// The fee that the client paid on top of the amount, see
+    /// [`State::set_fee_schedule()`](crate::State::set_fee_schedule).
+    pub fee: Option<Fee>,
+}
+
+/// Models a transfer between two clients.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Transfer {
+    /// The client that paid the money.
+    pub from: ClientId,
+    /// The client that received the money, and that can dispute the transfer.
+    pub to: ClientId,
+    /// The amount of the transaction.
     pub amount: Decimal,
-    pub has_dispute: bool,
+    /// Where the transaction is in the dispute lifecycle.
+    pub dispute: DisputeStatus,
+    /// Index of the event in the input stream that opened the current dispute.
+    pub disputed_at: Option<EventIndex>,
+    /// Number of disputes that have been opened for this transaction.
+    pub disputes: u32,
 }
 
 /// The different types of transactions of the payment engine.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub enum Transaction {
+    /// A deposit.
     Deposit(Deposit),
+    /// A withdrawal.
     Withdrawal(Withdrawal),
+    /// A transfer between two clients.
+    Transfer(Transfer),
 }
 
 impl Transaction {
+    /// Returns the client that the transaction belongs to, whose funds a dispute holds, which is the recipient of a
+    /// transfer.
+    ///
+    /// Note that [`Event::client()`](crate::Event::client) is the sender of a transfer, see
+    /// [`Transaction::parties()`] for both clients.
+    pub fn client(&self) -> ClientId {
+        match self {
+            Transaction::Deposit(deposit) => deposit.client,
+            Transaction::Withdrawal(withdrawal) => withdrawal.client,
+            Transaction::Transfer(transfer) => transfer.to,
+        }
+    }
+
+    /// Returns all clients whose funds the transaction moved: the client that it belongs to, and the sender of a
+    /// transfer.
+    pub fn parties(&self) -> impl Iterator<Item = ClientId> {
+        let sender = match self {
+            Transaction::Transfer(transfer) => Some(transfer.from),
+            Transaction::Deposit(_) | Transaction::Withdrawal(_) => None,
+        };
+        iter::once(self.client()).chain(sender)
+    }
+
+    /// Returns where the transaction is in the dispute lifecycle.
+    pub fn dispute(&self) -> DisputeStatus {
+        match self {
+            Transaction::Deposit(deposit) => deposit.dispute,
+            Transaction::Withdrawal(withdrawal) => withdrawal.dispute,
+            Transaction::Transfer(transfer) => transfer.dispute,
+        }
+    }
+
+    /// Returns the amount of the transaction.
+    pub fn amount(&self) -> Decimal {
+        match self {
+            Transaction::Deposit(deposit) => deposit.amount,
+            Transaction::Withdrawal(withdrawal) => withdrawal.amount,
+            Transaction::Transfer(transfer) => transfer.amount,
+        }
+    }
+
     /// Convenience function to create a [`Deposit`] variant.
-    pub fn deposit(client: ClientId, amount: Decimal) -> Self {
+    pub fn deposit(client: ClientId, amount: Decimal, counterparty: Option<String>, related: Option<TxId>) -> Self {
         Self::Deposit(Deposit {
             client,
             amount,
-            has_dispute: false,
+            dispute: DisputeStatus::None,
+            disputed_at: None,
+            disputes: 0,
+            counterparty,
+            related,
         })
     }
 
     /// Convenience function to create a [`Withdrawal`] variant.
-    pub fn withdrawal(client: ClientId, amount: Decimal) -> Self {
+    pub fn withdrawal(
+        client: ClientId,
+        amount: Decimal,
+        counterparty: Option<String>,
+        related: Option<TxId>,
+        fee: Option<Fee>,
+    ) -> Self {
         Self::Withdrawal(Withdrawal {
             client,
             amount,
-            has_dispute: false,
+            dispute: DisputeStatus::None,
+            disputed_at: None,
+            disputes: 0,
+            counterparty,
+            related,
+            fee,
         })
     }
+
+    /// Convenience function to create a [`Transfer`] variant.
+    pub fn transfer(from: ClientId, to: ClientId, amount: Decimal) -> Self {
+        Self::Transfer(Transfer {
+            from,
+            to,
+            amount,
+            dispute: DisputeStatus::None,
+            disputed_at: None,
+            disputes: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dispute_lifecycle() -> Result<(), Rejection> {
+        let status = DisputeStatus::None.dispute()?.resolve()?.dispute()?;
+        assert_eq!(status, DisputeStatus::Disputed);
+        assert_eq!(status.dispute(), Err(Rejection::AlreadyDisputed));
+
+        let status = status.charge_back()?;
+        assert_eq!(status, DisputeStatus::ChargedBack);
+        assert_eq!(status.dispute(), Err(Rejection::ChargedBack));
+        assert_eq!(status.resolve(), Err(Rejection::NotDisputed));
+        assert_eq!(DisputeStatus::Resolved.charge_back(), Err(Rejection::NotDisputed));
+
+        Ok(())
+    }
 }
@@ -0,0 +1,150 @@
+//! The operator's float account, which mirrors every movement of money in or out of the system.
+
+use rust_decimal::Decimal;
+
+use crate::transaction::{DisputeStatus, Transaction};
+
+/// The implicit account of the operator that every deposit, withdrawal and chargeback is posted against.
+///
+/// Its balance is what the operator's bank account should hold, which makes it the primary control when reconciling.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Float {
+    deposited: Decimal,
+    withdrawn: Decimal,
+    reversed: Decimal,
+    charged_back: Decimal,
+}
+
+impl Float {
+    /// Posts money received from a client.
+    pub fn deposit(&mut self, amount: Decimal) {
+        self.deposited += amount;
+    }
+
+    /// Posts money paid out to a client.
+    pub fn withdrawal(&mut self, amount: Decimal) {
+        self.withdrawn += amount;
+    }
+
+    /// Posts a withdrawal that was reversed after a dispute was resolved in favour of the client.
+    pub fn reversal(&mut self, amount: Decimal) {
+        self.reversed += amount;
+    }
+
+    /// Posts money that was returned to the issuer of a charged back deposit.
+    pub fn chargeback(&mut self, amount: Decimal) {
+        self.charged_back += amount;
+    }
+
+    /// Takes back all postings of `transaction`, e.g. before [`State::recompute_client()`] replays it.
+    ///
+    /// [`State::recompute_client()`]: crate::State::recompute_client
+    pub fn remove(&mut self, transaction: &Transaction) {
+        match transaction {
+            Transaction::Deposit(deposit) => {
+                self.deposited -= deposit.amount;
+                if deposit.dispute == DisputeStatus::ChargedBack {
+                    self.charged_back -= deposit.amount;
+                }
+            }
+            Transaction::Withdrawal(withdrawal) => {
+                self.withdrawn -= withdrawal.amount;
+                // Withdrawals can't be charged back, so every dispute but an open one was resolved.
+                let resolved = withdrawal.disputes - u32::from(withdrawal.dispute == DisputeStatus::Disputed);
+                self.reversed -= withdrawal.amount * Decimal::from(resolved);
+            }
+            // The money of a transfer stays with the operator.
+            Transaction::Transfer(_) => {}
+        }
+    }
+
+    /// Adds all postings of `other`, e.g. of another partition of the clients.
+    pub fn merge(&mut self, other: &Float) {
+        self.deposited += other.deposited;
+        self.withdrawn += other.withdrawn;
+        self.reversed += other.reversed;
+        self.charged_back += other.charged_back;
+    }
+
+    /// Returns the sum of all deposits.
+    pub fn deposited(&self) -> Decimal {
+        self.deposited
+    }
+
+    /// Returns the sum of all withdrawals.
+    pub fn withdrawn(&self) -> Decimal {
+        self.withdrawn
+    }
+
+    /// Returns the sum of all reversed withdrawals.
+    pub fn reversed(&self) -> Decimal {
+        self.reversed
+    }
+
+    /// Returns the sum of all charged back deposits.
+    pub fn charged_back(&self) -> Decimal {
+        self.charged_back
+    }
+
+    /// Returns the current balance of the float account.
+    pub fn balance(&self) -> Decimal {
+        self.deposited - self.withdrawn + self.reversed - self.charged_back
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn balance() {
+        let mut float = Float::default();
+        float.deposit(dec!(100));
+        float.deposit(dec!(20));
+        float.withdrawal(dec!(30));
+        float.withdrawal(dec!(5));
+        float.reversal(dec!(5));
+        float.chargeback(dec!(20));
+
+        assert_eq!(float.deposited(), dec!(120));
+        assert_eq!(float.withdrawn(), dec!(35));
+        assert_eq!(float.reversed(), dec!(5));
+        assert_eq!(float.charged_back(), dec!(20));
+        assert_eq!(float.balance(), dec!(70));
+    }
+
+    #[test]
+    fn remove() {
+        let mut deposit = Transaction::deposit(1, dec!(20), None, None);
+        if let Transaction::Deposit(deposit) = &mut deposit {
+            deposit.dispute = DisputeStatus::ChargedBack;
+            deposit.disputes = 1;
+        }
+        // Resolved once and disputed again.
+        let mut withdrawal = Transaction::withdrawal(1, dec!(5), None, None, None);
+        if let Transaction::Withdrawal(withdrawal) = &mut withdrawal {
+            withdrawal.dispute = DisputeStatus::Disputed;
+            withdrawal.disputes = 2;
+        }
+
+        let mut float = Float::default();
+        float.deposit(dec!(100));
+        float.deposit(dec!(20));
+        float.chargeback(dec!(20));
+        float.withdrawal(dec!(5));
+        float.reversal(dec!(5));
+        float.remove(&deposit);
+        float.remove(&withdrawal);
+        float.remove(&Transaction::transfer(1, 2, dec!(7)));
+
+        assert_eq!(
+            float,
+            Float {
+                deposited: dec!(100),
+                ..Float::default()
+            }
+        );
+    }
+}